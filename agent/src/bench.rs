@@ -0,0 +1,301 @@
+// Inference benchmark: runs a prompt set against a loaded engine and reports load time,
+// time to first token, throughput, latency percentiles and peak memory, so models and
+// quantizations can be compared per device class. Reports saved from several runs print
+// side by side with `comparison_table`.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tinyedgellmagents_core::{model_data, Error, ErrorKind, FinishReason, InferenceRequest, SuperTinyWasmLLM};
+
+// Used without a prompt file: short tasks like the ones the agent plans
+pub const DEFAULT_PROMPTS: &[&str] = &[
+    "Calculate 15*8",
+    "What is the square root of 144?",
+    "Summarize in one sentence: small language models can run on edge devices and keep data local.",
+    "List three uses for a temperature sensor.",
+];
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    // Name of the run in comparisons; the model file name if unset
+    pub label: Option<String>,
+    pub prompts: Vec<String>,
+    // Timed passes over the prompt set
+    pub iterations: usize,
+    // Untimed passes first, paging the model in and filling caches
+    pub warmup: usize,
+    pub max_tokens: u32,
+    // Fixed so every run generates the same tokens
+    pub seed: u64,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            label: None,
+            prompts: DEFAULT_PROMPTS.iter().map(|p| p.to_string()).collect(),
+            iterations: 3,
+            warmup: 1,
+            max_tokens: 64,
+            seed: 0,
+        }
+    }
+}
+
+impl BenchConfig {
+    // Prompts from a JSON list of strings, or one prompt per non-empty line
+    pub fn load_prompts(path: impl AsRef<Path>) -> Result<Vec<String>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::new(ErrorKind::InputFailed, format!("Failed to read prompts {}: {}", path.display(), e)))?;
+        if text.trim_start().starts_with('[') {
+            return serde_json::from_str(&text)
+                .map_err(|e| Error::new(ErrorKind::InvalidRequest, format!("Invalid prompt list {}: {}", path.display(), e)).into());
+        }
+        Ok(text.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect())
+    }
+}
+
+// Milliseconds over the measured requests
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl LatencyStats {
+    // Nearest-rank percentiles and the mean to three decimals; all zero without samples
+    pub fn from_samples(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Self {
+            mean: (sorted.iter().sum::<f64>() / sorted.len() as f64 * 1000.0).round() / 1000.0,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub label: String,
+    pub model: String,
+    pub backend: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    pub prompts: usize,
+    pub iterations: usize,
+    pub max_tokens: u32,
+    pub load_ms: f64,
+    pub ttft_ms: LatencyStats,
+    // Whole request, prompt processing included
+    pub latency_ms: LatencyStats,
+    // Completion tokens over the summed request latency
+    pub tokens_per_second: f64,
+    pub prompt_tokens: u64,
+    // Prompt tokens the prompt cache let the backend skip
+    pub cached_prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss_bytes: Option<u64>,
+    // Requests that failed or were answered by the demo fallback; left out of the stats
+    pub errors: usize,
+}
+
+impl BenchReport {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| Error::new(ErrorKind::InputFailed, format!("Failed to read report {}: {}", path.display(), e)))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid benchmark report {}", path.display()))
+    }
+}
+
+// Load the model if needed, then time `config.iterations` passes over the prompts
+pub fn run(llm: &mut SuperTinyWasmLLM, config: &BenchConfig) -> Result<BenchReport> {
+    if config.prompts.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidRequest, "The benchmark has no prompts").into());
+    }
+    if !llm.is_loaded() {
+        llm.load_model().context("Failed to load model")?;
+    }
+
+    for _ in 0..config.warmup {
+        for prompt in &config.prompts {
+            let _ = measure(llm, prompt, config);
+        }
+    }
+
+    let mut ttft = Vec::new();
+    let mut latency = Vec::new();
+    let (mut prompt_tokens, mut cached_prompt_tokens, mut completion_tokens) = (0, 0, 0);
+    let mut errors = 0;
+    for _ in 0..config.iterations {
+        for prompt in &config.prompts {
+            match measure(llm, prompt, config) {
+                Ok(sample) => {
+                    latency.push(millis(sample.latency));
+                    ttft.push(millis(sample.first_token.unwrap_or(sample.latency)));
+                    prompt_tokens += sample.prompt_tokens as u64;
+                    cached_prompt_tokens += sample.cached_prompt_tokens as u64;
+                    completion_tokens += sample.completion_tokens as u64;
+                }
+                Err(e) => {
+                    log::warn!("Benchmark prompt failed: {:#}", e);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    let total_seconds = latency.iter().sum::<f64>() / 1000.0;
+    let model_file = llm.model_info().map(|info| info.file_name.clone());
+    Ok(BenchReport {
+        label: config.label.clone().or_else(|| model_file.clone()).unwrap_or_else(|| llm.model_path().to_string()),
+        model: llm.model_path().to_string(),
+        backend: llm.backend_name().unwrap_or("none").to_string(),
+        quantization: llm.model_info().and_then(|info| info.quantization.clone()),
+        prompts: config.prompts.len(),
+        iterations: config.iterations,
+        max_tokens: config.max_tokens,
+        load_ms: millis(llm.load_time()),
+        ttft_ms: LatencyStats::from_samples(&ttft),
+        latency_ms: LatencyStats::from_samples(&latency),
+        tokens_per_second: if total_seconds > 0.0 { (completion_tokens as f64 / total_seconds * 100.0).round() / 100.0 } else { 0.0 },
+        prompt_tokens,
+        cached_prompt_tokens,
+        completion_tokens,
+        peak_rss_bytes: model_data::peak_rss_bytes(),
+        errors,
+    })
+}
+
+struct Sample {
+    latency: Duration,
+    // None when nothing was generated
+    first_token: Option<Duration>,
+    prompt_tokens: u32,
+    cached_prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+fn measure(llm: &SuperTinyWasmLLM, prompt: &str, config: &BenchConfig) -> Result<Sample> {
+    let request = InferenceRequest {
+        prompt: prompt.to_string(),
+        max_tokens: Some(config.max_tokens),
+        seed: Some(config.seed),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let mut first_token = None;
+    let response = llm.generate_response_stream(&request, &mut |_| {
+        first_token.get_or_insert_with(|| started.elapsed());
+    })?;
+    let latency = started.elapsed();
+    if response.finish_reason == FinishReason::Error {
+        return Err(anyhow!(response.backend_error.unwrap_or_else(|| "backend failed".to_string())));
+    }
+    Ok(Sample {
+        latency,
+        first_token,
+        prompt_tokens: response.prompt_tokens,
+        cached_prompt_tokens: response.cached_prompt_tokens,
+        completion_tokens: response.tokens_generated,
+    })
+}
+
+// Rounded to the microsecond
+fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+// Reports as an aligned text table, one row per run
+pub fn comparison_table(reports: &[BenchReport]) -> String {
+    let header = ["run", "backend", "quant", "load ms", "ttft p50", "p50 ms", "p95 ms", "tok/s", "peak RSS MB", "errors"];
+    let rows: Vec<Vec<String>> = reports
+        .iter()
+        .map(|r| {
+            vec![
+                r.label.clone(),
+                r.backend.clone(),
+                r.quantization.clone().unwrap_or_else(|| "-".to_string()),
+                format!("{:.0}", r.load_ms),
+                format!("{:.1}", r.ttft_ms.p50),
+                format!("{:.1}", r.latency_ms.p50),
+                format!("{:.1}", r.latency_ms.p95),
+                format!("{:.1}", r.tokens_per_second),
+                r.peak_rss_bytes.map_or_else(|| "-".to_string(), |bytes| format!("{:.0}", bytes as f64 / (1024.0 * 1024.0))),
+                r.errors.to_string(),
+            ]
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter().map(|row| row[i].len()).chain([header[i].len()]).max().unwrap_or(0))
+        .collect();
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            // Names left-aligned, numbers right-aligned
+            .map(|(i, (cell, &width))| if i < 3 { format!("{:<width$}", cell) } else { format!("{:>width$}", cell) })
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    let mut table = vec![line(header.to_vec())];
+    table.extend(rows.iter().map(|row| line(row.iter().map(String::as_str).collect())));
+    table.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tinyedgellmagents_core::BackendKind;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let samples: Vec<f64> = (1..=20).map(f64::from).collect();
+        let stats = LatencyStats::from_samples(&samples);
+        assert_eq!((stats.p50, stats.p95, stats.max, stats.mean), (10.0, 19.0, 20.0, 10.5));
+        assert_eq!(LatencyStats::from_samples(&[4.0]).p95, 4.0);
+        assert_eq!(LatencyStats::from_samples(&[]), LatencyStats::default());
+    }
+
+    #[test]
+    fn test_bench_reports_every_request_and_compares_runs() {
+        let mut llm = SuperTinyWasmLLM::new(String::new()).with_backend_kind(BackendKind::Simulation);
+        let config = BenchConfig {
+            label: Some("sim".to_string()),
+            prompts: vec!["Calculate 15*8".to_string(), "Hello".to_string()],
+            iterations: 2,
+            warmup: 0,
+            max_tokens: 8,
+            seed: 1,
+        };
+        let report = run(&mut llm, &config).unwrap();
+        assert_eq!((report.label.as_str(), report.backend.as_str(), report.errors), ("sim", "simulation", 0));
+        assert!(report.completion_tokens > 0 && report.prompt_tokens > 0);
+        assert!(report.latency_ms.p50 <= report.latency_ms.p95 && report.ttft_ms.p50 <= report.latency_ms.max);
+
+        let saved: BenchReport = serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        let other = BenchReport { label: "q8 on pi".to_string(), quantization: Some("Q8_0".to_string()), ..saved };
+        let table = comparison_table(&[report, other]);
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("run") && lines[2].starts_with("q8 on pi") && lines[2].contains("Q8_0"));
+
+        let empty = BenchConfig { prompts: Vec::new(), ..BenchConfig::default() };
+        assert!(run(&mut llm, &empty).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tinyedgellmagents_core::{ChatMessage, ChatTemplate};
+
+use crate::memory::Message;
+use crate::planner::Planner;
+
+pub const CONTEXT_STRATEGIES_ENV_VAR: &str = "TINYEDGELLMAGENTS_CONTEXT_STRATEGIES";
+
+// Metadata marking history messages that carry tool output
+pub const TOOL_OUTPUT_METADATA: &str = "tool_output";
+
+// Ways to shrink a planning prompt that does not fit the model's context window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    // Cut long tool results in history and session values down to `tool_output_chars`
+    TruncateToolOutputs,
+    // Remove tool examples from the system prompt, last first
+    TrimExamples,
+    // Replace earlier turns with a one-line-per-message digest in the system prompt
+    SummarizeHistory,
+    // Remove earlier turns, oldest first
+    DropOldestHistory,
+}
+
+impl TruncationStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TruncationStrategy::TruncateToolOutputs => "truncate_tool_outputs",
+            TruncationStrategy::TrimExamples => "trim_examples",
+            TruncationStrategy::SummarizeHistory => "summarize_history",
+            TruncationStrategy::DropOldestHistory => "drop_oldest_history",
+        }
+    }
+}
+
+impl FromStr for TruncationStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "truncate_tool_outputs" | "truncate_tools" => Ok(TruncationStrategy::TruncateToolOutputs),
+            "trim_examples" | "examples" => Ok(TruncationStrategy::TrimExamples),
+            "summarize_history" | "summarize" => Ok(TruncationStrategy::SummarizeHistory),
+            "drop_oldest_history" | "drop_oldest" => Ok(TruncationStrategy::DropOldestHistory),
+            other => Err(anyhow!(
+                "Unknown context strategy '{}' (expected truncate_tool_outputs, trim_examples, summarize_history or drop_oldest_history)",
+                other
+            )),
+        }
+    }
+}
+
+// How the agent fits its planning prompt into the model's context window
+#[derive(Debug, Clone)]
+pub struct ContextPolicy {
+    // Applied in order, each until the prompt fits or it has nothing left to remove
+    pub strategies: Vec<TruncationStrategy>,
+    // Earlier turns included before the current task
+    pub history_messages: usize,
+    pub tool_output_chars: usize,
+}
+
+impl Default for ContextPolicy {
+    fn default() -> Self {
+        Self {
+            strategies: vec![
+                TruncationStrategy::TruncateToolOutputs,
+                TruncationStrategy::DropOldestHistory,
+                TruncationStrategy::TrimExamples,
+            ],
+            history_messages: 3,
+            tool_output_chars: 200,
+        }
+    }
+}
+
+impl ContextPolicy {
+    // Default policy, with the strategies listed in TINYEDGELLMAGENTS_CONTEXT_STRATEGIES if set
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(CONTEXT_STRATEGIES_ENV_VAR) {
+            Ok(value) if !value.trim().is_empty() => Ok(Self::default().with_strategies(parse_strategies(&value)?)),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn with_strategies(mut self, strategies: Vec<TruncationStrategy>) -> Self {
+        self.strategies = strategies;
+        self
+    }
+}
+
+// Comma-separated strategy names, e.g. "summarize,drop_oldest"
+pub fn parse_strategies(list: &str) -> Result<Vec<TruncationStrategy>> {
+    list.split(',').filter(|s| !s.trim().is_empty()).map(str::parse).collect()
+}
+
+// One thing removed or shortened to make the prompt fit
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextDrop {
+    pub strategy: TruncationStrategy,
+    pub item: String,
+    pub tokens_saved: usize,
+}
+
+// Token accounting of a planning prompt, reported in the task response
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContextReport {
+    pub prompt_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<usize>,
+    // Kept free for the completion
+    pub reserved_tokens: usize,
+    pub fits: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<ContextDrop>,
+}
+
+// Parts of the planning prompt, shrunk piece by piece until it fits
+pub struct ContextBuilder<'a> {
+    planner: &'a Planner,
+    task: &'a str,
+    examples: Vec<String>,
+    session: Vec<(String, String)>,
+    history: Vec<Message>,
+    summary: Option<String>,
+}
+
+impl<'a> ContextBuilder<'a> {
+    // `history` holds the earlier turns, oldest first, without the current task
+    pub fn new(planner: &'a Planner, task: &'a str, session: Vec<(String, String)>, history: &[Message]) -> Self {
+        Self {
+            planner,
+            task,
+            examples: planner.prompt_examples(),
+            session,
+            history: history.to_vec(),
+            summary: None,
+        }
+    }
+
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        let mut system = self.planner.generate_system_prompt_with(&self.examples);
+        if !self.session.is_empty() {
+            system.push_str("\n\nSession Context:\n");
+            for (key, value) in &self.session {
+                system.push_str(&format!("- {}: {}\n", key, value));
+            }
+        }
+        if let Some(summary) = &self.summary {
+            system.push_str(&format!("\n\nSummary of earlier conversation:\n{}", summary));
+        }
+
+        let mut messages = vec![ChatMessage::new("system", system.trim_end())];
+        messages.extend(self.history.iter().map(|m| ChatMessage::new(&m.role, &m.content)));
+        messages.push(ChatMessage::new("user", &format!("User task: {}", self.task)));
+        messages
+    }
+
+    // Render the prompt, applying the policy's strategies while it exceeds the context
+    // length minus `reserved_tokens`. A prompt that still does not fit is returned as is
+    // and rejected by the engine.
+    pub fn build(
+        mut self,
+        template: ChatTemplate,
+        policy: &ContextPolicy,
+        context_length: Option<usize>,
+        reserved_tokens: usize,
+        count_tokens: &dyn Fn(&str) -> usize,
+    ) -> (String, ContextReport) {
+        if self.history.len() > policy.history_messages {
+            self.history.drain(..self.history.len() - policy.history_messages);
+        }
+        let budget = context_length.map(|n| n.saturating_sub(reserved_tokens));
+        let mut prompt = template.render(&self.messages(), true);
+        let mut tokens = count_tokens(&prompt);
+        let mut dropped = Vec::new();
+
+        for &strategy in &policy.strategies {
+            while budget.is_some_and(|budget| tokens > budget) {
+                let Some(item) = self.shrink(strategy, policy) else {
+                    break;
+                };
+                prompt = template.render(&self.messages(), true);
+                let shrunk = count_tokens(&prompt);
+                dropped.push(ContextDrop { strategy, item, tokens_saved: tokens.saturating_sub(shrunk) });
+                tokens = shrunk;
+            }
+        }
+        for drop in &dropped {
+            log::info!("Context window: {} dropped {} ({} tokens)", drop.strategy.as_str(), drop.item, drop.tokens_saved);
+        }
+
+        let report = ContextReport {
+            prompt_tokens: tokens,
+            context_length,
+            reserved_tokens,
+            fits: budget.is_none_or(|budget| tokens <= budget),
+            dropped,
+        };
+        (prompt, report)
+    }
+
+    // Apply one step of `strategy`, describing what it removed, or None if it has nothing left
+    fn shrink(&mut self, strategy: TruncationStrategy, policy: &ContextPolicy) -> Option<String> {
+        let limit = policy.tool_output_chars;
+        match strategy {
+            TruncationStrategy::TruncateToolOutputs => {
+                if let Some(message) = self.history.iter_mut().find(|m| {
+                    m.metadata.contains_key(TOOL_OUTPUT_METADATA) && m.content.chars().count() > limit
+                }) {
+                    let item = format!("tool output in {} message ({} chars)", message.role, message.content.chars().count());
+                    message.content = truncate_chars(&message.content, limit);
+                    return Some(item);
+                }
+                let (key, value) = self.session.iter_mut().find(|(_, value)| value.chars().count() > limit)?;
+                let item = format!("session value '{}' ({} chars)", key, value.chars().count());
+                *value = truncate_chars(value, limit);
+                Some(item)
+            }
+            TruncationStrategy::TrimExamples => self.examples.pop().map(|example| format!("example {}", example)),
+            TruncationStrategy::SummarizeHistory => {
+                if self.history.is_empty() {
+                    return None;
+                }
+                let digest: Vec<String> = self
+                    .history
+                    .iter()
+                    .map(|m| format!("- {}: {}", m.role, truncate_chars(m.content.lines().next().unwrap_or(""), 80)))
+                    .collect();
+                let item = format!("{} history messages", self.history.len());
+                self.summary = Some(match self.summary.take() {
+                    Some(earlier) => format!("{}\n{}", earlier, digest.join("\n")),
+                    None => digest.join("\n"),
+                });
+                self.history.clear();
+                Some(item)
+            }
+            TruncationStrategy::DropOldestHistory => {
+                if self.history.is_empty() {
+                    // The summary is the oldest history left
+                    return self.summary.take().map(|_| "history summary".to_string());
+                }
+                let message = self.history.remove(0);
+                Some(format!("{} message '{}'", message.role, truncate_chars(&message.content, 40)))
+            }
+        }
+    }
+}
+
+const TRUNCATION_MARK: &str = "… [truncated]";
+
+// At most `limit` characters of `text`, the marker included when it was cut
+fn truncate_chars(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    let keep = limit.saturating_sub(TRUNCATION_MARK.chars().count());
+    let end = text.char_indices().nth(keep).map_or(text.len(), |(end, _)| end);
+    format!("{}{}", &text[..end], TRUNCATION_MARK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Roughly four characters per token, like the engine's estimate without a vocabulary
+    fn count(text: &str) -> usize {
+        text.len() / 4
+    }
+
+    fn history() -> Vec<Message> {
+        vec![
+            Message::new("user", "What is 2+2?"),
+            Message::new("assistant", "{\"tool\": \"math\", \"args\": [\"2+2\"]}"),
+            Message::new("system", &format!("Task completed. Result: {}", "4 ".repeat(300)))
+                .with_metadata(TOOL_OUTPUT_METADATA, "true"),
+        ]
+    }
+
+    #[test]
+    fn test_prompt_within_budget_is_untouched() {
+        let planner = Planner::default();
+        let builder = ContextBuilder::new(&planner, "Add 1 and 2", vec![], &history());
+        let expected = ChatTemplate::Plain.render(&builder.messages(), true);
+
+        let (prompt, report) = builder.build(ChatTemplate::Plain, &ContextPolicy::default(), None, 100, &count);
+        assert_eq!(prompt, expected);
+        assert!(report.fits && report.dropped.is_empty());
+        assert_eq!(report.prompt_tokens, count(&prompt));
+    }
+
+    #[test]
+    fn test_strategies_applied_in_order_until_prompt_fits() {
+        let planner = Planner::default();
+        let full = ContextBuilder::new(&planner, "Add 1 and 2", vec![], &history());
+        let full_tokens = count(&ChatTemplate::Plain.render(&full.messages(), true));
+
+        // Room for the prompt once the tool output is cut and two examples are gone
+        let policy = ContextPolicy::default().with_strategies(vec![
+            TruncationStrategy::TruncateToolOutputs,
+            TruncationStrategy::TrimExamples,
+        ]);
+        let (prompt, report) = full.build(ChatTemplate::Plain, &policy, Some(full_tokens), 200, &count);
+        assert!(report.fits);
+        assert!(prompt.contains("[truncated]") && prompt.contains("User task: Add 1 and 2"));
+        assert_eq!(report.dropped[0].strategy, TruncationStrategy::TruncateToolOutputs);
+        assert!(report.dropped[1..].iter().all(|d| d.strategy == TruncationStrategy::TrimExamples));
+        let saved: usize = report.dropped.iter().map(|d| d.tokens_saved).sum();
+        assert_eq!(report.prompt_tokens, full_tokens - saved);
+
+        // Summarizing keeps a digest of every turn in the system prompt
+        let policy = ContextPolicy::default().with_strategies(vec![TruncationStrategy::SummarizeHistory]);
+        let builder = ContextBuilder::new(&planner, "Add 1 and 2", vec![], &history());
+        let (prompt, report) = builder.build(ChatTemplate::Plain, &policy, Some(full_tokens), 300, &count);
+        assert!(prompt.contains("Summary of earlier conversation:\n- user: What is 2+2?"));
+        assert_eq!(report.dropped[0].item, "3 history messages");
+
+        // Nothing left to remove: reported as not fitting
+        let policy = ContextPolicy::default().with_strategies(vec![TruncationStrategy::DropOldestHistory]);
+        let builder = ContextBuilder::new(&planner, "Add 1 and 2", vec![], &history());
+        let (_, report) = builder.build(ChatTemplate::Plain, &policy, Some(50), 10, &count);
+        assert!(!report.fits);
+        assert_eq!(report.dropped.len(), 3);
+        assert_eq!(parse_strategies("summarize, drop-oldest").unwrap().len(), 2);
+        assert!(parse_strategies("forget").is_err());
+    }
+}
@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use std::path::Path;
 use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
+use tinyedgellmagents_core::{Error, ErrorKind};
 use wasmtime::*;
 use tokio::time::timeout;
 use tokio::io::AsyncWriteExt;
@@ -14,6 +15,9 @@ pub struct ToolResult {
     pub success: bool,
     pub result: String,
     pub error: Option<String>,
+    // Classification of `error` for failed calls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<ErrorKind>,
     pub execution_time_ms: u64,
     pub tool_name: String,
     pub metadata: HashMap<String, String>,
@@ -25,17 +29,19 @@ impl ToolResult {
             success: true,
             result: result.to_string(),
             error: None,
+            error_kind: None,
             execution_time_ms: execution_time.as_millis() as u64,
             tool_name: tool_name.to_string(),
             metadata: HashMap::new(),
         }
     }
 
-    pub fn error(tool_name: &str, error: &str, execution_time: Duration) -> Self {
+    pub fn error(tool_name: &str, error: &Error, execution_time: Duration) -> Self {
         Self {
             success: false,
             result: String::new(),
-            error: Some(error.to_string()),
+            error: Some(error.message().to_string()),
+            error_kind: Some(error.kind()),
             execution_time_ms: execution_time.as_millis() as u64,
             tool_name: tool_name.to_string(),
             metadata: HashMap::new(),
@@ -46,6 +52,18 @@ impl ToolResult {
         self.metadata.insert(key.to_string(), value.to_string());
         self
     }
+
+    // The typed error of a failed call, naming the tool in its details
+    pub fn to_error(&self) -> Option<Error> {
+        if self.success {
+            return None;
+        }
+        let message = self.error.as_deref().unwrap_or("Tool failed");
+        Some(
+            Error::new(self.error_kind.unwrap_or(ErrorKind::ToolFailed), format!("Error in {}: {}", self.tool_name, message))
+                .with_detail("tool", &self.tool_name),
+        )
+    }
 }
 
 pub struct WasmTool {
@@ -73,20 +91,26 @@ impl WasmTool {
 
     pub async fn execute(&self, input: &str) -> Result<String> {
         // Try native tool first
-        if let Ok(output) = self.execute_native_tool(input).await {
-            return Ok(output);
-        }
+        let native_error = match self.execute_native_tool(input).await {
+            Ok(output) => return Ok(output),
+            Err(e) => e,
+        };
         
         // Try WASM tool execution
         if let Ok(output) = self.execute_wasm_tool(input).await {
             return Ok(output);
         }
         
-        Err(anyhow!("Tool execution failed for {}: neither native nor WASM execution succeeded", self.name))
+        // Report why the native run failed, keeping its classification if it had one
+        let kind = Error::classify(&native_error, ErrorKind::ToolFailed).kind();
+        Err(Error::new(
+            kind,
+            format!("Tool execution failed for {}: neither native nor WASM execution succeeded ({:#})", self.name, native_error),
+        )
+        .into())
     }
     
     async fn execute_wasm_tool(&self, input: &str) -> Result<String> {
-        use wasmtime_wasi::WasiCtxBuilder;
         use std::process::{Command, Stdio};
         
         // For now, use wasmtime CLI to execute WASM tools with proper I/O
@@ -144,7 +168,12 @@ impl WasmTool {
         };
         
         if !std::path::Path::new(&absolute_path).exists() {
-            return Err(anyhow!("Native tool not found: {} (resolved from {})", absolute_path, tool_path));
+            return Err(Error::new(
+                ErrorKind::ToolNotFound,
+                format!("Native tool not found: {} (resolved from {})", absolute_path, tool_path),
+            )
+            .with_detail("path", &absolute_path)
+            .into());
         }
         
         // Execute the tool
@@ -152,7 +181,14 @@ impl WasmTool {
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .spawn()?;
+            .spawn()
+            .map_err(|e| {
+                let kind = match e.kind() {
+                    std::io::ErrorKind::PermissionDenied => ErrorKind::ToolDenied,
+                    _ => ErrorKind::ToolFailed,
+                };
+                Error::new(kind, format!("Failed to start {}: {}", absolute_path, e)).with_detail("path", &absolute_path)
+            })?;
         
         // Send input to tool
         if let Some(stdin) = child.stdin.take() {
@@ -211,10 +247,10 @@ impl ToolDispatcher {
                     match self.register_tool(tool_name, path.to_string_lossy().as_ref()) {
                         Ok(_) => {
                             discovered += 1;
-                            println!("Discovered WASM tool: {} at {}", tool_name, path.display());
+                            log::info!("Discovered WASM tool: {} at {}", tool_name, path.display());
                         }
                         Err(e) => {
-                            eprintln!("Failed to register tool {}: {}", tool_name, e);
+                            log::warn!("Failed to register tool {}: {}", tool_name, e);
                         }
                     }
                 }
@@ -236,10 +272,10 @@ impl ToolDispatcher {
                                     match self.register_tool(tool_name, path.to_string_lossy().as_ref()) {
                                         Ok(_) => {
                                             discovered += 1;
-                                            println!("Discovered native tool: {} at {}", tool_name, path.display());
+                                            log::info!("Discovered native tool: {} at {}", tool_name, path.display());
                                         }
                                         Err(e) => {
-                                            eprintln!("Failed to register tool {}: {}", tool_name, e);
+                                            log::warn!("Failed to register tool {}: {}", tool_name, e);
                                         }
                                     }
                                 }
@@ -250,10 +286,10 @@ impl ToolDispatcher {
                             match self.register_tool(tool_name, path.to_string_lossy().as_ref()) {
                                 Ok(_) => {
                                     discovered += 1;
-                                    println!("Discovered native tool: {} at {}", tool_name, path.display());
+                                    log::info!("Discovered native tool: {} at {}", tool_name, path.display());
                                 }
                                 Err(e) => {
-                                    eprintln!("Failed to register tool {}: {}", tool_name, e);
+                                    log::warn!("Failed to register tool {}: {}", tool_name, e);
                                 }
                             }
                         }
@@ -300,8 +336,10 @@ impl ToolDispatcher {
         // Map tool aliases to actual tool names
         let actual_tool_name = self.map_tool_alias(&action.tool);
         
-        let tool = self.tools.get(&actual_tool_name)
-            .ok_or_else(|| anyhow!("Unknown tool: {} (mapped from {})", actual_tool_name, action.tool))?;
+        let tool = self.tools.get(&actual_tool_name).ok_or_else(|| {
+            Error::new(ErrorKind::ToolNotFound, format!("Unknown tool: {} (mapped from {})", actual_tool_name, action.tool))
+                .with_detail("tool", &action.tool)
+        })?;
 
         // Prepare input JSON for the tool
         let tool_input = if action.args.len() == 1 {
@@ -314,7 +352,7 @@ impl ToolDispatcher {
         } else {
             // For tools with multiple arguments
             serde_json::json!({
-                "operation": action.args.get(0).unwrap_or(&"default".to_string()),
+                "operation": action.args.first().unwrap_or(&"default".to_string()),
                 "args": &action.args[1..],
                 "context": action.context
             })
@@ -331,6 +369,13 @@ impl ToolDispatcher {
             Ok(Ok(output)) => {
                 // Try to parse tool output as JSON
                 if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&output) {
+                    // Tools report their own failures as {"status": "error", "error": "..."}
+                    if parsed.get("status").and_then(|s| s.as_str()) == Some("error") {
+                        let message = parsed.get("error").and_then(|e| e.as_str()).unwrap_or("Tool reported an error");
+                        // The shell tool refuses commands outside its allow-list
+                        let kind = if message.contains("not allowed") { ErrorKind::ToolDenied } else { ErrorKind::ToolFailed };
+                        return Ok(ToolResult::error(&actual_tool_name, &Error::new(kind, message), execution_time));
+                    }
                     if let Some(result) = parsed.get("result") {
                         return Ok(ToolResult::success(
                             &actual_tool_name,
@@ -341,12 +386,13 @@ impl ToolDispatcher {
                 }
                 
                 // If not JSON, return raw output
-                Ok(ToolResult::success(&actual_tool_name, &output.trim(), execution_time))
+                Ok(ToolResult::success(&actual_tool_name, output.trim(), execution_time))
             }
-            Ok(Err(e)) => Ok(ToolResult::error(&actual_tool_name, &e.to_string(), execution_time)),
+            Ok(Err(e)) => Ok(ToolResult::error(&actual_tool_name, &Error::classify(&e, ErrorKind::ToolFailed), execution_time)),
             Err(_) => Ok(ToolResult::error(
                 &actual_tool_name,
-                "Tool execution timeout",
+                &Error::new(ErrorKind::ToolTimeout, "Tool execution timeout")
+                    .with_detail("timeout_ms", self.default_timeout.as_millis() as u64),
                 execution_time,
             )),
         }
@@ -380,7 +426,8 @@ impl ToolDispatcher {
                         Ok(tool_result) => results.push(tool_result),
                         Err(e) => {
                             // Create an error result for failed executions
-                            results.push(ToolResult::error("unknown", &e.to_string(), Duration::default()));
+                            let error = Error::classify(&e, ErrorKind::ToolFailed);
+                            results.push(ToolResult::error("unknown", &error, Duration::default()));
                         }
                     }
                 }
@@ -388,7 +435,7 @@ impl ToolDispatcher {
             ExecutionStrategy::Priority => {
                 // Sort by priority (higher number = higher priority)
                 let mut sorted_actions = plan.actions.clone();
-                sorted_actions.sort_by(|a, b| b.priority.cmp(&a.priority));
+                sorted_actions.sort_by_key(|a| std::cmp::Reverse(a.priority));
 
                 for action in &sorted_actions {
                     let result = self.execute_action(action).await?;
@@ -516,7 +563,6 @@ impl Default for ToolDispatcher {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::planner::ActionPlan;
 
     #[tokio::test]
     async fn test_real_tool_execution() {
@@ -528,7 +574,7 @@ mod tests {
         }
         
         // This test would only pass with real tools present
-        assert!(dispatcher.get_available_tools().len() >= 0);
+        assert_eq!(dispatcher.get_available_tools().len(), dispatcher.get_stats().total_tools);
     }
 
     #[tokio::test]
@@ -541,7 +587,7 @@ mod tests {
         
         // Should find at least some tools if directory exists
         if std::path::Path::new("../tools").exists() {
-            assert!(discovered >= 0);
+            assert!(dispatcher.get_available_tools().len() <= discovered);
         }
     }
 } 
\ No newline at end of file
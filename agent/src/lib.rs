@@ -1,22 +1,47 @@
+pub mod context;
 pub mod memory;
 pub mod planner;
 pub mod dispatcher;
+pub mod openai;
+pub mod bench;
 
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
-use tinyedgellmagents_core::{SuperTinyWasmLLM, InferenceRequest, InferenceResponse};
+use tinyedgellmagents_core::{
+    CancelToken, ChatMessage, DEFAULT_MAX_TOKENS, EmbeddingRequest, Error, ErrorKind, FinishReason, SuperTinyWasmLLM, InferenceRequest, LoraSelection, InferenceResponse, Timings, ModelInfo, ModelMemory, ModelRegistry, ModelStatus,
+};
 
+pub use context::{ContextBuilder, ContextDrop, ContextPolicy, ContextReport, TruncationStrategy};
 pub use memory::{AgentMemory, Message, MemoryStats};
 pub use planner::{ActionPlan, ExecutionPlan, ExecutionStrategy, Planner, ToolDefinition};
 pub use dispatcher::{ToolDispatcher, ToolResult, DispatcherStats};
+pub use bench::{BenchConfig, BenchReport, LatencyStats};
 
-#[derive(Debug, Deserialize)]
+// Registry names the agent routes to when they are registered: plans come from the
+// planner model and final answers from the answer model, otherwise both use the default
+pub const PLANNER_MODEL: &str = "planner";
+pub const ANSWER_MODEL: &str = "answer";
+
+#[derive(Debug, Default, Deserialize)]
 pub struct TaskRequest {
     pub task: String,
+    // Registry model for the whole task, instead of the planner and answer models
+    pub model: Option<String>,
     pub context: Option<String>,
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
+    // Fixed sampling seed, for reproducible plans
+    pub seed: Option<u64>,
+    // LoRA adapters for the plan and answer, e.g. [{"name": "weather"}], instead of the
+    // active ones
+    pub lora: Option<Vec<LoraSelection>>,
+    // Report LLM tokens as they are generated, before the final response
+    #[serde(default)]
+    pub stream: bool,
+    // Set to stop the LLM call in progress, e.g. on Ctrl-C
+    #[serde(skip)]
+    pub cancel: Option<CancelToken>,
 }
 
 #[derive(Debug, Serialize)]
@@ -27,45 +52,128 @@ pub struct TaskResponse {
     pub tools_used: Vec<String>,
     pub execution_time_ms: u64,
     pub memory_stats: MemoryStats,
+    // Why the task failed, for responses with success = false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    // Token accounting of the planning prompt and what was cut to fit the context window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<ContextReport>,
+    // How sure the model was of its plan, from 0 to 1, when the backend reports log probabilities
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    // Every LLM call the task made, in order
+    pub llm_calls: Vec<LlmCall>,
+}
+
+// How one LLM call of a task ended, what it cost and which backend served it
+#[derive(Debug, Clone, Serialize)]
+pub struct LlmCall {
+    // "plan" or "answer"
+    pub stage: String,
+    pub backend: String,
+    pub finish_reason: FinishReason,
+    pub prompt_tokens: u32,
+    pub cached_prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub timings: Timings,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend_error: Option<String>,
+}
+
+impl LlmCall {
+    fn new(stage: &str, response: &InferenceResponse) -> Self {
+        if let Some(error) = &response.backend_error {
+            log::warn!("The {} call fell back to the {} backend: {}", stage, response.backend, error);
+        }
+        Self {
+            stage: stage.to_string(),
+            backend: response.backend.clone(),
+            finish_reason: response.finish_reason,
+            prompt_tokens: response.prompt_tokens,
+            cached_prompt_tokens: response.cached_prompt_tokens,
+            completion_tokens: response.tokens_generated,
+            timings: response.timings.clone(),
+            backend_error: response.backend_error.clone(),
+        }
+    }
+}
+
+// Newline-delimited output of a streamed task: LLM tokens, then the TaskResponse
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TaskStreamEvent {
+    Token { index: usize, text: String },
+    Done(Box<TaskResponse>),
 }
 
 pub struct TinyEdgeAgent {
-    llm: SuperTinyWasmLLM,
+    models: ModelRegistry,
     memory: AgentMemory,
     planner: Planner,
     dispatcher: ToolDispatcher,
+    context_policy: ContextPolicy,
+    // Plans the model is less sure of are not executed
+    min_confidence: Option<f32>,
     model_loaded: bool,
 }
 
 impl TinyEdgeAgent {
     pub fn new(model_path: &str) -> Self {
+        Self::with_llm(SuperTinyWasmLLM::new(model_path.to_string()))
+    }
+
+    // Build an agent around a pre-configured engine, e.g. one with a custom backend
+    pub fn with_llm(llm: SuperTinyWasmLLM) -> Self {
+        Self::with_registry(ModelRegistry::single("default", llm))
+    }
+
+    // Build an agent over several named models, loaded as tasks need them
+    pub fn with_registry(models: ModelRegistry) -> Self {
         Self {
-            llm: SuperTinyWasmLLM::new(model_path.to_string()),
+            models,
             memory: AgentMemory::new(),
             planner: Planner::default(), // Includes default tools
             dispatcher: ToolDispatcher::new(),
+            context_policy: ContextPolicy::from_env().unwrap_or_else(|e| {
+                log::warn!("{}, using the default context strategies", e);
+                ContextPolicy::default()
+            }),
+            min_confidence: None,
             model_loaded: false,
         }
     }
 
+    // Fit planning prompts into the context window with this policy instead of the default
+    pub fn with_context_policy(mut self, policy: ContextPolicy) -> Self {
+        self.context_policy = policy;
+        self
+    }
+
+    // Ask for clarification instead of running plans whose confidence is below
+    // `threshold`; only backends reporting log probabilities give a confidence
+    pub fn with_min_confidence(mut self, threshold: f32) -> Self {
+        self.min_confidence = Some(threshold);
+        self
+    }
+
     pub async fn initialize(&mut self) -> Result<()> {
-        // Load the LLM model
-        self.llm.load_model()
-            .map_err(|e| anyhow!("Failed to load LLM model: {}", e))?;
+        // Load the default model; the others are loaded on first use
+        self.models.get(None)
+            .context("Failed to load LLM model")?;
         self.model_loaded = true;
 
-        println!("TinyEdgeAgent initialized successfully");
+        log::info!("TinyEdgeAgent initialized successfully");
         Ok(())
     }
 
     pub async fn load_tools(&mut self, tools_dir: &str) -> Result<usize> {
         if !Path::new(tools_dir).exists() {
-            println!("Tools directory '{}' does not exist, skipping tool loading", tools_dir);
+            log::info!("Tools directory '{}' does not exist, skipping tool loading", tools_dir);
             return Ok(0);
         }
 
         let discovered = self.dispatcher.discover_tools(tools_dir)?;
-        println!("Loaded {} tools from {}", discovered, tools_dir);
+        log::info!("Loaded {} tools from {}", discovered, tools_dir);
         
         // Health check tools before registering with planner
         let tool_health = self.dispatcher.health_check().await.unwrap_or_default();
@@ -86,9 +194,9 @@ impl TinyEdgeAgent {
                         examples: vec![format!("{{\"tool\": \"{}\", \"args\": [\"operation\", \"arg1\"]}}", tool_name)],
                     };
                     self.planner.register_tool(tool_def);
-                    println!("Registered healthy tool: {}", tool_name);
+                    log::info!("Registered healthy tool: {}", tool_name);
                 } else {
-                    println!("Skipped unhealthy tool: {}", tool_name);
+                    log::warn!("Skipped unhealthy tool: {}", tool_name);
                 }
             }
         }
@@ -97,25 +205,45 @@ impl TinyEdgeAgent {
     }
 
     pub async fn execute_task(&mut self, request: &TaskRequest) -> Result<TaskResponse> {
+        self.execute_task_stream(request, &mut |_| {}).await
+    }
+
+    // Execute a task, handing LLM output to `on_token` piece by piece as it is generated
+    pub async fn execute_task_stream(
+        &mut self,
+        request: &TaskRequest,
+        on_token: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<TaskResponse> {
         let start_time = std::time::Instant::now();
 
         if !self.model_loaded {
-            return Err(anyhow!("Agent not initialized. Call initialize() first."));
+            return Err(Error::new(ErrorKind::ModelNotLoaded, "Agent not initialized. Call initialize() first.").into());
         }
 
         // Store task in memory
         self.memory.store("current_task", &request.task);
         self.memory.add_to_history(Message::new("user", &request.task));
 
-        // Build context for LLM
-        let context = self.memory.build_context_prompt(3); // Include last 3 messages
-        let system_prompt = self.planner.generate_system_prompt();
-        
-        let enhanced_prompt = format!(
-            "{}\n\n{}\n\nUser task: {}",
-            system_prompt,
-            context,
-            request.task
+        // Render the conversation in the model's chat format: system prompt and session
+        // context, recent turns, then the current task, shrunk to leave room for the completion
+        let planner_model = request.model.as_deref()
+            .or(Some(PLANNER_MODEL).filter(|name| self.models.contains(name)));
+        let llm = self.models.get(planner_model)?;
+        let template = llm.chat_template();
+        let earlier = self.memory.get_recent_history(self.context_policy.history_messages + 1);
+        let builder = ContextBuilder::new(
+            &self.planner,
+            &request.task,
+            self.memory.session_entries(),
+            earlier.split_last().map_or(&[], |(_, earlier)| earlier),
+        );
+        let (enhanced_prompt, context_window) = builder.build(
+            template,
+            &self.context_policy,
+            llm.context_length(),
+            request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS) as usize,
+            // Plus the BOS token the engine adds
+            &|text| llm.count_tokens(text) + 1,
         );
 
         // Generate plan via LLM
@@ -123,10 +251,54 @@ impl TinyEdgeAgent {
             prompt: enhanced_prompt,
             max_tokens: request.max_tokens,
             temperature: request.temperature,
+            seed: request.seed,
+            stop: template.stop_sequences(),
+            lora: request.lora.clone(),
+            // Backends that support it only emit well-formed calls to known tools
+            json_schema: self.planner.action_schema(),
+            stream: request.stream,
+            timeout_ms: Some(self.planner.default_timeout() * 1000),
+            cancel: request.cancel.clone(),
+            logprobs: Some(0),
+            ..Default::default()
         };
 
-        let llm_response = self.llm.generate_response(&llm_request)
-            .map_err(|e| anyhow!("LLM inference failed: {}", e))?;
+        let llm_response = llm.generate_response_stream(&llm_request, on_token)
+            .context("LLM inference failed")?;
+        let mut llm_calls = vec![LlmCall::new("plan", &llm_response)];
+
+        // A plan from the fallback backend is only an echo of the prompt: fail the task
+        if let Some(error) = backend_failure(&llm_response) {
+            return Ok(TaskResponse {
+                success: false,
+                result: error.message().to_string(),
+                reasoning: None,
+                tools_used: vec![],
+                execution_time_ms: start_time.elapsed().as_millis() as u64,
+                memory_stats: self.memory.get_stats(),
+                error: Some(error),
+                context_window: Some(context_window),
+                confidence: None,
+                llm_calls,
+            });
+        }
+
+        // A plan cut short is not worth parsing; report what was generated
+        if let Some(error) = interruption(llm_response.finish_reason) {
+            return Ok(TaskResponse {
+                success: false,
+                result: llm_response.response,
+                reasoning: None,
+                tools_used: vec![],
+                execution_time_ms: start_time.elapsed().as_millis() as u64,
+                memory_stats: self.memory.get_stats(),
+                error: Some(error),
+                context_window: Some(context_window),
+                confidence: None,
+                llm_calls,
+            });
+        }
+        let confidence = llm_response.logprobs.as_deref().and_then(Planner::plan_confidence);
 
         // Store LLM response in memory
         self.memory.add_to_history(Message::new("assistant", &llm_response.response));
@@ -136,26 +308,62 @@ impl TinyEdgeAgent {
             Ok(plan) => plan,
             Err(e) => {
                 // Fallback: try to extract simple text response
-                println!("Warning: Failed to parse LLM response as action plan: {}", e);
+                log::warn!("Failed to parse LLM response as action plan: {}", e);
+                let timeout = self.planner.default_timeout();
+                let prompt = format!("Task: {}", request.task);
+                let (result, error) = match self.answer(request, &prompt, timeout, &mut llm_calls, on_token)? {
+                    Answer::Text(answer) => (answer, None),
+                    Answer::Skipped => (llm_response.response, None),
+                    Answer::Failed(error) => (error.message().to_string(), Some(error)),
+                };
                 return Ok(TaskResponse {
-                    success: true,
-                    result: llm_response.response,
+                    success: error.is_none(),
+                    result,
                     reasoning: Some("Direct LLM response (no tools executed)".to_string()),
                     tools_used: vec![],
                     execution_time_ms: start_time.elapsed().as_millis() as u64,
                     memory_stats: self.memory.get_stats(),
+                    error,
+                    context_window: Some(context_window),
+                    confidence,
+                    llm_calls,
                 });
             }
         };
 
+        // A small model unsure of its plan is likely to have made up a tool or argument
+        if let (Some(confidence), Some(threshold)) = (confidence, self.min_confidence) {
+            if confidence < threshold {
+                let tool = execution_plan.actions.first().map(|a| a.tool.clone()).unwrap_or_default();
+                log::warn!("Not running the plan for '{}': confidence {:.2} is below {:.2}", tool, confidence, threshold);
+                return Ok(TaskResponse {
+                    success: false,
+                    result: "I'm not sure how to do that. Could you rephrase the task or add details?".to_string(),
+                    reasoning: execution_plan.actions.first().and_then(|a| a.reasoning.clone()),
+                    tools_used: vec![],
+                    execution_time_ms: start_time.elapsed().as_millis() as u64,
+                    memory_stats: self.memory.get_stats(),
+                    error: Some(
+                        Error::new(ErrorKind::LowConfidence, format!("Plan confidence {:.2} is below {:.2}", confidence, threshold))
+                            .with_detail("tool", tool)
+                            .with_detail("confidence", confidence)
+                            .with_detail("threshold", threshold),
+                    ),
+                    context_window: Some(context_window),
+                    confidence: Some(confidence),
+                    llm_calls,
+                });
+            }
+        }
+
         // Execute the plan
         let tool_results = self.dispatcher.execute_plan(&execution_plan).await
-            .map_err(|e| anyhow!("Tool execution failed: {}", e))?;
+            .context("Tool execution failed")?;
 
         // Process results
         let mut final_result = String::new();
         let mut tools_used = Vec::new();
-        let mut all_successful = true;
+        let mut first_error = None;
 
         for result in &tool_results {
             tools_used.push(result.tool_name.clone());
@@ -170,35 +378,119 @@ impl TinyEdgeAgent {
                 if let Some(action) = execution_plan.actions.iter().find(|a| a.tool == result.tool_name) {
                     self.memory.cache_tool_result(&action.cache_key(), &result.result);
                 }
-            } else {
-                all_successful = false;
-                if let Some(error) = &result.error {
-                    final_result.push_str(&format!("Error in {}: {}", result.tool_name, error));
-                }
+            } else if let Some(error) = result.to_error() {
+                final_result.push_str(error.message());
+                first_error.get_or_insert(error);
+            }
+        }
+
+        // Let the answer model phrase the tool results, when one is registered
+        if first_error.is_none() && !final_result.is_empty() {
+            let prompt = format!("Task: {}\nTool results: {}", request.task, final_result);
+            match self.answer(request, &prompt, execution_plan.timeout_seconds, &mut llm_calls, on_token)? {
+                Answer::Text(answer) => final_result = answer,
+                Answer::Skipped => {}
+                // Keep the tool results, but the task did not get its answer
+                Answer::Failed(error) => first_error = Some(error),
             }
         }
 
         // Store results in memory
         self.memory.store("last_result", &final_result);
-        self.memory.add_to_history(Message::new("system", &format!("Task completed. Result: {}", final_result)));
+        self.memory.add_to_history(
+            Message::new("system", &format!("Task completed. Result: {}", final_result))
+                .with_metadata(context::TOOL_OUTPUT_METADATA, "true"),
+        );
 
         let execution_time = start_time.elapsed().as_millis() as u64;
 
         Ok(TaskResponse {
-            success: all_successful,
+            success: first_error.is_none(),
             result: if final_result.is_empty() { "No results generated".to_string() } else { final_result },
             reasoning: execution_plan.actions.first().and_then(|a| a.reasoning.clone()),
             tools_used,
             execution_time_ms: execution_time,
             memory_stats: self.memory.get_stats(),
+            error: first_error,
+            context_window: Some(context_window),
+            confidence,
+            llm_calls,
         })
     }
 
+    // Vectors for `texts` from the named registry model, or the default one, e.g. for
+    // semantic tool selection or memory retrieval
+    pub fn embed(&mut self, texts: &[String], model: Option<&str>) -> Result<Vec<Vec<f32>>> {
+        let request = EmbeddingRequest { model: model.map(str::to_string), ..EmbeddingRequest::new(texts.to_vec()) };
+        Ok(self.models.embed(&request)?.embeddings)
+    }
+
+    // Final answer from the answer model. Skipped when there is none, the request picked
+    // its own model, or the answer was cut short; Failed when the backend failed.
+    fn answer(
+        &mut self,
+        request: &TaskRequest,
+        prompt: &str,
+        timeout_seconds: u64,
+        llm_calls: &mut Vec<LlmCall>,
+        on_token: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<Answer> {
+        if request.model.is_some() || !self.models.contains(ANSWER_MODEL) {
+            return Ok(Answer::Skipped);
+        }
+        let llm = self.models.get(Some(ANSWER_MODEL))?;
+        let template = llm.chat_template();
+        let messages = [
+            ChatMessage::new("system", "Answer the user's task concisely, using the tool results when given."),
+            ChatMessage::new("user", prompt),
+        ];
+        let answer_request = InferenceRequest {
+            prompt: template.render(&messages, true),
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            seed: request.seed,
+            stop: template.stop_sequences(),
+            lora: request.lora.clone(),
+            stream: request.stream,
+            timeout_ms: Some(timeout_seconds * 1000),
+            cancel: request.cancel.clone(),
+            ..Default::default()
+        };
+        let response = llm.generate_response_stream(&answer_request, on_token)
+            .context("Answer generation failed")?;
+        llm_calls.push(LlmCall::new("answer", &response));
+        if let Some(error) = backend_failure(&response) {
+            return Ok(Answer::Failed(error));
+        }
+        if let Some(error) = interruption(response.finish_reason) {
+            log::warn!("{}, keeping the tool results", error);
+            return Ok(Answer::Skipped);
+        }
+        Ok(Answer::Text(response.response.trim().to_string()))
+    }
+
     // Agent introspection
     pub fn get_available_tools(&self) -> Vec<String> {
         self.dispatcher.get_available_tools()
     }
 
+    // Model info, memory and backend describe the default model
+    pub fn get_model_info(&self) -> Option<&ModelInfo> {
+        self.models.peek(None)?.model_info()
+    }
+
+    pub fn get_model_memory(&self) -> Option<ModelMemory> {
+        self.models.peek(None)?.memory_usage()
+    }
+
+    pub fn get_backend_name(&self) -> Option<&str> {
+        self.models.peek(None)?.backend_name()
+    }
+
+    pub fn get_models(&self) -> Vec<ModelStatus> {
+        self.models.status()
+    }
+
     pub fn get_memory_stats(&self) -> MemoryStats {
         self.memory.get_stats()
     }
@@ -234,6 +526,34 @@ impl TinyEdgeAgent {
     }
 }
 
+// Outcome of the answer pass of a task
+enum Answer {
+    Text(String),
+    Skipped,
+    Failed(Error),
+}
+
+// Task error for an LLM call the fallback backend answered after the real one failed
+fn backend_failure(response: &InferenceResponse) -> Option<Error> {
+    if response.finish_reason != FinishReason::Error && response.backend_error.is_none() {
+        return None;
+    }
+    let backend_error = response.backend_error.as_deref().unwrap_or("backend failed");
+    Some(
+        Error::new(ErrorKind::InferenceFailed, format!("LLM inference failed: {}", backend_error))
+            .with_detail("backend_error", backend_error),
+    )
+}
+
+// Task error for an LLM call that was cancelled or ran out of time
+fn interruption(reason: FinishReason) -> Option<Error> {
+    match reason {
+        FinishReason::Cancelled => Some(Error::new(ErrorKind::Cancelled, "Generation cancelled")),
+        FinishReason::Deadline => Some(Error::new(ErrorKind::DeadlineExceeded, "Generation ran past its deadline")),
+        FinishReason::Stop | FinishReason::Length | FinishReason::StopSequence | FinishReason::Error => None,
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct AgentHealthStatus {
     pub llm_loaded: bool,
@@ -264,6 +584,316 @@ mod tests {
         assert_eq!(stats.session_entries, 1);
     }
 
+    // Agent answering from an in-memory replay fixture, with no model file
+    async fn replay_agent(fixture: &str) -> TinyEdgeAgent {
+        let backend = tinyedgellmagents_core::ReplayBackend::new(
+            tinyedgellmagents_core::ReplayFixture::from_json(fixture).unwrap(),
+        )
+        .unwrap();
+        let llm = SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(backend));
+        let mut agent = TinyEdgeAgent::with_llm(llm);
+        agent.initialize().await.unwrap();
+        agent
+    }
+
+    // Shell script tool that reports its "operation" back, or fails
+    #[cfg(unix)]
+    fn script_tool(agent: &mut TinyEdgeAgent, name: &str, fail: bool) {
+        use std::os::unix::fs::PermissionsExt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Separate directory per call so parallel tests never rewrite a running script
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "tinyedge-agent-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let body = if fail {
+            "cat > /dev/null\necho 'tool crashed' >&2\nexit 1\n"
+        } else {
+            "sed -n 's/.*\"operation\":\"\\([^\"]*\\)\".*/{\"result\":\"\\1\"}/p'\n"
+        };
+        std::fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        agent.dispatcher.register_tool(name, path.to_str().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_single_action() {
+        let mut agent = replay_agent(
+            r#"{"rules": [{"match": {"contains": "User task: What is 2+2?"},
+                           "response": "{\"tool\": \"math\", \"args\": [\"2+2\"], \"reasoning\": \"add\"}"}]}"#,
+        )
+        .await;
+        script_tool(&mut agent, "math-native", false);
+
+        let response = agent.execute_task(&TaskRequest {
+            task: "What is 2+2?".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        assert!(response.success);
+        assert_eq!(response.tools_used, vec!["math-native"]);
+        assert!(response.result.contains("2+2"));
+        assert_eq!(response.reasoning.as_deref(), Some("add"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_multi_step_plan() {
+        let mut agent = replay_agent(
+            r#"{"rules": [{"match": {"regex": "User task: Add then multiply$"},
+                           "response": "[{\"tool\": \"math\", \"args\": [\"1+2\"]}, {\"tool\": \"math\", \"args\": [\"3*4\"]}]"}]}"#,
+        )
+        .await;
+        script_tool(&mut agent, "math-native", false);
+
+        let response = agent.execute_task(&TaskRequest {
+            task: "Add then multiply".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        assert!(response.success);
+        assert_eq!(response.tools_used.len(), 2);
+        assert!(response.result.contains("1+2") && response.result.contains("3*4"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_task_malformed_output() {
+        let mut agent = replay_agent(r#"{"rules": [], "default_response": "Sorry, I cannot do that."}"#).await;
+
+        let response = agent.execute_task(&TaskRequest {
+            task: "Tell me a joke".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        assert!(response.success);
+        assert!(response.tools_used.is_empty());
+        assert_eq!(response.result, "Sorry, I cannot do that.");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_output_fails_task() {
+        // No rule matches, so the demo backend answers in the replay backend's place
+        let mut agent = replay_agent(r#"{"rules": []}"#).await;
+        let request = TaskRequest { task: "Tell me a joke".to_string(), ..Default::default() };
+        let response = agent.execute_task(&request).await.unwrap();
+        assert!(!response.success && response.tools_used.is_empty());
+        assert!(!response.result.contains("Demo mode"));
+        let error = response.error.unwrap();
+        assert_eq!(error.kind(), ErrorKind::InferenceFailed);
+        assert!(error.details()["backend_error"].as_str().unwrap().starts_with("replay: No replay rule matches"));
+
+        // Same for the answer model phrasing a direct response
+        let mut models = ModelRegistry::single(PLANNER_MODEL, replay_llm(r#"{"rules": [], "default_response": "No plan"}"#));
+        models.insert(ANSWER_MODEL, replay_llm(r#"{"rules": []}"#));
+        let mut agent = TinyEdgeAgent::with_registry(models);
+        agent.initialize().await.unwrap();
+        let response = agent.execute_task(&request).await.unwrap();
+        assert!(!response.success);
+        assert_eq!(response.error.map(|e| e.kind()), Some(ErrorKind::InferenceFailed));
+        assert_eq!(response.llm_calls[1].backend, "demo");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_tool_error() {
+        let mut agent = replay_agent(
+            r#"{"rules": [{"match": {"contains": "List files"},
+                           "response": "{\"tool\": \"shell\", \"args\": [\"ls\", \"-la\"]}"}]}"#,
+        )
+        .await;
+        script_tool(&mut agent, "shell-native", true);
+
+        let response = agent.execute_task(&TaskRequest {
+            task: "List files".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+
+        assert!(!response.success);
+        assert_eq!(response.tools_used, vec!["shell-native"]);
+        assert!(response.result.contains("Error in shell-native"));
+        let error = response.error.unwrap();
+        assert_eq!((error.kind(), error.exit_code()), (ErrorKind::ToolFailed, 5));
+        assert_eq!(error.details()["tool"], "shell-native");
+    }
+
+    #[tokio::test]
+    async fn test_execute_task_unknown_tool() {
+        let mut agent = replay_agent(
+            r#"{"rules": [{"match": {"contains": "Fetch it"},
+                           "response": "{\"tool\": \"fetch\", \"args\": [\"get\", \"http://example.com\"]}"}]}"#,
+        )
+        .await;
+
+        let request = TaskRequest { task: "Fetch it".to_string(), ..Default::default() };
+        let error = agent.execute_task(&request).await.unwrap_err();
+        let error = Error::classify(&error, ErrorKind::Internal);
+        assert_eq!(error.kind(), ErrorKind::ToolNotFound);
+        assert_eq!(error.details()["tool"], "fetch");
+        assert!(error.message().starts_with("Tool execution failed"));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_task_runs_no_tools() {
+        let mut agent = replay_agent(
+            r#"{"rules": [{"match": {"contains": "Fetch it"},
+                           "response": "{\"tool\": \"fetch\", \"args\": [\"get\", \"http://example.com\"]}"}]}"#,
+        )
+        .await;
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let request = TaskRequest { task: "Fetch it".to_string(), cancel: Some(cancel), ..Default::default() };
+        let response = agent.execute_task(&request).await.unwrap();
+        assert!(!response.success);
+        assert!(response.tools_used.is_empty());
+        assert_eq!(response.error.map(|e| e.kind()), Some(ErrorKind::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_execute_task_stream() {
+        let mut agent = replay_agent(r#"{"rules": [], "default_response": "Sorry, I cannot do that."}"#).await;
+
+        let mut pieces = Vec::new();
+        let request = TaskRequest { task: "Tell me a joke".to_string(), stream: true, ..Default::default() };
+        let response = agent
+            .execute_task_stream(&request, &mut |piece| pieces.push(piece.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(pieces.concat(), response.result);
+
+        let done = serde_json::to_value(TaskStreamEvent::Done(Box::new(response))).unwrap();
+        assert_eq!(done["event"], "done");
+        assert_eq!(done["result"], "Sorry, I cannot do that.");
+    }
+
+    fn replay_llm(fixture: &str) -> SuperTinyWasmLLM {
+        let backend = tinyedgellmagents_core::ReplayBackend::new(
+            tinyedgellmagents_core::ReplayFixture::from_json(fixture).unwrap(),
+        )
+        .unwrap();
+        SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(backend))
+    }
+
+    // Plans one tool call with the same log probability for every token
+    struct UnsureBackend(f32);
+
+    impl tinyedgellmagents_core::InferenceBackend for UnsureBackend {
+        fn name(&self) -> &str {
+            "unsure"
+        }
+
+        fn generate(&self, _request: &InferenceRequest) -> Result<tinyedgellmagents_core::BackendOutput> {
+            let text = r#"{"tool": "math", "args": ["2+2"]}"#;
+            let token = tinyedgellmagents_core::TokenLogprob { token: text.to_string(), logprob: self.0, top_logprobs: vec![] };
+            Ok(tinyedgellmagents_core::BackendOutput {
+                text: text.to_string(),
+                logprobs: Some(vec![token.clone(), token]),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsure_plan_asks_for_clarification() {
+        let llm = SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(UnsureBackend(-2.0)));
+        let mut agent = TinyEdgeAgent::with_llm(llm).with_min_confidence(0.5);
+        agent.initialize().await.unwrap();
+
+        let request = TaskRequest { task: "What is 2+2?".to_string(), ..Default::default() };
+        let response = agent.execute_task(&request).await.unwrap();
+        assert!(!response.success && response.tools_used.is_empty());
+        assert!((response.confidence.unwrap() - (-2.0f32).exp()).abs() < 1e-6);
+        let error = response.error.unwrap();
+        assert_eq!((error.kind(), &error.details()["tool"]), (ErrorKind::LowConfidence, &serde_json::json!("math")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_planner_and_answer_models() {
+        let mut models = ModelRegistry::single(
+            PLANNER_MODEL,
+            replay_llm(r#"{"rules": [{"match": {"contains": "User task: What is 2+2?"},
+                                      "response": "{\"tool\": \"math\", \"args\": [\"4\"]}"}],
+                           "default_response": "planner answer"}"#),
+        );
+        models.insert(
+            ANSWER_MODEL,
+            replay_llm(r#"{"rules": [{"match": {"contains": "Task: What is 2+2?\nTool results: "},
+                                      "response": " The answer is 4. "}],
+                           "default_response": "answer model"}"#),
+        );
+        let mut agent = TinyEdgeAgent::with_registry(models);
+        agent.initialize().await.unwrap();
+        script_tool(&mut agent, "math-native", false);
+
+        let request = TaskRequest { task: "What is 2+2?".to_string(), ..Default::default() };
+        let response = agent.execute_task(&request).await.unwrap();
+        assert_eq!((response.result.as_str(), response.tools_used.len()), ("The answer is 4.", 1));
+        let stages: Vec<&str> = response.llm_calls.iter().map(|call| call.stage.as_str()).collect();
+        assert_eq!(stages, ["plan", "answer"]);
+        assert!(response.llm_calls.iter().all(|call| call.backend == "replay" && call.backend_error.is_none()));
+
+        // Naming a model runs the whole task on it, without the answer pass
+        let pinned = TaskRequest { model: Some(ANSWER_MODEL.to_string()), ..request };
+        assert_eq!(agent.execute_task(&pinned).await.unwrap().result, "answer model");
+
+        let unknown = TaskRequest { task: "Hi".to_string(), model: Some("huge".to_string()), ..Default::default() };
+        let error = Error::classify(&agent.execute_task(&unknown).await.unwrap_err(), ErrorKind::Internal);
+        assert_eq!(error.kind(), ErrorKind::ModelNotFound);
+        assert_eq!(agent.get_models().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_history_dropped_to_fit_context_window() {
+        let llm = replay_llm(r#"{"rules": [], "default_response": "Sorry, I cannot do that."}"#);
+        let mut agent = TinyEdgeAgent::with_llm(llm.with_context_length(300))
+            .with_context_policy(ContextPolicy::default().with_strategies(vec![TruncationStrategy::DropOldestHistory]));
+        agent.initialize().await.unwrap();
+
+        let request = TaskRequest { task: "Tell me a joke".to_string(), max_tokens: Some(20), ..Default::default() };
+        let first = agent.execute_task(&request).await.unwrap().context_window.unwrap();
+        assert!(first.dropped.is_empty());
+        assert_eq!((first.context_length, first.reserved_tokens), (Some(300), 20));
+
+        // The first exchange no longer fits next to the system prompt and new task
+        let second = agent.execute_task(&request).await.unwrap().context_window.unwrap();
+        assert!(second.fits && second.prompt_tokens <= 280);
+        assert_eq!(second.dropped[0].strategy, TruncationStrategy::DropOldestHistory);
+        assert!(second.dropped[0].item.starts_with("user message 'Tell me a joke'"));
+        let json = serde_json::to_value(&second).unwrap();
+        assert_eq!(json["dropped"][0]["strategy"], "drop_oldest_history");
+    }
+
+    #[tokio::test]
+    async fn test_prompt_uses_chat_template() {
+        let fixture = r#"{"rules": [{"match": {"regex": "(?s)^<\\|im_start\\|>system\n.*<\\|im_start\\|>user\nUser task: Say hi<\\|im_end\\|>\n<\\|im_start\\|>assistant\n$"},
+                                     "response": "hi there"},
+                                    {"match": {"regex": "(?s)<\\|im_start\\|>assistant\nhi there<\\|im_end\\|>\n.*User task: Again<"},
+                                     "response": "hi again"}],
+                          "default_response": "unformatted"}"#;
+        let backend = tinyedgellmagents_core::ReplayBackend::new(
+            tinyedgellmagents_core::ReplayFixture::from_json(fixture).unwrap(),
+        )
+        .unwrap();
+        let llm = SuperTinyWasmLLM::new(String::new())
+            .with_backend(Box::new(backend))
+            .with_chat_template(tinyedgellmagents_core::ChatTemplate::ChatMl);
+        let mut agent = TinyEdgeAgent::with_llm(llm);
+        agent.initialize().await.unwrap();
+
+        let request = TaskRequest { task: "Say hi".to_string(), ..Default::default() };
+        assert_eq!(agent.execute_task(&request).await.unwrap().result, "hi there");
+        // Earlier turns become chat messages ahead of the current task
+        let again = TaskRequest { task: "Again".to_string(), ..Default::default() };
+        assert_eq!(agent.execute_task(&again).await.unwrap().result, "hi again");
+    }
+
     #[test]
     fn test_task_request_parsing() {
         let json = r#"{"task": "What is 2+2?", "max_tokens": 50}"#;
@@ -1,7 +1,17 @@
-use tinyedgellmagents::{TinyEdgeAgent, TaskRequest};
+use tinyedgellmagents::{
+    bench, BenchConfig, BenchReport, ContextPolicy, TinyEdgeAgent, TaskRequest, TaskStreamEvent, TruncationStrategy,
+};
+use log::LevelFilter;
+use tinyedgellmagents_core::logging::{self, LogConfig, LogFormat};
+use tinyedgellmagents_core::{
+    BackendKind, CancelToken, ChatTemplate, Error, ErrorKind, ErrorResponse, LoadMode, LoraSpec, ModelCache, ModelManifest,
+    ModelRegistry, ReplayBackend, SuperTinyWasmLLM,
+};
 use std::env;
 use std::io::{self, Read, Write};
-use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(name = "tinyedgellmagents")]
@@ -15,6 +25,44 @@ struct Cli {
     #[arg(short, long)]
     model: Option<String>,
     
+    /// Model registry config (JSON) with named models, used instead of --model and the
+    /// other single-model options
+    #[arg(long, value_name = "CONFIG")]
+    models: Option<String>,
+    
+    /// Inference backend: auto, wasi-nn, native, simulation or demo
+    #[arg(short, long)]
+    backend: Option<BackendKind>,
+    
+    /// Chat template: plain, chatml, llama2, llama3, zephyr, phi3 or gemma (defaults to the model's)
+    #[arg(long, value_name = "TEMPLATE")]
+    chat_template: Option<ChatTemplate>,
+    
+    /// Model loading: mmap (page weights in on demand) or read (load the whole file)
+    #[arg(long, value_name = "MODE")]
+    load_mode: Option<LoadMode>,
+    
+    /// LoRA adapter (GGUF) applied on top of the model, as PATH or PATH:SCALE; repeatable
+    #[arg(long, value_name = "ADAPTER")]
+    lora: Vec<LoraSpec>,
+    
+    /// Answer prompts from a replay fixture instead of a model
+    #[arg(long, value_name = "FIXTURE")]
+    replay: Option<String>,
+    
+    /// Record prompt/response pairs into a replay fixture
+    #[arg(long, value_name = "FIXTURE")]
+    record: Option<String>,
+    
+    /// How to shrink prompts that overflow the context window, applied in order:
+    /// truncate_tool_outputs, trim_examples, summarize_history, drop_oldest_history
+    #[arg(long, value_name = "STRATEGIES", value_delimiter = ',')]
+    context_strategies: Vec<TruncationStrategy>,
+    
+    /// Ask for clarification instead of running plans the model is less sure of (0 to 1)
+    #[arg(long, value_name = "CONFIDENCE")]
+    min_confidence: Option<f32>,
+    
     /// Tools directory (optional, defaults to ../tools)
     #[arg(short, long)]
     tools: Option<String>,
@@ -27,6 +75,18 @@ struct Cli {
     #[arg(short, long)]
     pretty: bool,
     
+    /// Show LLM tokens as they are generated (NDJSON events outside interactive mode)
+    #[arg(long)]
+    stream: bool,
+    
+    /// Log format on stderr or the log file: text or json
+    #[arg(long, value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+    
+    /// Write logs to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+    
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
@@ -44,6 +104,12 @@ enum Commands {
         /// Temperature for LLM response
         #[arg(long, default_value = "0.7")]
         temperature: f32,
+        /// Sampling seed, for reproducible output
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Registry model to run the task on (see --models)
+        #[arg(long, value_name = "NAME")]
+        model_name: Option<String>,
     },
     /// Show system status
     Status,
@@ -53,54 +119,264 @@ enum Commands {
     Health,
     /// Enter interactive mode
     Interactive,
+    /// Serve an OpenAI-compatible HTTP API (/v1/completions, /v1/chat/completions, /v1/models)
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Download, verify and remove models in the local model cache
+    Models {
+        /// Model manifest (JSON) with names, URLs, checksums and sizes
+        /// (defaults to SUPERTINYWASMLLM_MODELS_MANIFEST or manifest.json in the cache)
+        #[arg(long, value_name = "MANIFEST")]
+        manifest: Option<String>,
+        /// Cache directory (defaults to SUPERTINYWASMLLM_MODELS_DIR or the user cache directory)
+        #[arg(long, value_name = "DIR")]
+        cache_dir: Option<String>,
+        #[command(subcommand)]
+        action: ModelsCommand,
+    },
+    /// Benchmark the model on a prompt set: load time, time to first token, tokens/sec,
+    /// p50/p95 latency and peak RSS
+    Bench {
+        /// Prompts as a JSON list of strings or one per line (defaults to a built-in set)
+        #[arg(long, value_name = "FILE")]
+        prompts: Option<String>,
+        /// Timed passes over the prompt set
+        #[arg(long, default_value = "3")]
+        iterations: usize,
+        /// Untimed passes before measuring
+        #[arg(long, default_value = "1")]
+        warmup: usize,
+        /// Maximum tokens per response
+        #[arg(long, default_value = "64")]
+        max_tokens: u32,
+        /// Name of this run in comparisons (defaults to the model file name)
+        #[arg(long)]
+        label: Option<String>,
+        /// Also write the JSON report to this file
+        #[arg(long, value_name = "FILE")]
+        save: Option<String>,
+        /// Saved reports of earlier runs to show alongside this one
+        #[arg(long, value_name = "REPORT", num_args = 1..)]
+        compare: Vec<String>,
+        /// Only show the --compare reports, without running the benchmark
+        #[arg(long)]
+        no_run: bool,
+        /// Output: json (the reports) or table (one row per run)
+        #[arg(long, value_enum, default_value = "json")]
+        format: BenchFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BenchFormat {
+    Json,
+    Table,
+}
+
+#[derive(Subcommand)]
+enum ModelsCommand {
+    /// Download a manifest model into the cache, resuming an interrupted download
+    Pull {
+        /// Model name in the manifest
+        name: String,
+        /// Install from this local path or mirror URL instead of the manifest URLs
+        #[arg(long, value_name = "PATH_OR_URL")]
+        from: Option<String>,
+    },
+    /// List cached models
+    List,
+    /// Check cached models against their checksums
+    Verify {
+        /// Model to check (all cached models if omitted)
+        name: Option<String>,
+    },
+    /// Remove a model from the cache
+    Rm {
+        /// Model name
+        name: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    let model_given = cli.model.is_some();
     
     // Initialize agent
     let model_path = cli.model.unwrap_or_else(|| 
         env::var("TINYEDGELLMAGENTS_MODEL").unwrap_or_else(|_| "core/model.gguf".to_string())
     );
     
+    let models_config = cli.models.or_else(|| env::var("TINYEDGELLMAGENTS_MODELS").ok());
+    
     let tools_dir = cli.tools.unwrap_or_else(|| 
         env::var("TINYEDGELLMAGENTS_TOOLS_DIR").unwrap_or_else(|_| "../tools".to_string())
     );
 
-    if !cli.verbose {
-        // Suppress startup messages in non-verbose mode
-        std::env::set_var("TINYEDGELLMAGENTS_QUIET", "1");
+    // Diagnostics go to the log (stderr unless --log-file); stdout carries only results.
+    // Outside verbose mode only warnings are logged unless TINYEDGELLMAGENTS_LOG says otherwise.
+    let mut log_config = LogConfig::from_env()?;
+    if cli.verbose {
+        log_config.level = LevelFilter::Debug;
+    } else if env::var_os(logging::LOG_ENV_VAR).is_none() {
+        log_config.level = LevelFilter::Warn;
+    }
+    if let Some(format) = cli.log_format {
+        log_config.format = format;
+    }
+    if let Some(file) = &cli.log_file {
+        log_config.file = Some(file.into());
     }
+    logging::init(log_config)?;
 
-    println!("TinyEdgeLLMAgents v0.1.0 - Experimental Edge LLM Agent Runtime");
-    println!("============================================");
-    println!("Model path: {}", model_path);
-    println!("Tools directory: {}", tools_dir);
-    
-    if cli.verbose {
-        println!("Initializing agent...");
+    // Cache management needs neither a model nor tools
+    if let Some(Commands::Models { manifest, cache_dir, action }) = cli.command {
+        return run_models(manifest, cache_dir, action, cli.pretty);
     }
+
+    // Registry entries carry their own model settings, so the single-model flags would be ignored
+    if models_config.is_some() {
+        let ignored: Vec<&str> = [
+            ("--model", model_given),
+            ("--backend", cli.backend.is_some()),
+            ("--chat-template", cli.chat_template.is_some()),
+            ("--load-mode", cli.load_mode.is_some()),
+            ("--lora", !cli.lora.is_empty()),
+            ("--replay", cli.replay.is_some()),
+            ("--record", cli.record.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(flag, set)| set.then_some(flag))
+        .collect();
+        if !ignored.is_empty() {
+            let message = format!(
+                "{} cannot be combined with --models, whose entries carry their own model settings",
+                ignored.join(", ")
+            );
+            fail(Error::new(ErrorKind::InvalidRequest, message).with_detail("flags", &ignored), cli.pretty);
+        }
+    }
+
+    // A model pulled into the cache can be given by name instead of path
+    let model_path = if Path::new(&model_path).exists() {
+        model_path
+    } else {
+        ModelCache::from_env().resolve(&model_path).map(|path| path.display().to_string()).unwrap_or(model_path)
+    };
+
+    log::info!("TinyEdgeLLMAgents v0.1.0 - Experimental Edge LLM Agent Runtime");
+    log::info!("Model path: {}", model_path);
+    log::info!("Tools directory: {}", tools_dir);
+    log::debug!("Initializing agent...");
     
-    let mut agent = TinyEdgeAgent::new(&model_path);
-    
-    if let Err(e) = agent.initialize().await {
-        eprintln!("Failed to initialize agent: {}", e);
-        std::process::exit(1);
+    let mut llm = SuperTinyWasmLLM::new(model_path.clone());
+    if let Some(kind) = cli.backend {
+        llm = llm.with_backend_kind(kind);
+    }
+    if let Some(template) = cli.chat_template {
+        llm = llm.with_chat_template(template);
+    }
+    if let Some(mode) = cli.load_mode {
+        llm = llm.with_load_mode(mode);
+    }
+    for spec in &cli.lora {
+        llm = llm.with_lora(spec.clone());
+    }
+    if let Some(fixture) = &cli.replay {
+        llm = llm.with_backend(Box::new(ReplayBackend::from_file(fixture)?));
+    }
+    if let Some(fixture) = &cli.record {
+        llm = llm.with_record_fixture(fixture);
+    }
+
+    // A registry config replaces the single model
+    let registry = match &models_config {
+        Some(path) => {
+            log::info!("Model registry: {}", path);
+            ModelRegistry::load_config(path)
+                .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InvalidRequest), cli.pretty))
+        }
+        None => ModelRegistry::single("default", llm),
+    };
+
+    // The HTTP API serves the default engine directly, without the agent's planner and tools
+    if let Some(Commands::Serve { listen }) = &cli.command {
+        let mut registry = registry;
+        let mut llm = registry.take(None)
+            .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::ModelNotFound), cli.pretty));
+        if let Err(e) = llm.load_model() {
+            fail(Error::classify(&e.context("Failed to load model"), ErrorKind::ModelLoadFailed), cli.pretty);
+        }
+        tinyedgellmagents::openai::serve(llm, listen).await?;
+        return Ok(());
+    }
+
+    // Benchmarks time the default engine alone, like the HTTP API serves it
+    if let Some(Commands::Bench { prompts, iterations, warmup, max_tokens, label, save, compare, no_run, format }) = cli.command {
+        let mut reports: Vec<BenchReport> = compare.iter()
+            .map(|path| BenchReport::load(path).unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InvalidRequest), cli.pretty)))
+            .collect();
+        if !no_run {
+            let mut config = BenchConfig { label, iterations, warmup, max_tokens, ..BenchConfig::default() };
+            if let Some(path) = prompts {
+                config.prompts = BenchConfig::load_prompts(path)
+                    .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InvalidRequest), cli.pretty));
+            }
+            let mut registry = registry;
+            let mut llm = registry.take(None)
+                .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::ModelNotFound), cli.pretty));
+            let report = bench::run(&mut llm, &config)
+                .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::ModelLoadFailed), cli.pretty));
+            if let Some(path) = save {
+                std::fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+            }
+            reports.push(report);
+        }
+        match format {
+            BenchFormat::Table => println!("{}", bench::comparison_table(&reports)),
+            // A single run prints its report; comparisons print the list
+            BenchFormat::Json if reports.len() == 1 => output_json(&reports[0], cli.pretty)?,
+            BenchFormat::Json => output_json(&reports, cli.pretty)?,
+        }
+        return Ok(());
+    }
+
+    let mut agent = TinyEdgeAgent::with_registry(registry);
+    if !cli.context_strategies.is_empty() {
+        agent = agent.with_context_policy(ContextPolicy::default().with_strategies(cli.context_strategies));
+    }
+    let min_confidence = cli.min_confidence
+        .or_else(|| env::var("TINYEDGELLMAGENTS_MIN_CONFIDENCE").ok().and_then(|value| value.parse().ok()));
+    if let Some(threshold) = min_confidence {
+        agent = agent.with_min_confidence(threshold);
     }
     
-    if cli.verbose {
-        println!("TinyEdgeAgent initialized successfully");
-        println!("Loading tools...");
+    if let Err(e) = agent.initialize().await {
+        fail(Error::classify(&e.context("Failed to initialize agent"), ErrorKind::ModelLoadFailed), cli.pretty);
     }
     
+    log::debug!("Loading tools...");
     let tools_loaded = agent.load_tools(&tools_dir).await.unwrap_or(0);
-    println!("Loaded {} tools", tools_loaded);
+    log::info!("Loaded {} tools", tools_loaded);
     
     // Handle commands
     match cli.command {
-        Some(Commands::Task { task, max_tokens, temperature }) => {
-            execute_single_task(&mut agent, &task, max_tokens, temperature, cli.pretty).await?;
+        Some(Commands::Task { task, max_tokens, temperature, seed, model_name }) => {
+            let request = TaskRequest {
+                task,
+                model: model_name,
+                context: None,
+                max_tokens: Some(max_tokens),
+                temperature: Some(temperature),
+                seed,
+                lora: None,
+                stream: cli.stream,
+                cancel: None,
+            };
+            run_task(&mut agent, &request, cli.pretty).await?;
         }
         Some(Commands::Status) => {
             show_status(&agent, cli.pretty).await?;
@@ -112,39 +388,113 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             show_health(&agent, cli.pretty).await?;
         }
         Some(Commands::Interactive) => {
-            run_interactive_mode(&mut agent, cli.pretty).await?;
+            run_interactive_mode(&mut agent, cli.stream, cli.pretty).await?;
+        }
+        Some(Commands::Serve { .. }) | Some(Commands::Models { .. }) | Some(Commands::Bench { .. }) => {
+            unreachable!("handled before agent initialization")
         }
         None if cli.interactive => {
-            run_interactive_mode(&mut agent, cli.pretty).await?;
+            run_interactive_mode(&mut agent, cli.stream, cli.pretty).await?;
         }
         None => {
             // Default: read from stdin (backwards compatible)
-            run_stdin_mode(&mut agent, cli.pretty).await?;
+            run_stdin_mode(&mut agent, cli.stream, cli.pretty).await?;
         }
     }
     
     Ok(())
 }
 
-async fn execute_single_task(
-    agent: &mut TinyEdgeAgent, 
-    task: &str, 
-    max_tokens: u32, 
-    temperature: f32,
+// Print `error` as an error response and exit with the code documented for its category
+fn fail(error: Error, pretty: bool) -> ! {
+    log::error!("{} ({})", error, error.kind());
+    let exit_code = error.exit_code();
+    if let Err(e) = output_json(&ErrorResponse { id: None, error }, pretty) {
+        log::error!("Failed to write error response: {}", e);
+    }
+    std::process::exit(exit_code);
+}
+
+// Run a model cache command and print its result as JSON. Verification failures exit
+// with the checksum_mismatch exit code after the report is printed.
+fn run_models(
+    manifest: Option<String>,
+    cache_dir: Option<String>,
+    action: ModelsCommand,
+    pretty: bool
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cache = cache_dir.map(ModelCache::new).unwrap_or_else(ModelCache::from_env);
+    match action {
+        ModelsCommand::Pull { name, from } => {
+            let path = manifest.map(PathBuf::from).unwrap_or_else(|| ModelManifest::default_path(&cache));
+            let manifest = ModelManifest::load(&path)
+                .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InvalidRequest), pretty));
+            let entry = manifest.get(&name)
+                .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::ModelNotFound), pretty));
+            let model = cache.pull(entry, from.as_deref())
+                .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::DownloadFailed), pretty));
+            output_json(&model, pretty)?;
+        }
+        ModelsCommand::List => {
+            let models = cache.list().unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InputFailed), pretty));
+            output_json(&serde_json::json!({ "cache_dir": cache.dir(), "models": models }), pretty)?;
+        }
+        ModelsCommand::Verify { name } => {
+            let names = match name {
+                Some(name) => vec![name],
+                None => cache.list()
+                    .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InputFailed), pretty))
+                    .into_iter()
+                    .map(|model| model.name)
+                    .collect(),
+            };
+            let reports: Vec<_> = names.iter()
+                .map(|name| cache.verify(name).unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InputFailed), pretty)))
+                .collect();
+            output_json(&serde_json::json!({ "models": reports }), pretty)?;
+            if reports.iter().any(|report| !report.ok) {
+                std::process::exit(ErrorKind::ChecksumMismatch.exit_code());
+            }
+        }
+        ModelsCommand::Rm { name } => {
+            let model = cache.remove(&name)
+                .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::InputFailed), pretty));
+            output_json(&model, pretty)?;
+        }
+    }
+    Ok(())
+}
+
+// Execute a request and print its response, as NDJSON events when it asks to stream.
+// A task that fails ends the process with its error's exit code.
+async fn run_task(
+    agent: &mut TinyEdgeAgent,
+    request: &TaskRequest,
     pretty: bool
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let request = TaskRequest {
-        task: task.to_string(),
-        context: None,
-        max_tokens: Some(max_tokens),
-        temperature: Some(temperature),
+    let error = if request.stream {
+        let mut index = 0;
+        let result = agent.execute_task_stream(request, &mut |text| {
+            let event = TaskStreamEvent::Token { index, text: text.to_string() };
+            index += 1;
+            if let Err(e) = output_event(&event) {
+                log::error!("Failed to write token event: {}", e);
+            }
+        }).await;
+        // Streamed output stays one compact JSON object per line, errors included
+        let response = result.unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::Internal), false));
+        let error = response.error.clone();
+        output_event(&TaskStreamEvent::Done(Box::new(response)))?;
+        error
+    } else {
+        let response = agent.execute_task(request).await
+            .unwrap_or_else(|e| fail(Error::classify(&e, ErrorKind::Internal), pretty));
+        output_response(&response, pretty)?;
+        response.error
     };
     
-    let response = agent.execute_task(&request).await?;
-    output_response(&response, pretty)?;
-    
-    if !response.success {
-        std::process::exit(1);
+    if let Some(error) = error {
+        std::process::exit(error.exit_code());
     }
     
     Ok(())
@@ -159,6 +509,10 @@ async fn show_status(agent: &TinyEdgeAgent, pretty: bool) -> Result<(), Box<dyn
         "version": "0.1.0",
         "status": "ready",
         "llm_loaded": health.llm_loaded,
+        "backend": agent.get_backend_name(),
+        "model": agent.get_model_info(),
+        "model_memory": agent.get_model_memory(),
+        "models": agent.get_models(),
         "total_tools": health.total_tools,
         "healthy_tools": health.tools_healthy.values().filter(|&&v| v).count(),
         "memory_usage": health.memory_usage,
@@ -187,7 +541,7 @@ async fn show_health(agent: &TinyEdgeAgent, pretty: bool) -> Result<(), Box<dyn
     Ok(())
 }
 
-async fn run_interactive_mode(agent: &mut TinyEdgeAgent, pretty: bool) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_interactive_mode(agent: &mut TinyEdgeAgent, stream: bool, pretty: bool) -> Result<(), Box<dyn std::error::Error>> {
     println!("\nTinyEdgeLLMAgents Interactive Mode");
     println!("Type your tasks naturally, or use commands:");
     println!("  /help    - Show this help");
@@ -195,7 +549,20 @@ async fn run_interactive_mode(agent: &mut TinyEdgeAgent, pretty: bool) -> Result
     println!("  /tools   - List available tools");
     println!("  /health  - Run health check");
     println!("  /quit    - Exit interactive mode");
+    println!("  Ctrl-C stops the current generation");
     println!();
+
+    // Ctrl-C cancels the task in progress; at the prompt it exits as usual
+    let current: Arc<Mutex<Option<CancelToken>>> = Arc::default();
+    let watched = Arc::clone(&current);
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            match watched.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+                Some(token) => token.cancel(),
+                None => std::process::exit(130),
+            }
+        }
+    });
     
     loop {
         print!("💬 > ");
@@ -238,21 +605,39 @@ async fn run_interactive_mode(agent: &mut TinyEdgeAgent, pretty: bool) -> Result
             }
             _ => {
                 // Regular task
+                let cancel = CancelToken::new();
                 let request = TaskRequest {
                     task: input.to_string(),
+                    model: None,
                     context: None,
                     max_tokens: Some(100),
                     temperature: Some(0.7),
+                    seed: None,
+                    lora: None,
+                    stream,
+                    cancel: Some(cancel.clone()),
                 };
                 
                 println!("🔄 Processing...");
-                match agent.execute_task(&request).await {
+                *current.lock().unwrap_or_else(|e| e.into_inner()) = Some(cancel);
+                let result = agent.execute_task_stream(&request, &mut |text| {
+                    if stream {
+                        print!("{}", text);
+                        let _ = io::stdout().flush();
+                    }
+                }).await;
+                current.lock().unwrap_or_else(|e| e.into_inner()).take();
+                if stream {
+                    println!();
+                }
+                match result {
                     Ok(response) => {
                         println!("✅ Result:");
                         output_response(&response, pretty)?;
                     }
                     Err(e) => {
-                        println!("❌ Error: {}", e);
+                        let error = Error::classify(&e, ErrorKind::Internal);
+                        println!("❌ Error ({}): {}", error.kind(), error);
                     }
                 }
             }
@@ -263,57 +648,58 @@ async fn run_interactive_mode(agent: &mut TinyEdgeAgent, pretty: bool) -> Result
     Ok(())
 }
 
-async fn run_stdin_mode(agent: &mut TinyEdgeAgent, pretty: bool) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_stdin_mode(agent: &mut TinyEdgeAgent, stream: bool, pretty: bool) -> Result<(), Box<dyn std::error::Error>> {
     if atty::is(atty::Stream::Stdin) {
-        println!("\n📥 Reading from stdin...");
-        println!("💡 Tip: Use --interactive for interactive mode");
-        println!("📖 Example: echo '{{\"task\": \"What is 2+2?\"}}' | tinyedgeagents");
-        println!();
+        eprintln!("\n📥 Reading from stdin...");
+        eprintln!("💡 Tip: Use --interactive for interactive mode");
+        eprintln!("📖 Example: echo '{{\"task\": \"What is 2+2?\"}}' | tinyedgeagents");
+        eprintln!();
     }
     
     let mut input = String::new();
     io::stdin().read_to_string(&mut input)?;
     
     if input.trim().is_empty() {
-        eprintln!("❌ No input provided");
-        std::process::exit(1);
+        fail(Error::new(ErrorKind::InvalidRequest, "No input provided"), pretty);
     }
     
     // Try to parse as JSON first
-    match serde_json::from_str::<TaskRequest>(&input) {
-        Ok(request) => {
-            let response = agent.execute_task(&request).await?;
-            output_response(&response, pretty)?;
-            
-            if !response.success {
-                std::process::exit(1);
-            }
+    let request = match serde_json::from_str::<TaskRequest>(&input) {
+        Ok(mut request) => {
+            request.stream |= stream;
+            request
         }
         Err(_) => {
             // Treat as plain text task
-            let request = TaskRequest {
+            TaskRequest {
                 task: input.trim().to_string(),
+                model: None,
                 context: None,
                 max_tokens: Some(100),
                 temperature: Some(0.7),
-            };
-            
-            let response = agent.execute_task(&request).await?;
-            output_response(&response, pretty)?;
-            
-            if !response.success {
-                std::process::exit(1);
+                seed: None,
+                lora: None,
+                stream,
+                cancel: None,
             }
         }
-    }
+    };
     
-    Ok(())
+    run_task(agent, &request, pretty).await
 }
 
 fn output_response(response: &tinyedgellmagents::TaskResponse, pretty: bool) -> Result<(), Box<dyn std::error::Error>> {
     output_json(response, pretty)
 }
 
+// Streamed events are always one compact JSON object per line
+fn output_event(event: &TaskStreamEvent) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", serde_json::to_string(event)?)?;
+    stdout.flush()?;
+    Ok(())
+}
+
 fn output_json(value: &impl serde::Serialize, pretty: bool) -> Result<(), Box<dyn std::error::Error>> {
     let output = if pretty {
         serde_json::to_string_pretty(value)?
@@ -60,6 +60,13 @@ impl AgentMemory {
         self.session_data.remove(key)
     }
 
+    // Session data sorted by key, for prompts that do not change from run to run
+    pub fn session_entries(&self) -> Vec<(String, String)> {
+        let mut entries: Vec<_> = self.session_data.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        entries.sort();
+        entries
+    }
+
     pub fn clear_session(&mut self) {
         self.session_data.clear();
     }
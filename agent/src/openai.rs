@@ -0,0 +1,601 @@
+// OpenAI-compatible HTTP API over the inference engine: /v1/completions,
+// /v1/chat/completions (with SSE streaming), /v1/embeddings and /v1/models. A small HTTP/1.1
+// server on tokio; every response closes its connection.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tinyedgellmagents_core::{
+    ChatMessage, EmbeddingRequest, Error, ErrorCategory, ErrorKind, FinishReason, InferenceRequest, InferenceResponse,
+    LoraSelection, SuperTinyWasmLLM,
+};
+
+// Requests larger than this are refused
+const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+pub async fn serve(llm: SuperTinyWasmLLM, addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("OpenAI-compatible API listening on http://{}", listener.local_addr()?);
+    serve_listener(Arc::new(llm), listener).await
+}
+
+pub async fn serve_listener(llm: Arc<SuperTinyWasmLLM>, listener: TcpListener) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let llm = Arc::clone(&llm);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(llm, stream).await {
+                log::warn!("HTTP connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+// Error mapped to an HTTP status and an OpenAI-style error body
+struct ApiError {
+    status: u16,
+    message: String,
+    // Engine errors report their kind as the code, others the HTTP status
+    kind: Option<ErrorKind>,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into(), kind: None }
+    }
+
+    fn from_engine(error: &anyhow::Error) -> Self {
+        let error = Error::classify(error, ErrorKind::InferenceFailed);
+        let status = match (error.kind(), error.category()) {
+            (ErrorKind::ModelNotLoaded, _) => 503,
+            (_, ErrorCategory::Request) => 400,
+            _ => 500,
+        };
+        Self { status, message: error.message().to_string(), kind: Some(error.kind()) }
+    }
+
+    // A response the demo fallback produced after the selected backend failed: its text
+    // is no completion, so it is reported as the backend's error
+    fn from_fallback(response: &InferenceResponse) -> Option<Self> {
+        if response.finish_reason != FinishReason::Error && response.backend_error.is_none() {
+            return None;
+        }
+        let message = response.backend_error.as_deref().unwrap_or("backend failed");
+        Some(Self { status: 500, message: format!("Inference failed: {}", message), kind: Some(ErrorKind::InferenceFailed) })
+    }
+
+    fn body(&self) -> Value {
+        let kind = if self.status >= 500 { "server_error" } else { "invalid_request_error" };
+        let code = match self.kind {
+            Some(kind) => json!(kind),
+            None => json!(self.status),
+        };
+        json!({"error": {"message": self.message, "type": kind, "code": code}})
+    }
+}
+
+async fn handle_connection(llm: Arc<SuperTinyWasmLLM>, stream: TcpStream) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let request = match read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(e) => return write_json(stream.get_mut(), e.status, &e.body()).await,
+    };
+    let stream = stream.get_mut();
+
+    let result = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/v1/models") => write_json(stream, 200, &models(&llm)).await,
+        ("POST", "/v1/completions") => completion(&llm, &request.body, stream, Kind::Text).await,
+        ("POST", "/v1/chat/completions") => completion(&llm, &request.body, stream, Kind::Chat).await,
+        ("POST", "/v1/embeddings") => embeddings(&llm, &request.body, stream).await,
+        (_, "/v1/models" | "/v1/completions" | "/v1/chat/completions" | "/v1/embeddings") => {
+            let error = ApiError::new(405, format!("Method {} not allowed", request.method));
+            write_json(stream, error.status, &error.body()).await
+        }
+        (_, path) => {
+            let error = ApiError::new(404, format!("Unknown endpoint {}", path));
+            write_json(stream, error.status, &error.body()).await
+        }
+    };
+    stream.shutdown().await.ok();
+    result
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<HttpRequest, ApiError> {
+    let bad_request = |e: std::io::Error| ApiError::new(400, format!("Malformed HTTP request: {}", e));
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.map_err(bad_request)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(ApiError::new(400, "Malformed HTTP request line"));
+    };
+    let method = method.to_string();
+    let path = target.split('?').next().unwrap_or_default().trim_end_matches('/').to_string();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await.map_err(bad_request)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| ApiError::new(400, "Invalid Content-Length"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(ApiError::new(413, format!("Request body exceeds {} bytes", MAX_BODY_BYTES)));
+    }
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await.map_err(bad_request)?;
+    Ok(HttpRequest { method, path, body })
+}
+
+fn model_id(llm: &SuperTinyWasmLLM) -> String {
+    llm.model_info()
+        .map(|info| info.file_name.clone())
+        .unwrap_or_else(|| "tinyedgellmagents".to_string())
+}
+
+fn models(llm: &SuperTinyWasmLLM) -> Value {
+    json!({
+        "object": "list",
+        "data": [{"id": model_id(llm), "object": "model", "created": unix_time(), "owned_by": "tinyedgellmagents"}]
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Text,
+    Chat,
+}
+
+// Options shared by both completion endpoints; top_k, min_p, repeat_penalty, grammar
+// and lora are extensions understood by llama.cpp-style servers
+#[derive(Debug, Default, Deserialize)]
+struct CompletionOptions {
+    max_tokens: Option<u32>,
+    max_completion_tokens: Option<u32>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    top_k: Option<usize>,
+    min_p: Option<f32>,
+    repeat_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+    presence_penalty: Option<f32>,
+    seed: Option<u64>,
+    stop: Option<Value>,
+    #[serde(default)]
+    stream: bool,
+    n: Option<u32>,
+    response_format: Option<Value>,
+    grammar: Option<String>,
+    lora: Option<Vec<LoraSelection>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextCompletionRequest {
+    prompt: Value,
+    #[serde(flatten)]
+    options: CompletionOptions,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    messages: Vec<ChatMessageIn>,
+    #[serde(flatten)]
+    options: CompletionOptions,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessageIn {
+    role: String,
+    // A string, or a list of content parts of which the text ones are used
+    #[serde(default)]
+    content: Value,
+}
+
+impl CompletionOptions {
+    fn into_request(self, prompt: String, mut stop: Vec<String>) -> Result<InferenceRequest, ApiError> {
+        if self.n.is_some_and(|n| n != 1) {
+            return Err(ApiError::new(400, "Only n = 1 is supported"));
+        }
+        match self.stop {
+            None | Some(Value::Null) => {}
+            Some(Value::String(s)) => stop.push(s),
+            Some(Value::Array(items)) => stop.extend(items.iter().filter_map(|s| s.as_str().map(str::to_string))),
+            Some(_) => return Err(ApiError::new(400, "stop must be a string or a list of strings")),
+        }
+        let json_schema = match &self.response_format {
+            None => None,
+            Some(format) => match format.get("type").and_then(Value::as_str) {
+                Some("text") => None,
+                Some("json_object") => Some(json!({"type": "object"})),
+                Some("json_schema") => format.pointer("/json_schema/schema").cloned().or(Some(json!({}))),
+                _ => return Err(ApiError::new(400, "Unsupported response_format")),
+            },
+        };
+
+        Ok(InferenceRequest {
+            prompt,
+            max_tokens: self.max_completion_tokens.or(self.max_tokens),
+            temperature: self.temperature,
+            top_k: self.top_k,
+            top_p: self.top_p,
+            min_p: self.min_p,
+            repeat_penalty: self.repeat_penalty,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            seed: self.seed,
+            stop,
+            grammar: self.grammar,
+            json_schema,
+            lora: self.lora,
+            stream: self.stream,
+            ..Default::default()
+        })
+    }
+}
+
+fn text_content(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn build_request(llm: &SuperTinyWasmLLM, body: &[u8], kind: Kind) -> Result<InferenceRequest, ApiError> {
+    let invalid = |e: serde_json::Error| ApiError::new(400, format!("Invalid request body: {}", e));
+    match kind {
+        Kind::Text => {
+            let request: TextCompletionRequest = serde_json::from_slice(body).map_err(invalid)?;
+            let prompt = match &request.prompt {
+                Value::String(prompt) => prompt.clone(),
+                Value::Array(prompts) if prompts.len() == 1 && prompts[0].is_string() => {
+                    prompts[0].as_str().unwrap_or_default().to_string()
+                }
+                _ => return Err(ApiError::new(400, "prompt must be a string")),
+            };
+            request.options.into_request(prompt, Vec::new())
+        }
+        Kind::Chat => {
+            let request: ChatCompletionRequest = serde_json::from_slice(body).map_err(invalid)?;
+            if request.messages.is_empty() {
+                return Err(ApiError::new(400, "messages must not be empty"));
+            }
+            let messages: Vec<ChatMessage> = request
+                .messages
+                .iter()
+                .map(|m| {
+                    let role = if m.role == "developer" { "system" } else { m.role.as_str() };
+                    ChatMessage::new(role, &text_content(&m.content))
+                })
+                .collect();
+            let template = llm.chat_template();
+            request.options.into_request(template.render(&messages, true), template.stop_sequences())
+        }
+    }
+}
+
+// OpenAI only distinguishes running out of tokens from stopping
+fn finish_reason(response: &InferenceResponse) -> &'static str {
+    match response.finish_reason {
+        FinishReason::Length => "length",
+        _ => "stop",
+    }
+}
+
+fn usage(response: &InferenceResponse) -> Value {
+    json!({
+        "prompt_tokens": response.prompt_tokens,
+        "completion_tokens": response.tokens_generated,
+        "total_tokens": response.prompt_tokens + response.tokens_generated,
+    })
+}
+
+// One response object, or one streamed chunk when `finish` is not yet known
+fn choice(kind: Kind, text: &str, finish: Option<&str>, chunk: bool) -> Value {
+    match (kind, chunk) {
+        (Kind::Text, _) => json!({"index": 0, "text": text, "logprobs": null, "finish_reason": finish}),
+        (Kind::Chat, false) => json!({
+            "index": 0,
+            "message": {"role": "assistant", "content": text},
+            "logprobs": null,
+            "finish_reason": finish,
+        }),
+        (Kind::Chat, true) => {
+            let delta = if finish.is_some() { json!({}) } else { json!({"content": text}) };
+            json!({"index": 0, "delta": delta, "logprobs": null, "finish_reason": finish})
+        }
+    }
+}
+
+fn envelope(kind: Kind, id: &str, model: &str, chunk: bool, choice: Value) -> Value {
+    let object = match (kind, chunk) {
+        (Kind::Text, _) => "text_completion",
+        (Kind::Chat, false) => "chat.completion",
+        (Kind::Chat, true) => "chat.completion.chunk",
+    };
+    json!({"id": id, "object": object, "created": unix_time(), "model": model, "choices": [choice]})
+}
+
+async fn completion(llm: &Arc<SuperTinyWasmLLM>, body: &[u8], stream: &mut TcpStream, kind: Kind) -> Result<()> {
+    let request = match build_request(llm, body, kind) {
+        Ok(request) => request,
+        Err(e) => return write_json(stream, e.status, &e.body()).await,
+    };
+    let id = format!("{}-{:x}", if kind == Kind::Chat { "chatcmpl" } else { "cmpl" }, unique_suffix());
+    let model = model_id(llm);
+
+    if !request.stream {
+        let engine = Arc::clone(llm);
+        let generation = tokio::task::spawn_blocking(move || engine.generate_response(&request));
+        return match generation.await? {
+            Ok(response) => {
+                if let Some(error) = ApiError::from_fallback(&response) {
+                    return write_json(stream, error.status, &error.body()).await;
+                }
+                let finish = finish_reason(&response);
+                let mut body = envelope(kind, &id, &model, false, choice(kind, &response.response, Some(finish), false));
+                body["usage"] = usage(&response);
+                write_json(stream, 200, &body).await
+            }
+            Err(e) => {
+                let error = ApiError::from_engine(&e);
+                write_json(stream, error.status, &error.body()).await
+            }
+        };
+    }
+
+    // Generation runs on a blocking thread and hands pieces over a channel
+    let (sender, mut pieces) = mpsc::unbounded_channel::<String>();
+    let engine = Arc::clone(llm);
+    let generation = tokio::task::spawn_blocking(move || {
+        engine.generate_response_stream(&request, &mut |piece| {
+            sender.send(piece.to_string()).ok();
+        })
+    });
+
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")
+        .await?;
+    if kind == Kind::Chat {
+        let opening = json!({"index": 0, "delta": {"role": "assistant", "content": ""}, "logprobs": null, "finish_reason": null});
+        write_event(stream, &envelope(kind, &id, &model, true, opening)).await?;
+    }
+    while let Some(piece) = pieces.recv().await {
+        write_event(stream, &envelope(kind, &id, &model, true, choice(kind, &piece, None, true))).await?;
+    }
+
+    match generation.await? {
+        // The pieces sent came from the demo fallback: end with the backend's error, not a finish reason
+        Ok(response) => match ApiError::from_fallback(&response) {
+            Some(error) => write_event(stream, &error.body()).await?,
+            None => {
+                let finish = finish_reason(&response);
+                let mut last = envelope(kind, &id, &model, true, choice(kind, "", Some(finish), true));
+                last["usage"] = usage(&response);
+                write_event(stream, &last).await?;
+            }
+        },
+        Err(e) => write_event(stream, &ApiError::from_engine(&e).body()).await?,
+    }
+    stream.write_all(b"data: [DONE]\n\n").await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsBody {
+    #[serde(flatten)]
+    request: EmbeddingRequest,
+    encoding_format: Option<String>,
+}
+
+async fn embeddings(llm: &Arc<SuperTinyWasmLLM>, body: &[u8], stream: &mut TcpStream) -> Result<()> {
+    let request = match serde_json::from_slice::<EmbeddingsBody>(body) {
+        Ok(body) if body.encoding_format.as_deref().is_some_and(|format| format != "float") => {
+            let error = ApiError::new(400, "Only the float encoding_format is supported");
+            return write_json(stream, error.status, &error.body()).await;
+        }
+        Ok(body) => body.request,
+        Err(e) => {
+            let error = ApiError::new(400, format!("Invalid request body: {}", e));
+            return write_json(stream, error.status, &error.body()).await;
+        }
+    };
+
+    let engine = Arc::clone(llm);
+    match tokio::task::spawn_blocking(move || engine.embed(&request)).await? {
+        Ok(response) => {
+            let data: Vec<Value> = response
+                .embeddings
+                .iter()
+                .enumerate()
+                .map(|(index, vector)| json!({"object": "embedding", "index": index, "embedding": vector}))
+                .collect();
+            let usage = json!({"prompt_tokens": response.prompt_tokens, "total_tokens": response.prompt_tokens});
+            let body = json!({"object": "list", "data": data, "model": model_id(llm), "usage": usage});
+            write_json(stream, 200, &body).await
+        }
+        Err(e) => {
+            let error = ApiError::from_engine(&e);
+            write_json(stream, error.status, &error.body()).await
+        }
+    }
+}
+
+async fn write_event(stream: &mut TcpStream, value: &Value) -> Result<()> {
+    stream.write_all(format!("data: {}\n\n", value).as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn write_json(stream: &mut TcpStream, status: u16, body: &Value) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.flush().await.map_err(|e| anyhow!("Failed to write response: {}", e))
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn unique_suffix() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tinyedgellmagents_core::{ChatTemplate, ReplayBackend, ReplayFixture};
+
+    async fn start() -> String {
+        let fixture = ReplayFixture::from_json(
+            r#"{"rules": [{"match": {"exact": "Say hi"}, "response": "hi there"},
+                          {"match": {"contains": "<|im_start|>user\nWhat is 2+2?<|im_end|>"}, "response": "4"}]}"#,
+        )
+        .unwrap();
+        let mut llm = SuperTinyWasmLLM::new(String::new())
+            .with_backend(Box::new(ReplayBackend::new(fixture).unwrap()))
+            .with_chat_template(ChatTemplate::ChatMl);
+        llm.load_model().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve_listener(Arc::new(llm), listener));
+        addr
+    }
+
+    // Send one request and return the status code and body
+    async fn call(addr: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("{} {} HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head[9..12].parse().unwrap(), body.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_completions_and_models() {
+        let addr = start().await;
+
+        let (status, body) = call(&addr, "POST", "/v1/completions", r#"{"model": "x", "prompt": "Say hi", "max_tokens": 20}"#).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body["object"], "text_completion");
+        assert_eq!(body["choices"][0]["text"], "hi there");
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+        assert_eq!(body["usage"]["total_tokens"], body["usage"]["prompt_tokens"].as_u64().unwrap() + 2);
+
+        let chat = r#"{"messages": [{"role": "user", "content": [{"type": "text", "text": "What is 2+2?"}]}]}"#;
+        let (_, body) = call(&addr, "POST", "/v1/chat/completions", chat).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "4");
+
+        let (status, body) = call(&addr, "GET", "/v1/models", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["data"][0]["object"], "model");
+
+        let (status, body) = call(&addr, "POST", "/v1/completions", r#"{"prompt": 1}"#).await;
+        assert_eq!(status, 400);
+        assert!(serde_json::from_str::<Value>(&body).unwrap()["error"]["message"].is_string());
+        assert_eq!(call(&addr, "GET", "/v2/nothing", "").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn test_embeddings() {
+        let addr = start().await;
+
+        let (status, body) = call(&addr, "POST", "/v1/embeddings", r#"{"model": "x", "input": ["Say hi", "What is 2+2?"]}"#).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body["object"], "list");
+        assert_eq!(body["data"][1]["index"], 1);
+        assert!(body["data"][0]["embedding"].as_array().is_some_and(|v| !v.is_empty()));
+
+        let (status, _) = call(&addr, "POST", "/v1/embeddings", r#"{"input": "hi", "encoding_format": "base64"}"#).await;
+        assert_eq!(status, 400);
+        assert_eq!(call(&addr, "POST", "/v1/embeddings", r#"{"input": []}"#).await.0, 400);
+    }
+
+    #[tokio::test]
+    async fn test_chat_streaming_sse() {
+        let addr = start().await;
+        let chat = r#"{"messages": [{"role": "user", "content": "What is 2+2?"}], "stream": true}"#;
+        let (status, body) = call(&addr, "POST", "/v1/chat/completions", chat).await;
+        assert_eq!(status, 200);
+
+        let events: Vec<&str> = body.split("\n\n").filter_map(|e| e.strip_prefix("data: ")).collect();
+        assert_eq!(events.last(), Some(&"[DONE]"));
+        let chunks: Vec<Value> = events[..events.len() - 1].iter().map(|e| serde_json::from_str(e).unwrap()).collect();
+        assert!(chunks.iter().all(|c| c["object"] == "chat.completion.chunk"));
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        let text: String = chunks.iter().filter_map(|c| c["choices"][0]["delta"]["content"].as_str()).collect();
+        assert_eq!(text, "4");
+        assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_output_is_an_error() {
+        let addr = start().await;
+
+        // No replay rule matches, so only the demo backend could answer
+        let (status, body) = call(&addr, "POST", "/v1/completions", r#"{"prompt": "Tell me a joke"}"#).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status, 500);
+        assert_eq!(body["error"]["code"], "inference_failed");
+        assert!(body["error"]["message"].as_str().unwrap().contains("No replay rule matches"));
+
+        let chat = r#"{"messages": [{"role": "user", "content": "Tell me a joke"}], "stream": true}"#;
+        let (status, body) = call(&addr, "POST", "/v1/chat/completions", chat).await;
+        assert_eq!(status, 200);
+        let events: Vec<&str> = body.split("\n\n").filter_map(|e| e.strip_prefix("data: ")).collect();
+        assert_eq!(events.last(), Some(&"[DONE]"));
+        let last: Value = serde_json::from_str(events[events.len() - 2]).unwrap();
+        assert_eq!(last["error"]["code"], "inference_failed");
+        assert!(!body.contains("\"finish_reason\":\"stop\""));
+    }
+}
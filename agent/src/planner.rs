@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
+use tinyedgellmagents_core::{Error, ErrorKind, TokenLogprob};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionPlan {
@@ -101,6 +102,21 @@ impl Planner {
         &self.available_tools
     }
 
+    // Seconds a plan, and the LLM call producing it, may take
+    pub fn default_timeout(&self) -> u64 {
+        self.default_timeout
+    }
+
+    // How sure the model was of a plan: the geometric mean probability of its tokens,
+    // from 0 to 1. None when the backend reported no log probabilities.
+    pub fn plan_confidence(logprobs: &[TokenLogprob]) -> Option<f32> {
+        if logprobs.is_empty() {
+            return None;
+        }
+        let mean = logprobs.iter().map(|t| t.logprob).sum::<f32>() / logprobs.len() as f32;
+        Some(mean.exp())
+    }
+
     // Main function: Parse LLM response into execution plan
     pub fn parse_llm_response(&self, response: &str) -> Result<ExecutionPlan> {
         // Try different parsing strategies
@@ -116,7 +132,9 @@ impl Planner {
             return Ok(plan);
         }
 
-        Err(anyhow!("Could not parse LLM response into action plan: {}", response))
+        Err(Error::new(ErrorKind::PlanParseFailed, format!("Could not parse LLM response into action plan: {}", response))
+            .with_detail("response", response)
+            .into())
     }
 
     // Parse direct JSON format like {"tool": "math", "args": ["2+2"]}
@@ -356,32 +374,13 @@ impl Planner {
         Ok(validated)
     }
 
-    fn map_tool_alias(&self, tool_name: &str) -> String {
-        // If tool exists directly, return it
-        if self.available_tools.contains_key(tool_name) {
-            return tool_name.to_string();
-        }
-
-        // Map aliases to actual tool names
-        match tool_name {
-            "math" => self.available_tools.keys()
-                .find(|k| k.contains("math"))
-                .cloned()
-                .unwrap_or_else(|| tool_name.to_string()),
-            "fetch" => self.available_tools.keys()
-                .find(|k| k.contains("fetch"))
-                .cloned()
-                .unwrap_or_else(|| tool_name.to_string()),
-            "shell" => self.available_tools.keys()
-                .find(|k| k.contains("shell"))
-                .cloned()
-                .unwrap_or_else(|| tool_name.to_string()),
-            _ => tool_name.to_string(),
-        }
-    }
-
     // Generate system prompt for LLM with available tools
     pub fn generate_system_prompt(&self) -> String {
+        self.generate_system_prompt_with(&self.prompt_examples())
+    }
+
+    // The system prompt with only the given examples, for prompts that must fit a small context
+    pub fn generate_system_prompt_with(&self, examples: &[String]) -> String {
         let mut prompt = String::from(
             "You are an autonomous agent. Parse user requests and output JSON action plans.\n\n"
         );
@@ -399,15 +398,43 @@ impl Planner {
         prompt.push_str("\nOutput format: {\"tool\": \"tool_name\", \"args\": [\"arg1\", \"arg2\"], \"reasoning\": \"explanation\"}\n");
         prompt.push_str("For multiple actions: [{\"tool\": \"tool1\", \"args\": [...]}, {\"tool\": \"tool2\", \"args\": [...]}]\n\n");
 
-        prompt.push_str("Examples:\n");
-        for tool in self.available_tools.values() {
-            for example in &tool.examples {
+        if !examples.is_empty() {
+            prompt.push_str("Examples:\n");
+            for example in examples {
                 prompt.push_str(&format!("- {}\n", example));
             }
         }
 
         prompt
     }
+
+    // Examples of every registered tool, in system prompt order
+    pub fn prompt_examples(&self) -> Vec<String> {
+        self.available_tools.values().flat_map(|tool| tool.examples.iter().cloned()).collect()
+    }
+
+    // JSON schema for the planner output format: one action or a list of them, naming
+    // only registered tools. Passed to the LLM so constrained decoding emits valid plans.
+    pub fn action_schema(&self) -> Option<serde_json::Value> {
+        if self.available_tools.is_empty() {
+            return None;
+        }
+        let mut tools: Vec<&String> = self.available_tools.keys().collect();
+        tools.sort();
+
+        let action = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "tool": {"enum": tools},
+                "args": {"type": "array", "items": {"type": "string"}},
+                "reasoning": {"type": "string"}
+            },
+            "required": ["tool", "args"]
+        });
+        Some(serde_json::json!({
+            "anyOf": [action, {"type": "array", "items": action, "minItems": 1}]
+        }))
+    }
 }
 
 impl Default for Planner {
@@ -471,6 +498,9 @@ mod tests {
         assert_eq!(plan.actions.len(), 1);
         assert_eq!(plan.actions[0].tool, "math");
         assert_eq!(plan.actions[0].args[0], "2+2");
+
+        let error = planner.parse_llm_response("The answer is four").unwrap_err();
+        assert_eq!(Error::classify(&error, ErrorKind::Internal).kind(), ErrorKind::PlanParseFailed);
     }
 
     #[test]
@@ -494,4 +524,20 @@ mod tests {
         assert!(prompt.contains("shell"));
         assert!(prompt.contains("JSON"));
     }
+
+    #[test]
+    fn test_action_schema_constrains_plans() {
+        let planner = Planner::default();
+        let grammar = tinyedgellmagents_core::Grammar::from_json_schema(&planner.action_schema().unwrap()).unwrap();
+
+        let single = r#"{"tool": "math", "args": ["2+2"], "reasoning": "Simple addition"}"#;
+        let multiple = r#"[{"tool": "math", "args": ["1+2"]}, {"tool": "shell", "args": ["ls"]}]"#;
+        assert!(grammar.matches(single) && grammar.matches(multiple));
+        assert!(!grammar.matches(r#"{"tool": "rm", "args": ["-rf"]}"#));
+        assert!(!grammar.matches("The answer is 4"));
+
+        // Whatever the schema admits, the planner can parse
+        assert_eq!(planner.parse_llm_response(multiple).unwrap().actions.len(), 2);
+        assert!(Planner::new().action_schema().is_none());
+    }
 } 
\ No newline at end of file
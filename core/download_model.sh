@@ -2,6 +2,8 @@
 
 set -euo pipefail
 
+# Checksum-verified, resumable downloads into the model cache: tinyedgellmagents models pull <name>
+
 echo "SuperTinyWasmLLM Model Downloader"
 echo "================================="
 
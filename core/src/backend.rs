@@ -0,0 +1,536 @@
+use crate::embedding::{hash_embedding, Pooling, HASH_EMBEDDING_DIMS};
+use crate::gguf::GgufFile;
+use crate::grammar::Grammar;
+use crate::lora::{LoraAdapter, LoraInfo};
+use crate::model_data::ModelData;
+use crate::native::{GenerateOptions, NativeModel};
+use crate::prompt_cache::PromptCacheStats;
+use crate::replay::{ReplayBackend, REPLAY_FIXTURE_ENV_VAR};
+use crate::sampling::{SamplingParams, TokenLogprob};
+use crate::{FinishReason, InferenceRequest, DEFAULT_MAX_TOKENS};
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(target_family = "wasm")]
+use crate::grammar::json_schema_to_gbnf;
+#[cfg(target_family = "wasm")]
+use wasi_nn::{ExecutionTarget, GraphBuilder, GraphEncoding, TensorType};
+
+pub const BACKEND_ENV_VAR: &str = "SUPERTINYWASMLLM_BACKEND";
+
+// A model file that has been opened and had its GGUF header parsed
+pub struct ModelFile {
+    pub path: String,
+    pub gguf: GgufFile,
+    pub data: Arc<ModelData>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BackendOutput {
+    pub text: String,
+    // Exact completion length when the backend tokenized it itself; otherwise
+    // the engine counts `text` with the model tokenizer
+    pub tokens_generated: Option<u32>,
+    // Prompt tokens whose KV state came from the prompt cache
+    pub cached_prompt_tokens: u32,
+    pub finish_reason: FinishReason,
+    // Time spent processing the prompt, when the backend measures it separately
+    pub prompt_eval: Option<Duration>,
+    // Per-token log probabilities, from backends that see the model's distribution
+    pub logprobs: Option<Vec<TokenLogprob>>,
+}
+
+// Something that can turn a prompt into a completion. Embedders can implement
+// this and hand it to `SuperTinyWasmLLM::with_backend`.
+pub trait InferenceBackend: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Called by `load_model` once the model file has been parsed, if there is one
+    fn load(&mut self, _model: &ModelFile) -> Result<()> {
+        Ok(())
+    }
+
+    fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput>;
+
+    // Called before generating; rejects request options the backend cannot honour
+    fn check_request(&self, _request: &InferenceRequest) -> Result<()> {
+        Ok(())
+    }
+
+    // Like `generate`, handing each piece of text to `on_token` as it is produced.
+    // Backends that cannot stream deliver the whole completion as one piece.
+    fn generate_stream(&self, request: &InferenceRequest, on_token: &mut dyn FnMut(&str)) -> Result<BackendOutput> {
+        let output = self.generate(request)?;
+        on_token(&output.text);
+        Ok(output)
+    }
+
+    // One vector per text, pooled from the model's hidden states
+    fn embed(&self, _texts: &[String], _pooling: Pooling) -> Result<Vec<Vec<f32>>> {
+        Err(anyhow!("The {} backend cannot compute embeddings", self.name()))
+    }
+
+    // Prompt cache counters, for backends that reuse the KV state of earlier prompts
+    fn prompt_cache_stats(&self) -> Option<PromptCacheStats> {
+        None
+    }
+
+    // Called with every prompt of a batch before its items are generated, so backends
+    // that cache prompt state can process what the prompts share once
+    fn prepare_batch(&self, _prompts: &[&str]) {}
+
+    // Called after `load` with the engine's LoRA adapters, if it has any
+    fn load_lora(&mut self, _adapters: Vec<LoraAdapter>) -> Result<()> {
+        Err(anyhow!("The {} backend cannot apply LoRA adapters", self.name()))
+    }
+
+    // Adapters requests can select
+    fn lora_adapters(&self) -> Vec<LoraInfo> {
+        Vec::new()
+    }
+}
+
+// Fail if `request` sets a sampling option outside `supported`, for backends that sample
+// but would ignore it. Temperature, seed, stop and max_tokens are always accepted: the
+// engine applies stop sequences and the token budget. Backends with fixed output accept
+// every option, as none of them could change their text.
+pub(crate) fn check_sampling(backend: &str, request: &InferenceRequest, supported: &[&str]) -> Result<()> {
+    let set = [
+        ("top_k", request.top_k.is_some()),
+        ("top_p", request.top_p.is_some()),
+        ("min_p", request.min_p.is_some()),
+        ("repeat_penalty", request.repeat_penalty.is_some()),
+        ("frequency_penalty", request.frequency_penalty.is_some()),
+        ("presence_penalty", request.presence_penalty.is_some()),
+    ];
+    let unsupported: Vec<&str> =
+        set.iter().filter(|(name, set)| *set && !supported.contains(name)).map(|(name, _)| *name).collect();
+    if unsupported.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!("The {} backend does not support {}", backend, unsupported.join(", ")))
+}
+
+// Embeddings for backends that have no model to take them from
+pub fn hashed_embeddings(texts: &[String]) -> Vec<Vec<f32>> {
+    texts.iter().map(|text| hash_embedding(text, HASH_EMBEDDING_DIMS)).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendKind {
+    #[default]
+    Auto,
+    WasiNn,
+    Native,
+    Simulation,
+    Demo,
+    Replay,
+}
+
+impl BackendKind {
+    // Read the backend choice from SUPERTINYWASMLLM_BACKEND, defaulting to auto
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(BACKEND_ENV_VAR) {
+            Ok(value) if !value.trim().is_empty() => value.parse(),
+            _ => Ok(BackendKind::Auto),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Auto => "auto",
+            BackendKind::WasiNn => "wasi-nn",
+            BackendKind::Native => "native",
+            BackendKind::Simulation => "simulation",
+            BackendKind::Demo => "demo",
+            BackendKind::Replay => "replay",
+        }
+    }
+
+    // Build a backend for this kind; `Auto` picks WASI-NN on wasm and the
+    // native CPU backend elsewhere, falling back to simulation without a usable model
+    pub fn create(&self, model: Option<&ModelFile>) -> Result<Box<dyn InferenceBackend>> {
+        let mut backend: Box<dyn InferenceBackend> = match self {
+            BackendKind::WasiNn => Box::new(WasiNnBackend),
+            BackendKind::Native => Box::new(NativeBackend::default()),
+            BackendKind::Simulation => return Ok(Box::new(SimulationBackend)),
+            BackendKind::Demo => return Ok(Box::new(DemoBackend)),
+            BackendKind::Replay => {
+                let path = std::env::var(REPLAY_FIXTURE_ENV_VAR)
+                    .map_err(|_| anyhow!("The replay backend needs {} set to a fixture file", REPLAY_FIXTURE_ENV_VAR))?;
+                return Ok(Box::new(ReplayBackend::from_file(path)?));
+            }
+            BackendKind::Auto => {
+                if cfg!(target_family = "wasm") {
+                    return BackendKind::WasiNn.create(model);
+                }
+                let Some(model) = model else {
+                    return Ok(Box::new(SimulationBackend));
+                };
+                let mut native = NativeBackend::default();
+                return match native.load(model) {
+                    Ok(()) => Ok(Box::new(native)),
+                    Err(e) => {
+                        log::warn!("Native CPU backend unavailable: {}, using native simulation mode", e);
+                        Ok(Box::new(SimulationBackend))
+                    }
+                };
+            }
+        };
+
+        match model {
+            Some(model) => backend.load(model)?,
+            None if *self == BackendKind::Native => {
+                return Err(anyhow!("The native backend needs a model file"));
+            }
+            None => {}
+        }
+        Ok(backend)
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(BackendKind::Auto),
+            "wasi-nn" | "wasinn" | "wasi_nn" => Ok(BackendKind::WasiNn),
+            "native" | "cpu" => Ok(BackendKind::Native),
+            "simulation" | "sim" => Ok(BackendKind::Simulation),
+            "demo" => Ok(BackendKind::Demo),
+            "replay" => Ok(BackendKind::Replay),
+            other => Err(anyhow!(
+                "Unknown backend '{}' (expected auto, wasi-nn, native, simulation, demo or replay)",
+                other
+            )),
+        }
+    }
+}
+
+// WASI-NN graph preloaded by the host runtime (e.g. WasmEdge --nn-preload default:GGML:AUTO:model.gguf)
+pub struct WasiNnBackend;
+
+impl InferenceBackend for WasiNnBackend {
+    fn name(&self) -> &str {
+        "wasi-nn"
+    }
+
+    fn check_request(&self, request: &InferenceRequest) -> Result<()> {
+        check_sampling(self.name(), request, &["top_p", "repeat_penalty", "frequency_penalty", "presence_penalty"])
+    }
+
+    #[cfg(target_family = "wasm")]
+    fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput> {
+        // WASI-NN graph initialization using autodetect backend
+        let graph = GraphBuilder::new(GraphEncoding::Autodetec, ExecutionTarget::AUTO)
+            .build_from_cache("default")?;
+
+        let mut context = graph.init_execution_context()?;
+
+        // Sampling options go to the GGML plugin as JSON metadata on input 1; it has no
+        // top-k or min-p options (`check_request` rejects them), and stop sequences are
+        // applied by the engine
+        let params = SamplingParams::from_request(request);
+        let mut metadata = serde_json::json!({
+            "n-predict": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "temp": params.temperature,
+            "top-p": params.top_p,
+            "repeat-penalty": params.repeat_penalty,
+            "frequency-penalty": params.frequency_penalty,
+            "presence-penalty": params.presence_penalty,
+            "seed": params.seed,
+        });
+        // The plugin takes GBNF, so JSON schemas are converted here
+        let grammar = match (&request.grammar, &request.json_schema) {
+            (Some(grammar), _) => Some(grammar.clone()),
+            (None, Some(schema)) => Some(json_schema_to_gbnf(schema)?),
+            (None, None) => None,
+        };
+        if let Some(grammar) = grammar {
+            metadata["grammar"] = serde_json::Value::String(grammar);
+        }
+        let metadata = metadata.to_string();
+        context.set_input(1, TensorType::U8, &[1], metadata.as_bytes())?;
+
+        // Prepare input prompt
+        let prompt = &request.prompt;
+        let tensor_data = prompt.as_bytes().to_vec();
+
+        // Set input tensor with dimensions [1]
+        context.set_input(0, TensorType::U8, &[1], &tensor_data)?;
+
+        // Execute inference
+        context.compute()?;
+
+        // Get output with larger buffer for safety
+        let max_tokens = request.max_tokens.unwrap_or(100);
+        let mut output_buffer = vec![0u8; (max_tokens * 10) as usize];
+        let output_size = context.get_output(0, &mut output_buffer)?;
+
+        // Bounds check
+        let safe_output_size = output_size.min(output_buffer.len());
+
+        // Convert output to text
+        let response_text = String::from_utf8_lossy(&output_buffer[..safe_output_size]).to_string();
+
+        // Clean and trim response
+        let text = response_text.trim().to_string();
+
+        Ok(BackendOutput {
+            text,
+            tokens_generated: None,
+            cached_prompt_tokens: 0,
+            finish_reason: FinishReason::Stop,
+            prompt_eval: None,
+            logprobs: None,
+        })
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    fn generate(&self, _request: &InferenceRequest) -> Result<BackendOutput> {
+        Err(anyhow!("WASI-NN is only available when running as wasm under a WASI-NN runtime"))
+    }
+}
+
+// Pure-Rust CPU inference over the loaded GGUF weights
+#[derive(Default)]
+pub struct NativeBackend {
+    model: Option<NativeModel>,
+}
+
+impl InferenceBackend for NativeBackend {
+    fn name(&self) -> &str {
+        "native"
+    }
+
+    fn load(&mut self, model: &ModelFile) -> Result<()> {
+        let native = NativeModel::load(&model.gguf, Arc::clone(&model.data))?;
+        log::info!(
+            "Native CPU backend ready ({} layers, {} vocab)",
+            native.config().n_layer,
+            native.config().n_vocab
+        );
+        self.model = Some(native);
+        Ok(())
+    }
+
+    fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput> {
+        self.generate_stream(request, &mut |_| {})
+    }
+
+    fn generate_stream(&self, request: &InferenceRequest, on_token: &mut dyn FnMut(&str)) -> Result<BackendOutput> {
+        let model = self.model.as_ref().ok_or_else(|| anyhow!("Native backend has no model loaded"))?;
+        let output = model.generate_stream(
+            &request.prompt,
+            request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            &SamplingParams::from_request(request),
+            &GenerateOptions {
+                grammar: Grammar::from_request(request)?.as_ref(),
+                interrupt: Some(&|| request.interrupted()),
+                logprobs: request.logprobs,
+                lora: request.lora.as_deref(),
+            },
+            on_token,
+        )?;
+
+        Ok(BackendOutput {
+            // As streamed, so both forms of a response agree
+            text: output.text,
+            tokens_generated: Some(output.completion_tokens as u32),
+            cached_prompt_tokens: output.cached_tokens as u32,
+            finish_reason: output.finish_reason,
+            prompt_eval: Some(output.prompt_eval),
+            logprobs: output.logprobs,
+        })
+    }
+
+    fn embed(&self, texts: &[String], pooling: Pooling) -> Result<Vec<Vec<f32>>> {
+        let model = self.model.as_ref().ok_or_else(|| anyhow!("Native backend has no model loaded"))?;
+        texts.iter().map(|text| model.embed(text, pooling)).collect()
+    }
+
+    fn prompt_cache_stats(&self) -> Option<PromptCacheStats> {
+        self.model.as_ref().map(NativeModel::prompt_cache_stats)
+    }
+
+    fn load_lora(&mut self, adapters: Vec<LoraAdapter>) -> Result<()> {
+        let model = self.model.as_mut().ok_or_else(|| anyhow!("Native backend has no model loaded"))?;
+        adapters.into_iter().try_for_each(|adapter| model.add_adapter(adapter))
+    }
+
+    fn lora_adapters(&self) -> Vec<LoraInfo> {
+        self.model.as_ref().map(NativeModel::adapters).unwrap_or_default()
+    }
+
+    fn prepare_batch(&self, prompts: &[&str]) {
+        if let Some(model) = &self.model {
+            model.cache_shared_prefix(prompts);
+        }
+    }
+}
+
+// Keyword-driven stand-in for a model, producing tool calls for the agent planner
+pub struct SimulationBackend;
+
+impl InferenceBackend for SimulationBackend {
+    fn name(&self) -> &str {
+        "simulation"
+    }
+
+    fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput> {
+        Ok(BackendOutput {
+            text: simulate_response(&request.prompt),
+            tokens_generated: None,
+            cached_prompt_tokens: 0,
+            finish_reason: FinishReason::Stop,
+            prompt_eval: None,
+            logprobs: None,
+        })
+    }
+
+    fn embed(&self, texts: &[String], _pooling: Pooling) -> Result<Vec<Vec<f32>>> {
+        Ok(hashed_embeddings(texts))
+    }
+}
+
+fn simulate_response(original_prompt: &str) -> String {
+    // Native mode: simulate intelligent response based on prompt analysis
+    let prompt = &original_prompt.to_lowercase();
+
+    // Extract available tools from prompt (system prompt includes tool list)
+    let math_tool = if prompt.contains("math-native") {
+        "math-native"
+    } else if prompt.contains("- math:") {
+        "math"
+    } else {
+        "math-native" // Default to math-native if available
+    };
+
+    let fetch_tool = if prompt.contains("fetch-native") {
+        "fetch-native"
+    } else {
+        "fetch"
+    };
+
+    let shell_tool = if prompt.contains("shell-native") {
+        "shell-native"
+    } else {
+        "shell"
+    };
+
+    if prompt.contains("2+2") || prompt.contains("2 + 2") {
+        format!(r#"{{"tool": "{}", "args": ["2+2"], "reasoning": "Simple addition calculation"}}"#, math_tool)
+    } else if prompt.contains("5*7") || prompt.contains("5 * 7") {
+        format!(r#"{{"tool": "{}", "args": ["5*7"], "reasoning": "Multiplication calculation"}}"#, math_tool)
+    } else if prompt.contains("4*5") || prompt.contains("4 * 5") {
+        format!(r#"{{"tool": "{}", "args": ["4*5"], "reasoning": "Multiplication calculation"}}"#, math_tool)
+    } else if prompt.contains("3*7") || prompt.contains("3 * 7") {
+        format!(r#"{{"tool": "{}", "args": ["3*7"], "reasoning": "Multiplication calculation"}}"#, math_tool)
+    } else if prompt.contains("math") && (prompt.contains("+") || prompt.contains("*") || prompt.contains("-") || prompt.contains("/")) {
+        // Extract simple math expression from user task
+        if let Some(task_start) = prompt.find("current_task:") {
+            let task_part = &prompt[task_start..];
+            if let Some(task_line) = task_part.lines().next() {
+                let task = task_line.replace("current_task:", "").trim().to_string();
+                if task.chars().any(|c| "+-*/".contains(c)) && task.len() < 20 {
+                    return format!(r#"{{"tool": "{}", "args": ["{}"], "reasoning": "Detected math expression"}}"#, math_tool, task);
+                }
+            }
+        }
+
+        // Fallback: extract from user query
+        if let Some(user_start) = prompt.rfind("user:") {
+            let user_part = &prompt[user_start + 5..].trim();
+            if let Some(user_line) = user_part.lines().next() {
+                let user_query = user_line.trim();
+                if user_query.chars().any(|c| "+-*/".contains(c)) && user_query.len() < 20 {
+                    return format!(r#"{{"tool": "{}", "args": ["{}"], "reasoning": "Math operation requested"}}"#, math_tool, user_query);
+                }
+            }
+        }
+
+        format!(r#"{{"tool": "{}", "args": ["calculation"], "reasoning": "Math operation requested"}}"#, math_tool)
+    } else if prompt.contains("http") {
+        format!(r#"{{"tool": "{}", "args": ["get", "http://example.com"], "reasoning": "HTTP request detected"}}"#, fetch_tool)
+    } else if prompt.contains("list") || prompt.contains("files") {
+        format!(r#"{{"tool": "{}", "args": ["ls", "-la"], "reasoning": "File listing requested"}}"#, shell_tool)
+    } else {
+        // Generic response
+        format!("I understand you want to: {}. Let me help with that.", original_prompt)
+    }
+}
+
+// Echoes the prompt back; used when the selected backend fails
+pub struct DemoBackend;
+
+impl InferenceBackend for DemoBackend {
+    fn name(&self) -> &str {
+        "demo"
+    }
+
+    fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput> {
+        let text = format!("{} [Demo mode: max_tokens={}, temperature={}]",
+                           request.prompt,
+                           request.max_tokens.unwrap_or(50),
+                           request.temperature.unwrap_or(0.7));
+        Ok(BackendOutput {
+            text,
+            tokens_generated: None,
+            cached_prompt_tokens: 0,
+            finish_reason: FinishReason::Stop,
+            prompt_eval: None,
+            logprobs: None,
+        })
+    }
+
+    fn embed(&self, texts: &[String], _pooling: Pooling) -> Result<Vec<Vec<f32>>> {
+        Ok(hashed_embeddings(texts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(prompt: &str) -> InferenceRequest {
+        InferenceRequest {
+            prompt: prompt.to_string(),
+            max_tokens: Some(10),
+            temperature: Some(0.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backend_kind_parsing() {
+        assert_eq!("native".parse::<BackendKind>().unwrap(), BackendKind::Native);
+        assert_eq!("WASI-NN".parse::<BackendKind>().unwrap(), BackendKind::WasiNn);
+        assert_eq!(" sim ".parse::<BackendKind>().unwrap(), BackendKind::Simulation);
+        assert!("gpu".parse::<BackendKind>().is_err());
+    }
+
+    #[test]
+    fn test_auto_without_model_uses_simulation() {
+        let backend = BackendKind::Auto.create(None).unwrap();
+        assert_eq!(backend.name(), "simulation");
+
+        let output = backend.generate(&request("What is 2+2? - math-native: calc")).unwrap();
+        assert!(output.text.contains("\"math-native\""));
+        assert!(output.text.contains("2+2"));
+    }
+
+    #[test]
+    fn test_native_requires_model() {
+        assert!(BackendKind::Native.create(None).is_err());
+    }
+
+    #[test]
+    fn test_demo_backend_echoes_prompt() {
+        let backend = BackendKind::Demo.create(None).unwrap();
+        let output = backend.generate(&request("hello")).unwrap();
+        assert!(output.text.starts_with("hello [Demo mode"));
+    }
+}
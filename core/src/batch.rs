@@ -0,0 +1,91 @@
+use crate::{Error, ErrorKind, ErrorResponse, InferenceRequest, InferenceResponse};
+use serde::Serialize;
+use serde_json::Value;
+
+// Several prompts answered in one request: a JSON array of requests, e.g.
+// [{"prompt": "a"}, {"prompt": "b", "max_tokens": 5}], or one request with a "prompts"
+// list whose other fields apply to every prompt, e.g. {"prompts": ["a", "b"], "seed": 1}
+#[derive(Debug)]
+pub struct BatchRequest {
+    pub id: Option<String>,
+    // One entry per item, in order; items that could not be parsed carry their error
+    pub items: Vec<Result<InferenceRequest, Error>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // One entry per item, in request order
+    pub results: Vec<BatchItem>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BatchItem {
+    Response(Box<InferenceResponse>),
+    Error(ErrorResponse),
+}
+
+impl BatchRequest {
+    // Whether a request line asks for a batch rather than a single completion
+    pub fn is_batch(value: &Value) -> bool {
+        value.is_array() || value.get("prompts").is_some()
+    }
+
+    pub fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Array(items) => Ok(Self { id: None, items: items.into_iter().map(parse_item).collect() }),
+            Value::Object(mut shared) => {
+                let id = match shared.remove("id") {
+                    Some(Value::String(id)) => Some(id),
+                    Some(Value::Null) | None => None,
+                    Some(_) => return Err(Error::new(ErrorKind::InvalidRequest, "Batch id must be a string")),
+                };
+                let Some(Value::Array(prompts)) = shared.remove("prompts") else {
+                    return Err(Error::new(ErrorKind::InvalidRequest, "prompts must be a list of strings"));
+                };
+                let items = prompts
+                    .into_iter()
+                    .map(|prompt| {
+                        let mut item = shared.clone();
+                        item.insert("prompt".to_string(), prompt);
+                        parse_item(Value::Object(item))
+                    })
+                    .collect();
+                Ok(Self { id, items })
+            }
+            _ => Err(Error::new(ErrorKind::InvalidRequest, "A batch is a list of requests or an object with prompts")),
+        }
+    }
+}
+
+fn parse_item(value: Value) -> Result<InferenceRequest, Error> {
+    let request: InferenceRequest = serde_json::from_value(value)
+        .map_err(|e| Error::new(ErrorKind::InvalidRequest, format!("Failed to parse request: {}", e)))?;
+    if request.stream {
+        return Err(Error::new(ErrorKind::InvalidRequest, "Streaming is not supported in batches"));
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_forms_and_item_errors() {
+        let shared = BatchRequest::from_value(serde_json::json!({"id": "b", "prompts": ["x", "y"], "seed": 7})).unwrap();
+        assert_eq!(shared.id.as_deref(), Some("b"));
+        let prompts: Vec<_> = shared.items.iter().map(|item| item.as_ref().unwrap().prompt.as_str()).collect();
+        assert_eq!(prompts, ["x", "y"]);
+        assert!(shared.items.iter().all(|item| item.as_ref().unwrap().seed == Some(7)));
+
+        let listed = BatchRequest::from_value(serde_json::json!([{"prompt": "x"}, {"prompt": 5}, {"prompt": "z", "stream": true}])).unwrap();
+        let kinds: Vec<_> = listed.items.iter().map(|item| item.as_ref().err().map(Error::kind)).collect();
+        assert_eq!(kinds, [None, Some(ErrorKind::InvalidRequest), Some(ErrorKind::InvalidRequest)]);
+
+        assert!(BatchRequest::is_batch(&serde_json::json!([])) && !BatchRequest::is_batch(&serde_json::json!({"prompt": "x"})));
+        assert!(BatchRequest::from_value(serde_json::json!({"prompts": "x"})).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const CHAT_TEMPLATE_ENV_VAR: &str = "SUPERTINYWASMLLM_CHAT_TEMPLATE";
+
+// GGUF metadata key holding the model's Jinja chat template
+pub const CHAT_TEMPLATE_METADATA_KEY: &str = "tokenizer.chat_template";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    // "system", "user" or "assistant"
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+// Prompt layouts of common chat fine-tunes. The BOS token is left out of every
+// layout because the tokenizer adds it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChatTemplate {
+    // No markup: "role: content" blocks, for base models and non-tokenizing backends
+    #[default]
+    Plain,
+    // <|im_start|>role ... <|im_end|> (Qwen, many fine-tunes)
+    ChatMl,
+    // [INST] <<SYS>> ... [/INST] (Llama 2 chat, Mistral instruct)
+    Llama2,
+    // <|start_header_id|>role<|end_header_id|> ... <|eot_id|>
+    Llama3,
+    // <|role|> ... </s> (Zephyr, TinyLlama chat)
+    Zephyr,
+    // <|role|> ... <|end|>
+    Phi3,
+    // <start_of_turn>user|model ... <end_of_turn>
+    Gemma,
+}
+
+impl ChatTemplate {
+    // Template named by SUPERTINYWASMLLM_CHAT_TEMPLATE, if set
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(CHAT_TEMPLATE_ENV_VAR) {
+            Ok(value) if !value.trim().is_empty() => value.parse().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    // Recognize a Jinja template from GGUF metadata by its distinctive markers
+    pub fn detect(jinja: &str) -> Option<Self> {
+        if jinja.contains("<|im_start|>") {
+            Some(ChatTemplate::ChatMl)
+        } else if jinja.contains("<|start_header_id|>") {
+            Some(ChatTemplate::Llama3)
+        } else if jinja.contains("<start_of_turn>") {
+            Some(ChatTemplate::Gemma)
+        } else if jinja.contains("<|user|>") && jinja.contains("<|end|>") {
+            Some(ChatTemplate::Phi3)
+        } else if jinja.contains("<|user|>") {
+            Some(ChatTemplate::Zephyr)
+        } else if jinja.contains("[INST]") {
+            Some(ChatTemplate::Llama2)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatTemplate::Plain => "plain",
+            ChatTemplate::ChatMl => "chatml",
+            ChatTemplate::Llama2 => "llama2",
+            ChatTemplate::Llama3 => "llama3",
+            ChatTemplate::Zephyr => "zephyr",
+            ChatTemplate::Phi3 => "phi3",
+            ChatTemplate::Gemma => "gemma",
+        }
+    }
+
+    // End-of-turn markers; generation should stop on them
+    pub fn stop_sequences(&self) -> Vec<String> {
+        let stops: &[&str] = match self {
+            ChatTemplate::Plain => &[],
+            ChatTemplate::ChatMl => &["<|im_end|>"],
+            ChatTemplate::Llama2 | ChatTemplate::Zephyr => &["</s>"],
+            ChatTemplate::Llama3 => &["<|eot_id|>"],
+            ChatTemplate::Phi3 => &["<|end|>"],
+            ChatTemplate::Gemma => &["<end_of_turn>"],
+        };
+        stops.iter().map(|s| s.to_string()).collect()
+    }
+
+    // Render a conversation; with `add_generation_prompt` the result ends where the
+    // assistant's next reply starts
+    pub fn render(&self, messages: &[ChatMessage], add_generation_prompt: bool) -> String {
+        let mut out = String::new();
+        match self {
+            ChatTemplate::Plain => {
+                let blocks: Vec<String> = messages
+                    .iter()
+                    .map(|m| match m.role.as_str() {
+                        "system" => m.content.clone(),
+                        role => format!("{}: {}", role, m.content),
+                    })
+                    .collect();
+                out = blocks.join("\n\n");
+            }
+            ChatTemplate::ChatMl => {
+                for m in messages {
+                    out.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", m.role, m.content));
+                }
+                if add_generation_prompt {
+                    out.push_str("<|im_start|>assistant\n");
+                }
+            }
+            ChatTemplate::Llama2 => {
+                // The system prompt lives inside the first [INST] block
+                let (system, turns) = split_system(messages);
+                let mut system = system.map(|s| format!("<<SYS>>\n{}\n<</SYS>>\n\n", s));
+                for (i, m) in turns.iter().enumerate() {
+                    if m.role == "assistant" {
+                        out.push_str(&format!(" {} </s>", m.content));
+                    } else {
+                        if i > 0 {
+                            out.push_str("<s>");
+                        }
+                        out.push_str(&format!("[INST] {}{} [/INST]", system.take().unwrap_or_default(), m.content));
+                    }
+                }
+            }
+            ChatTemplate::Llama3 => {
+                for m in messages {
+                    out.push_str(&format!("<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>", m.role, m.content));
+                }
+                if add_generation_prompt {
+                    out.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+                }
+            }
+            ChatTemplate::Zephyr | ChatTemplate::Phi3 => {
+                let end = if *self == ChatTemplate::Zephyr { "</s>" } else { "<|end|>" };
+                for m in messages {
+                    out.push_str(&format!("<|{}|>\n{}{}\n", m.role, m.content, end));
+                }
+                if add_generation_prompt {
+                    out.push_str("<|assistant|>\n");
+                }
+            }
+            ChatTemplate::Gemma => {
+                // No system role; it is prepended to the first user turn
+                let (system, turns) = split_system(messages);
+                let mut system = system.map(|s| format!("{}\n\n", s));
+                for m in turns {
+                    let role = if m.role == "assistant" { "model" } else { "user" };
+                    let prefix = if role == "user" { system.take().unwrap_or_default() } else { String::new() };
+                    out.push_str(&format!("<start_of_turn>{}\n{}{}<end_of_turn>\n", role, prefix, m.content));
+                }
+                if add_generation_prompt {
+                    out.push_str("<start_of_turn>model\n");
+                }
+            }
+        }
+        out
+    }
+}
+
+// Merge system messages into one prompt and return the remaining turns
+fn split_system(messages: &[ChatMessage]) -> (Option<String>, Vec<&ChatMessage>) {
+    let system: Vec<&str> = messages.iter().filter(|m| m.role == "system").map(|m| m.content.as_str()).collect();
+    let turns = messages.iter().filter(|m| m.role != "system").collect();
+    ((!system.is_empty()).then(|| system.join("\n\n")), turns)
+}
+
+impl FromStr for ChatTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "plain" | "none" | "raw" => Ok(ChatTemplate::Plain),
+            "chatml" => Ok(ChatTemplate::ChatMl),
+            "llama2" | "llama-2" | "mistral" => Ok(ChatTemplate::Llama2),
+            "llama3" | "llama-3" => Ok(ChatTemplate::Llama3),
+            "zephyr" | "tinyllama" => Ok(ChatTemplate::Zephyr),
+            "phi3" | "phi-3" | "phi" => Ok(ChatTemplate::Phi3),
+            "gemma" => Ok(ChatTemplate::Gemma),
+            other => Err(anyhow!(
+                "Unknown chat template '{}' (expected plain, chatml, llama2, llama3, zephyr, phi3 or gemma)",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::new("system", "Be brief."),
+            ChatMessage::new("user", "Hi"),
+            ChatMessage::new("assistant", "Hello!"),
+            ChatMessage::new("user", "2+2?"),
+        ]
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let messages = conversation();
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&messages, true),
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\nHello!<|im_end|>\n<|im_start|>user\n2+2?<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Llama2.render(&messages, true),
+            "[INST] <<SYS>>\nBe brief.\n<</SYS>>\n\nHi [/INST] Hello! </s><s>[INST] 2+2? [/INST]"
+        );
+        assert_eq!(
+            ChatTemplate::Zephyr.render(&messages[..2], true),
+            "<|system|>\nBe brief.</s>\n<|user|>\nHi</s>\n<|assistant|>\n"
+        );
+        assert_eq!(
+            ChatTemplate::Gemma.render(&messages[..2], true),
+            "<start_of_turn>user\nBe brief.\n\nHi<end_of_turn>\n<start_of_turn>model\n"
+        );
+        assert_eq!(ChatTemplate::Plain.render(&messages[..2], true), "Be brief.\n\nuser: Hi");
+    }
+
+    #[test]
+    fn test_detect_and_parse() {
+        let tinyllama = "{% for message in messages %}{% if message['role'] == 'user' %}{{ '<|user|>\n' + message['content'] + eos_token }}{% endif %}{% endfor %}";
+        assert_eq!(ChatTemplate::detect(tinyllama), Some(ChatTemplate::Zephyr));
+        assert_eq!(ChatTemplate::detect("{{ '<|im_start|>' + message['role'] }}"), Some(ChatTemplate::ChatMl));
+        assert_eq!(ChatTemplate::detect("{{ bos_token + '[INST] ' + content }}"), Some(ChatTemplate::Llama2));
+        assert_eq!(ChatTemplate::detect("{{ messages }}"), None);
+
+        assert_eq!("TinyLlama".parse::<ChatTemplate>().unwrap(), ChatTemplate::Zephyr);
+        assert!("vicuna".parse::<ChatTemplate>().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Width of the hashed vectors backends without a model produce
+pub const HASH_EMBEDDING_DIMS: usize = 256;
+
+// How per-token hidden states are combined into one vector per text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    // Average over all tokens; suits generative models used as encoders
+    #[default]
+    Mean,
+    // State of the final token, as embedding models trained with last-token pooling expect
+    Last,
+}
+
+// Texts to embed, e.g. {"input": ["first text", "second text"]} or {"input": "one text"}
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EmbeddingRequest {
+    pub id: Option<String>,
+    // Registry model to route the request to; the default model if unset
+    pub model: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub input: Vec<String>,
+    #[serde(default)]
+    pub pooling: Pooling,
+    // Scale every vector to unit length, so dot products are cosine similarities
+    #[serde(default = "default_normalize")]
+    pub normalize: bool,
+}
+
+fn default_normalize() -> bool {
+    true
+}
+
+impl EmbeddingRequest {
+    pub fn new(input: Vec<String>) -> Self {
+        Self { input, normalize: true, ..Default::default() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // One vector per input text, in input order
+    pub embeddings: Vec<Vec<f32>>,
+    pub dimensions: usize,
+    pub prompt_tokens: u32,
+    pub model_info: String,
+    pub backend: String,
+}
+
+// Signed feature hashing of words and character trigrams: no model needed, and texts
+// sharing vocabulary land near each other. Deterministic across runs and platforms.
+pub fn hash_embedding(text: &str, dims: usize) -> Vec<f32> {
+    let mut vector = vec![0.0; dims];
+    let mut add = |feature: &str, weight: f32| {
+        let hash = fnv1a(feature.as_bytes());
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % dims as u64) as usize] += sign * weight;
+    };
+
+    let text = text.to_lowercase();
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+        add(word, 1.0);
+        let chars: Vec<char> = format!(" {} ", word).chars().collect();
+        for trigram in chars.windows(3) {
+            add(&trigram.iter().collect::<String>(), 0.5);
+        }
+    }
+    normalize(&mut vector);
+    vector
+}
+
+// FNV-1a, fixed so vectors stay comparable across builds
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+// Scale to unit length; the zero vector stays as it is
+pub fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms = a.iter().map(|v| v * v).sum::<f32>().sqrt() * b.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norms > 0.0 {
+        dot / norms
+    } else {
+        0.0
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(text) => vec![text],
+        OneOrMany::Many(texts) => texts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_embedding_is_stable_and_similarity_aware() {
+        let a = hash_embedding("Calculate the square root of 16", HASH_EMBEDDING_DIMS);
+        assert_eq!(a, hash_embedding("calculate the SQUARE root of 16", HASH_EMBEDDING_DIMS));
+        assert!((a.iter().map(|v| v * v).sum::<f32>() - 1.0).abs() < 1e-5);
+
+        let related = hash_embedding("square root calculation", HASH_EMBEDDING_DIMS);
+        let unrelated = hash_embedding("fetch the weather page over http", HASH_EMBEDDING_DIMS);
+        assert!(cosine_similarity(&a, &related) > cosine_similarity(&a, &unrelated));
+        assert!(hash_embedding("", 8).iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn test_request_accepts_one_or_many_texts() {
+        let one: EmbeddingRequest = serde_json::from_str(r#"{"input": "hello"}"#).unwrap();
+        assert_eq!((one.input.len(), one.normalize, one.pooling), (1, true, Pooling::Mean));
+        let many: EmbeddingRequest =
+            serde_json::from_str(r#"{"input": ["a", "b"], "pooling": "last", "normalize": false}"#).unwrap();
+        assert_eq!((many.input.len(), many.normalize, many.pooling), (2, false, Pooling::Last));
+    }
+}
@@ -0,0 +1,264 @@
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+
+// Every failure reported by the engine or the agent. Codes and names are stable: scripts
+// branch on them, so new kinds get new codes and existing ones are never renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    ModelLoadFailed,
+    InputFailed,
+    InvalidRequest,
+    InferenceFailed,
+    InvalidCommand,
+    ModelNotFound,
+    ModelNotLoaded,
+    ContextLengthExceeded,
+    Cancelled,
+    DeadlineExceeded,
+    DownloadFailed,
+    ChecksumMismatch,
+    ToolNotFound,
+    ToolTimeout,
+    ToolDenied,
+    ToolFailed,
+    PlanParseFailed,
+    LowConfidence,
+    Internal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Request,
+    Model,
+    Inference,
+    Tool,
+    Plan,
+    Io,
+    Internal,
+}
+
+impl ErrorKind {
+    // Numeric code in error responses; 1-5 keep the values of the original ad-hoc codes
+    pub fn code(self) -> u32 {
+        match self {
+            ErrorKind::ModelLoadFailed => 1,
+            ErrorKind::InputFailed => 2,
+            ErrorKind::InvalidRequest => 3,
+            ErrorKind::InferenceFailed => 4,
+            ErrorKind::InvalidCommand => 5,
+            ErrorKind::ModelNotFound => 6,
+            ErrorKind::ModelNotLoaded => 7,
+            ErrorKind::ContextLengthExceeded => 8,
+            ErrorKind::Cancelled => 9,
+            ErrorKind::DeadlineExceeded => 10,
+            ErrorKind::DownloadFailed => 11,
+            ErrorKind::ChecksumMismatch => 12,
+            ErrorKind::ToolNotFound => 20,
+            ErrorKind::ToolTimeout => 21,
+            ErrorKind::ToolDenied => 22,
+            ErrorKind::ToolFailed => 23,
+            ErrorKind::PlanParseFailed => 30,
+            ErrorKind::LowConfidence => 31,
+            ErrorKind::Internal => 99,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::ModelLoadFailed => "model_load_failed",
+            ErrorKind::InputFailed => "input_failed",
+            ErrorKind::InvalidRequest => "invalid_request",
+            ErrorKind::InferenceFailed => "inference_failed",
+            ErrorKind::InvalidCommand => "invalid_command",
+            ErrorKind::ModelNotFound => "model_not_found",
+            ErrorKind::ModelNotLoaded => "model_not_loaded",
+            ErrorKind::ContextLengthExceeded => "context_length_exceeded",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::DeadlineExceeded => "deadline_exceeded",
+            ErrorKind::DownloadFailed => "download_failed",
+            ErrorKind::ChecksumMismatch => "checksum_mismatch",
+            ErrorKind::ToolNotFound => "tool_not_found",
+            ErrorKind::ToolTimeout => "tool_timeout",
+            ErrorKind::ToolDenied => "tool_denied",
+            ErrorKind::ToolFailed => "tool_failed",
+            ErrorKind::PlanParseFailed => "plan_parse_failed",
+            ErrorKind::LowConfidence => "low_confidence",
+            ErrorKind::Internal => "internal",
+        }
+    }
+
+    pub fn category(self) -> ErrorCategory {
+        match self {
+            ErrorKind::InvalidRequest
+            | ErrorKind::InvalidCommand
+            | ErrorKind::ContextLengthExceeded
+            | ErrorKind::Cancelled => ErrorCategory::Request,
+            ErrorKind::ModelLoadFailed
+            | ErrorKind::ModelNotFound
+            | ErrorKind::ModelNotLoaded
+            | ErrorKind::DownloadFailed
+            | ErrorKind::ChecksumMismatch => ErrorCategory::Model,
+            ErrorKind::InferenceFailed | ErrorKind::DeadlineExceeded => ErrorCategory::Inference,
+            ErrorKind::ToolNotFound | ErrorKind::ToolTimeout | ErrorKind::ToolDenied | ErrorKind::ToolFailed => {
+                ErrorCategory::Tool
+            }
+            ErrorKind::PlanParseFailed | ErrorKind::LowConfidence => ErrorCategory::Plan,
+            ErrorKind::InputFailed => ErrorCategory::Io,
+            ErrorKind::Internal => ErrorCategory::Internal,
+        }
+    }
+
+    // Process exit code when this error ends a one-shot run
+    pub fn exit_code(self) -> i32 {
+        self.category().exit_code()
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ErrorCategory {
+    // Documented in the README; 0 is success and 1 anything unclassified
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorCategory::Internal => 1,
+            ErrorCategory::Request => 2,
+            ErrorCategory::Model => 3,
+            ErrorCategory::Inference => 4,
+            ErrorCategory::Tool => 5,
+            ErrorCategory::Plan => 6,
+            ErrorCategory::Io => 7,
+        }
+    }
+}
+
+// A classified failure with a message and machine-readable details. It travels inside
+// anyhow errors and is recovered with `Error::classify` where the error is reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+    details: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            details: serde_json::Map::new(),
+        }
+    }
+
+    pub fn with_detail(mut self, key: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+        self.details.insert(key.to_string(), value);
+        self
+    }
+
+    // The typed error inside `error`, with the full context chain as its message, or
+    // `fallback` if nothing in the chain was classified
+    pub fn classify(error: &anyhow::Error, fallback: ErrorKind) -> Self {
+        let message = format!("{:#}", error);
+        match error.chain().find_map(|cause| cause.downcast_ref::<Error>()) {
+            Some(typed) => Self { message, ..typed.clone() },
+            None => Self::new(fallback, message),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn details(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.details
+    }
+
+    pub fn code(&self) -> u32 {
+        self.kind.code()
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        self.kind.category()
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.kind.exit_code()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// The same fields in every output mode: {"error", "code", "kind", "category", "details"}
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 5)?;
+        state.serialize_field("error", &self.message)?;
+        state.serialize_field("code", &self.code())?;
+        state.serialize_field("kind", &self.kind)?;
+        state.serialize_field("category", &self.category())?;
+        if self.details.is_empty() {
+            state.skip_field("details")?;
+        } else {
+            state.serialize_field("details", &self.details)?;
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_classify_finds_typed_error_in_chain() {
+        let typed = Error::new(ErrorKind::ContextLengthExceeded, "Prompt is 80 tokens")
+            .with_detail("prompt_tokens", 80)
+            .with_detail("context_length", 64);
+        let wrapped = Err::<(), _>(typed).context("Inference failed").unwrap_err();
+
+        let error = Error::classify(&wrapped, ErrorKind::InferenceFailed);
+        assert_eq!(error.kind(), ErrorKind::ContextLengthExceeded);
+        assert_eq!(error.message(), "Inference failed: Prompt is 80 tokens");
+        assert_eq!(error.details()["context_length"], 64);
+        assert_eq!(error.exit_code(), 2);
+
+        let plain = Error::classify(&anyhow::anyhow!("backend crashed"), ErrorKind::InferenceFailed);
+        assert_eq!((plain.kind(), plain.code(), plain.exit_code()), (ErrorKind::InferenceFailed, 4, 4));
+    }
+
+    #[test]
+    fn test_serialized_fields() {
+        let json = serde_json::to_value(Error::new(ErrorKind::ToolTimeout, "Tool timed out").with_detail("tool", "math")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "error": "Tool timed out",
+                "code": 21,
+                "kind": "tool_timeout",
+                "category": "tool",
+                "details": {"tool": "math"}
+            })
+        );
+        let bare = serde_json::to_value(Error::new(ErrorKind::Internal, "oops")).unwrap();
+        assert!(bare.get("details").is_none());
+        assert_eq!(serde_json::to_value(ErrorKind::PlanParseFailed).unwrap(), ErrorKind::PlanParseFailed.as_str());
+    }
+}
@@ -0,0 +1,760 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+pub const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+pub const DEFAULT_ALIGNMENT: u64 = 32;
+// Nesting limit for array metadata values; real files nest at most once
+const MAX_ARRAY_DEPTH: usize = 8;
+
+// Typed value from the GGUF key/value metadata table
+#[derive(Debug, Clone, PartialEq)]
+pub enum GgufValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    Bool(bool),
+    String(String),
+    Array(Vec<GgufValue>),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+impl GgufValue {
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            GgufValue::U8(v) => Some(v as u64),
+            GgufValue::U16(v) => Some(v as u64),
+            GgufValue::U32(v) => Some(v as u64),
+            GgufValue::U64(v) => Some(v),
+            GgufValue::I8(v) if v >= 0 => Some(v as u64),
+            GgufValue::I16(v) if v >= 0 => Some(v as u64),
+            GgufValue::I32(v) if v >= 0 => Some(v as u64),
+            GgufValue::I64(v) if v >= 0 => Some(v as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            GgufValue::I8(v) => Some(v as i64),
+            GgufValue::I16(v) => Some(v as i64),
+            GgufValue::I32(v) => Some(v as i64),
+            GgufValue::I64(v) => Some(v),
+            _ => self.as_u64().and_then(|v| i64::try_from(v).ok()),
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            GgufValue::F32(v) => Some(v),
+            GgufValue::F64(v) => Some(v as f32),
+            _ => self.as_i64().map(|v| v as f32),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            GgufValue::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            GgufValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[GgufValue]> {
+        match self {
+            GgufValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            GgufValue::U8(_) => "u8",
+            GgufValue::I8(_) => "i8",
+            GgufValue::U16(_) => "u16",
+            GgufValue::I16(_) => "i16",
+            GgufValue::U32(_) => "u32",
+            GgufValue::I32(_) => "i32",
+            GgufValue::F32(_) => "f32",
+            GgufValue::Bool(_) => "bool",
+            GgufValue::String(_) => "string",
+            GgufValue::Array(_) => "array",
+            GgufValue::U64(_) => "u64",
+            GgufValue::I64(_) => "i64",
+            GgufValue::F64(_) => "f64",
+        }
+    }
+}
+
+// Tensor element types as numbered by ggml
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GgmlType {
+    F32,
+    F16,
+    Q4_0,
+    Q4_1,
+    Q5_0,
+    Q5_1,
+    Q8_0,
+    Q8_1,
+    Q2_K,
+    Q3_K,
+    Q4_K,
+    Q5_K,
+    Q6_K,
+    Q8_K,
+    BF16,
+    Unknown(u32),
+}
+
+impl GgmlType {
+    pub fn from_id(id: u32) -> Self {
+        match id {
+            0 => GgmlType::F32,
+            1 => GgmlType::F16,
+            2 => GgmlType::Q4_0,
+            3 => GgmlType::Q4_1,
+            6 => GgmlType::Q5_0,
+            7 => GgmlType::Q5_1,
+            8 => GgmlType::Q8_0,
+            9 => GgmlType::Q8_1,
+            10 => GgmlType::Q2_K,
+            11 => GgmlType::Q3_K,
+            12 => GgmlType::Q4_K,
+            13 => GgmlType::Q5_K,
+            14 => GgmlType::Q6_K,
+            15 => GgmlType::Q8_K,
+            30 => GgmlType::BF16,
+            other => GgmlType::Unknown(other),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match *self {
+            GgmlType::F32 => 0,
+            GgmlType::F16 => 1,
+            GgmlType::Q4_0 => 2,
+            GgmlType::Q4_1 => 3,
+            GgmlType::Q5_0 => 6,
+            GgmlType::Q5_1 => 7,
+            GgmlType::Q8_0 => 8,
+            GgmlType::Q8_1 => 9,
+            GgmlType::Q2_K => 10,
+            GgmlType::Q3_K => 11,
+            GgmlType::Q4_K => 12,
+            GgmlType::Q5_K => 13,
+            GgmlType::Q6_K => 14,
+            GgmlType::Q8_K => 15,
+            GgmlType::BF16 => 30,
+            GgmlType::Unknown(id) => id,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            GgmlType::Unknown(id) => format!("unknown({})", id),
+            other => format!("{:?}", other),
+        }
+    }
+
+    // (elements per block, bytes per block)
+    pub fn block_layout(&self) -> Option<(u64, u64)> {
+        match self {
+            GgmlType::F32 => Some((1, 4)),
+            GgmlType::F16 | GgmlType::BF16 => Some((1, 2)),
+            GgmlType::Q4_0 => Some((32, 18)),
+            GgmlType::Q4_1 => Some((32, 20)),
+            GgmlType::Q5_0 => Some((32, 22)),
+            GgmlType::Q5_1 => Some((32, 24)),
+            GgmlType::Q8_0 => Some((32, 34)),
+            GgmlType::Q8_1 => Some((32, 36)),
+            GgmlType::Q2_K => Some((256, 84)),
+            GgmlType::Q3_K => Some((256, 110)),
+            GgmlType::Q4_K => Some((256, 144)),
+            GgmlType::Q5_K => Some((256, 176)),
+            GgmlType::Q6_K => Some((256, 210)),
+            GgmlType::Q8_K => Some((256, 292)),
+            GgmlType::Unknown(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TensorInfo {
+    pub name: String,
+    pub dims: Vec<u64>,
+    pub ggml_type: GgmlType,
+    pub offset: u64, // Relative to the start of the tensor data section
+}
+
+impl TensorInfo {
+    // None if the dimensions multiply past u64
+    pub fn n_elements(&self) -> Option<u64> {
+        self.dims.iter().try_fold(1u64, |n, &dim| n.checked_mul(dim))
+    }
+
+    pub fn size_bytes(&self) -> Option<u64> {
+        let (block_size, type_size) = self.ggml_type.block_layout()?;
+        (self.n_elements()? / block_size).checked_mul(type_size)
+    }
+
+    // Row length and row count when read as a matrix
+    pub fn matrix_shape(&self) -> Result<(usize, usize)> {
+        let cols = usize::try_from(self.dims.first().copied().unwrap_or(1)).ok();
+        let rows = self.dims.iter().skip(1).try_fold(1u64, |n, &dim| n.checked_mul(dim));
+        match (cols, rows.and_then(|rows| usize::try_from(rows).ok())) {
+            (Some(cols), Some(rows)) if cols.checked_mul(rows).is_some() => Ok((cols, rows)),
+            _ => Err(anyhow!("Tensor '{}' dimensions {:?} are too large", self.name, self.dims)),
+        }
+    }
+}
+
+// Parsed GGUF header: metadata table and tensor info table.
+// Tensor data itself is not copied; `data_offset` points at it in the source bytes.
+#[derive(Debug, Clone)]
+pub struct GgufFile {
+    pub version: u32,
+    pub metadata: HashMap<String, GgufValue>,
+    pub tensors: Vec<TensorInfo>,
+    pub alignment: u64,
+    pub data_offset: u64,
+}
+
+impl GgufFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 4 || &bytes[..4] != GGUF_MAGIC {
+            let found = &bytes[..bytes.len().min(4)];
+            return Err(anyhow!("Not a GGUF file (magic bytes {:02x?}, expected \"GGUF\")", found));
+        }
+
+        let mut reader = Reader { bytes, pos: 4, version: 0 };
+        let version = reader.read_u32()?;
+        if !(1..=3).contains(&version) {
+            return Err(anyhow!("Unsupported GGUF version: {}", version));
+        }
+        reader.version = version;
+
+        let tensor_count = reader.read_count()?;
+        let metadata_count = reader.read_count()?;
+
+        let mut metadata = HashMap::new();
+        for _ in 0..metadata_count {
+            let key = reader.read_string()?;
+            let value_type = reader.read_u32()?;
+            let value = reader
+                .read_value(value_type, 0)
+                .map_err(|e| anyhow!("Invalid metadata value for '{}': {}", key, e))?;
+            metadata.insert(key, value);
+        }
+
+        let mut tensors = Vec::new();
+        for _ in 0..tensor_count {
+            let name = reader.read_string()?;
+            let n_dims = reader.read_u32()?;
+            if n_dims > 4 {
+                return Err(anyhow!("Tensor '{}' has {} dimensions (max 4)", name, n_dims));
+            }
+            let mut dims = Vec::with_capacity(n_dims as usize);
+            for _ in 0..n_dims {
+                dims.push(reader.read_count()?);
+            }
+            let ggml_type = GgmlType::from_id(reader.read_u32()?);
+            let offset = reader.read_u64()?;
+            let tensor = TensorInfo { name, dims, ggml_type, offset };
+            if tensor.n_elements().is_none() {
+                return Err(anyhow!("Tensor '{}' dimensions {:?} overflow", tensor.name, tensor.dims));
+            }
+            tensors.push(tensor);
+        }
+
+        let alignment = match metadata.get("general.alignment").and_then(|v| v.as_u64()) {
+            Some(0) => return Err(anyhow!("Invalid general.alignment: 0")),
+            Some(a) => a,
+            None => DEFAULT_ALIGNMENT,
+        };
+        let data_offset = (reader.pos as u64).div_ceil(alignment) * alignment;
+
+        Ok(Self {
+            version,
+            metadata,
+            tensors,
+            alignment,
+            data_offset,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&GgufValue> {
+        self.metadata.get(key)
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(|v| v.as_str())
+    }
+
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get(key).and_then(|v| v.as_u64())
+    }
+
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.get(key).and_then(|v| v.as_f32())
+    }
+
+    pub fn architecture(&self) -> Option<&str> {
+        self.get_str("general.architecture")
+    }
+
+    // Lookup of architecture-scoped keys, e.g. "context_length" -> "llama.context_length"
+    pub fn arch_u64(&self, suffix: &str) -> Option<u64> {
+        let arch = self.architecture()?;
+        self.get_u64(&format!("{}.{}", arch, suffix))
+    }
+
+    pub fn arch_f32(&self, suffix: &str) -> Option<f32> {
+        let arch = self.architecture()?;
+        self.get_f32(&format!("{}.{}", arch, suffix))
+    }
+
+    pub fn tensor(&self, name: &str) -> Option<&TensorInfo> {
+        self.tensors.iter().find(|t| t.name == name)
+    }
+
+    // Byte range of a tensor within the file, checked against the file size
+    pub fn tensor_range(&self, tensor: &TensorInfo, file_size: u64) -> Result<std::ops::Range<usize>> {
+        if tensor.ggml_type.block_layout().is_none() {
+            return Err(anyhow!("Tensor '{}' has unsupported type {}", tensor.name, tensor.ggml_type.name()));
+        }
+        let bounds = self.data_offset.checked_add(tensor.offset).zip(tensor.size_bytes());
+        let Some((start, end)) = bounds.and_then(|(start, size)| Some((start, start.checked_add(size)?))) else {
+            return Err(anyhow!("Tensor '{}' offset or size overflows", tensor.name));
+        };
+        if end > file_size {
+            return Err(anyhow!(
+                "Tensor '{}' extends past end of file ({} > {} bytes); the model file is probably truncated",
+                tensor.name,
+                end,
+                file_size
+            ));
+        }
+        let range = usize::try_from(start).and_then(|start| Ok(start..usize::try_from(end)?));
+        range.map_err(|_| anyhow!("Tensor '{}' lies beyond the addressable memory", tensor.name))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    version: u32,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("Unexpected end of GGUF header at byte {}", self.pos))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    // Counts and lengths are u32 in GGUF v1 and u64 from v2 on
+    fn read_count(&mut self) -> Result<u64> {
+        if self.version == 1 {
+            Ok(self.read_u32()? as u64)
+        } else {
+            self.read_u64()
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let len = self.read_count()?;
+        let len = usize::try_from(len).map_err(|_| anyhow!("String length {} too large", len))?;
+        let bytes = self.take(len)?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    // `depth` counts the arrays this value is nested in
+    fn read_value(&mut self, value_type: u32, depth: usize) -> Result<GgufValue> {
+        Ok(match value_type {
+            0 => GgufValue::U8(self.read_array::<1>()?[0]),
+            1 => GgufValue::I8(self.read_array::<1>()?[0] as i8),
+            2 => GgufValue::U16(u16::from_le_bytes(self.read_array()?)),
+            3 => GgufValue::I16(i16::from_le_bytes(self.read_array()?)),
+            4 => GgufValue::U32(self.read_u32()?),
+            5 => GgufValue::I32(i32::from_le_bytes(self.read_array()?)),
+            6 => GgufValue::F32(f32::from_le_bytes(self.read_array()?)),
+            7 => GgufValue::Bool(self.read_array::<1>()?[0] != 0),
+            8 => GgufValue::String(self.read_string()?),
+            9 => {
+                if depth >= MAX_ARRAY_DEPTH {
+                    return Err(anyhow!("Arrays nested more than {} deep", MAX_ARRAY_DEPTH));
+                }
+                let item_type = self.read_u32()?;
+                let len = self.read_count()?;
+                // Every item takes at least one byte, so this bounds bogus lengths
+                if len > (self.bytes.len() - self.pos) as u64 {
+                    return Err(anyhow!("Array length {} exceeds remaining header size", len));
+                }
+                let mut items = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    items.push(self.read_value(item_type, depth + 1)?);
+                }
+                GgufValue::Array(items)
+            }
+            10 => GgufValue::U64(self.read_u64()?),
+            11 => GgufValue::I64(i64::from_le_bytes(self.read_array()?)),
+            12 => GgufValue::F64(f64::from_le_bytes(self.read_array()?)),
+            other => return Err(anyhow!("Unknown metadata value type {}", other)),
+        })
+    }
+}
+
+// Map of `general.file_type` to the llama.cpp quantization names
+fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        32 => "BF16",
+        _ => return None,
+    })
+}
+
+// Summary of a loaded model, derived from its GGUF header
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ModelInfo {
+    pub file_name: String,
+    pub file_size: u64,
+    pub gguf_version: u32,
+    pub tensor_count: usize,
+    pub metadata_count: usize,
+    pub architecture: Option<String>,
+    pub name: Option<String>,
+    pub quantization: Option<String>,
+    pub context_length: Option<u64>,
+    pub embedding_length: Option<u64>,
+    pub block_count: Option<u64>,
+    pub head_count: Option<u64>,
+    pub head_count_kv: Option<u64>,
+    pub vocab_size: Option<usize>,
+    pub tokenizer_model: Option<String>,
+    pub parameter_count: u64,
+    pub tensor_types: BTreeMap<String, usize>,
+    #[serde(skip)]
+    pub tensors: Vec<TensorInfo>,
+}
+
+impl ModelInfo {
+    pub fn from_gguf(gguf: &GgufFile, file_name: &str, file_size: u64) -> Self {
+        let mut tensor_types = BTreeMap::new();
+        for tensor in &gguf.tensors {
+            *tensor_types.entry(tensor.ggml_type.name()).or_insert(0) += 1;
+        }
+
+        // Prefer the declared file type; otherwise report the most common tensor type
+        let quantization = gguf
+            .get_u64("general.file_type")
+            .and_then(file_type_name)
+            .map(str::to_string)
+            .or_else(|| {
+                tensor_types
+                    .iter()
+                    .max_by_key(|(_, count)| **count)
+                    .map(|(name, _)| name.clone())
+            });
+
+        Self {
+            file_name: file_name.to_string(),
+            file_size,
+            gguf_version: gguf.version,
+            tensor_count: gguf.tensors.len(),
+            metadata_count: gguf.metadata.len(),
+            architecture: gguf.architecture().map(str::to_string),
+            name: gguf.get_str("general.name").map(str::to_string),
+            quantization,
+            context_length: gguf.arch_u64("context_length"),
+            embedding_length: gguf.arch_u64("embedding_length"),
+            block_count: gguf.arch_u64("block_count"),
+            head_count: gguf.arch_u64("attention.head_count"),
+            head_count_kv: gguf.arch_u64("attention.head_count_kv"),
+            vocab_size: gguf
+                .get("tokenizer.ggml.tokens")
+                .and_then(|v| v.as_array())
+                .map(|tokens| tokens.len()),
+            tokenizer_model: gguf.get_str("tokenizer.ggml.model").map(str::to_string),
+            parameter_count: gguf.tensors.iter().filter_map(TensorInfo::n_elements).fold(0, u64::saturating_add),
+            tensor_types,
+            tensors: gguf.tensors.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}, {}",
+            self.file_name,
+            self.architecture.as_deref().unwrap_or("unknown-arch"),
+            self.quantization.as_deref().unwrap_or("unknown-quant")
+        )?;
+        if let Some(ctx) = self.context_length {
+            write!(f, ", ctx={}", ctx)?;
+        }
+        write!(f, ", GGUF v{}]", self.gguf_version)
+    }
+}
+
+// Minimal GGUF writer used to build fixture models in tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    pub struct GgufBuilder {
+        metadata: Vec<(String, GgufValue)>,
+        tensors: Vec<(String, Vec<u64>, GgmlType, Vec<u8>)>,
+    }
+
+    impl GgufBuilder {
+        pub fn new() -> Self {
+            Self {
+                metadata: Vec::new(),
+                tensors: Vec::new(),
+            }
+        }
+
+        pub fn kv(mut self, key: &str, value: GgufValue) -> Self {
+            self.metadata.push((key.to_string(), value));
+            self
+        }
+
+        pub fn tensor(mut self, name: &str, dims: &[u64], ggml_type: GgmlType, data: Vec<u8>) -> Self {
+            self.tensors.push((name.to_string(), dims.to_vec(), ggml_type, data));
+            self
+        }
+
+        pub fn tensor_f32(self, name: &str, dims: &[u64], values: &[f32]) -> Self {
+            let data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            self.tensor(name, dims, GgmlType::F32, data)
+        }
+
+        pub fn build(self) -> Vec<u8> {
+            let mut out = Vec::new();
+            out.extend_from_slice(GGUF_MAGIC);
+            out.extend_from_slice(&3u32.to_le_bytes());
+            out.extend_from_slice(&(self.tensors.len() as u64).to_le_bytes());
+            out.extend_from_slice(&(self.metadata.len() as u64).to_le_bytes());
+
+            for (key, value) in &self.metadata {
+                write_string(&mut out, key);
+                out.extend_from_slice(&value_type_id(value).to_le_bytes());
+                write_value(&mut out, value);
+            }
+
+            let mut offset = 0u64;
+            let mut offsets = Vec::new();
+            for (name, dims, ggml_type, data) in &self.tensors {
+                write_string(&mut out, name);
+                out.extend_from_slice(&(dims.len() as u32).to_le_bytes());
+                for dim in dims {
+                    out.extend_from_slice(&dim.to_le_bytes());
+                }
+                out.extend_from_slice(&ggml_type.id().to_le_bytes());
+                out.extend_from_slice(&offset.to_le_bytes());
+                offsets.push(offset);
+                offset = (offset + data.len() as u64).div_ceil(DEFAULT_ALIGNMENT) * DEFAULT_ALIGNMENT;
+            }
+
+            let data_start = (out.len() as u64).div_ceil(DEFAULT_ALIGNMENT) * DEFAULT_ALIGNMENT;
+            out.resize(data_start as usize, 0);
+            for ((_, _, _, data), offset) in self.tensors.iter().zip(offsets) {
+                out.resize((data_start + offset) as usize, 0);
+                out.extend_from_slice(data);
+            }
+            out
+        }
+    }
+
+    fn write_string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn value_type_id(value: &GgufValue) -> u32 {
+        match value {
+            GgufValue::U8(_) => 0,
+            GgufValue::I8(_) => 1,
+            GgufValue::U16(_) => 2,
+            GgufValue::I16(_) => 3,
+            GgufValue::U32(_) => 4,
+            GgufValue::I32(_) => 5,
+            GgufValue::F32(_) => 6,
+            GgufValue::Bool(_) => 7,
+            GgufValue::String(_) => 8,
+            GgufValue::Array(_) => 9,
+            GgufValue::U64(_) => 10,
+            GgufValue::I64(_) => 11,
+            GgufValue::F64(_) => 12,
+        }
+    }
+
+    fn write_value(out: &mut Vec<u8>, value: &GgufValue) {
+        match value {
+            GgufValue::U8(v) => out.push(*v),
+            GgufValue::I8(v) => out.push(*v as u8),
+            GgufValue::U16(v) => out.extend_from_slice(&v.to_le_bytes()),
+            GgufValue::I16(v) => out.extend_from_slice(&v.to_le_bytes()),
+            GgufValue::U32(v) => out.extend_from_slice(&v.to_le_bytes()),
+            GgufValue::I32(v) => out.extend_from_slice(&v.to_le_bytes()),
+            GgufValue::F32(v) => out.extend_from_slice(&v.to_le_bytes()),
+            GgufValue::Bool(v) => out.push(*v as u8),
+            GgufValue::String(s) => write_string(out, s),
+            GgufValue::Array(items) => {
+                let item_type = items.first().map(value_type_id).unwrap_or(8);
+                out.extend_from_slice(&item_type.to_le_bytes());
+                out.extend_from_slice(&(items.len() as u64).to_le_bytes());
+                for item in items {
+                    write_value(out, item);
+                }
+            }
+            GgufValue::U64(v) => out.extend_from_slice(&v.to_le_bytes()),
+            GgufValue::I64(v) => out.extend_from_slice(&v.to_le_bytes()),
+            GgufValue::F64(v) => out.extend_from_slice(&v.to_le_bytes()),
+        }
+    }
+
+    pub fn strings(items: &[&str]) -> GgufValue {
+        GgufValue::Array(items.iter().map(|s| GgufValue::String(s.to_string())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+
+    fn sample_model() -> Vec<u8> {
+        GgufBuilder::new()
+            .kv("general.architecture", GgufValue::String("llama".to_string()))
+            .kv("general.name", GgufValue::String("tiny-test".to_string()))
+            .kv("general.file_type", GgufValue::U32(15))
+            .kv("llama.context_length", GgufValue::U32(2048))
+            .kv("llama.embedding_length", GgufValue::U32(4))
+            .kv("tokenizer.ggml.model", GgufValue::String("llama".to_string()))
+            .kv("tokenizer.ggml.tokens", strings(&["<unk>", "<s>", "</s>", "hi"]))
+            .tensor_f32("token_embd.weight", &[4, 4], &[0.5; 16])
+            .tensor_f32("output_norm.weight", &[4], &[1.0; 4])
+            .build()
+    }
+
+    #[test]
+    fn test_parse_header_and_metadata() {
+        let bytes = sample_model();
+        let gguf = GgufFile::parse(&bytes).unwrap();
+
+        assert_eq!(gguf.version, 3);
+        assert_eq!(gguf.architecture(), Some("llama"));
+        assert_eq!(gguf.arch_u64("context_length"), Some(2048));
+        assert_eq!(gguf.tensors.len(), 2);
+        assert_eq!(gguf.tensors[0].dims, vec![4, 4]);
+        assert_eq!(gguf.tensors[0].ggml_type, GgmlType::F32);
+        assert_eq!(gguf.data_offset % DEFAULT_ALIGNMENT, 0);
+
+        let tokens = gguf.get("tokenizer.ggml.tokens").unwrap().as_array().unwrap();
+        assert_eq!(tokens[3].as_str(), Some("hi"));
+    }
+
+    #[test]
+    fn test_tensor_data_location() {
+        let bytes = sample_model();
+        let gguf = GgufFile::parse(&bytes).unwrap();
+        let tensor = gguf.tensor("output_norm.weight").unwrap();
+        let range = gguf.tensor_range(tensor, bytes.len() as u64).unwrap();
+
+        assert_eq!(&bytes[range], &[1.0f32.to_le_bytes(); 4].concat()[..]);
+
+        // A truncated file must be reported rather than read out of bounds
+        assert!(gguf.tensor_range(tensor, bytes.len() as u64 - 1).is_err());
+    }
+
+    #[test]
+    fn test_model_info_summary() {
+        let bytes = sample_model();
+        let gguf = GgufFile::parse(&bytes).unwrap();
+        let info = ModelInfo::from_gguf(&gguf, "tiny.gguf", bytes.len() as u64);
+
+        assert_eq!(info.architecture.as_deref(), Some("llama"));
+        assert_eq!(info.quantization.as_deref(), Some("Q4_K_M"));
+        assert_eq!(info.vocab_size, Some(4));
+        assert_eq!(info.parameter_count, 20);
+        assert_eq!(info.tensor_types.get("F32"), Some(&2));
+        assert_eq!(info.to_string(), "tiny.gguf [llama, Q4_K_M, ctx=2048, GGUF v3]");
+    }
+
+    #[test]
+    fn test_rejects_invalid_files() {
+        assert!(GgufFile::parse(b"GGML").is_err());
+        assert!(GgufFile::parse(b"GG").is_err());
+
+        // Version 10 is what the old CI placeholder ("GGUF\n" + zeros) decodes to
+        let mut bogus = b"GGUF\n\0\0\0".to_vec();
+        bogus.resize(64, 0);
+        assert!(GgufFile::parse(&bogus).is_err());
+
+        let mut truncated = sample_model();
+        truncated.truncate(40);
+        assert!(GgufFile::parse(&truncated).is_err());
+
+        // Crafted sizes and nesting are errors rather than overflows or stack exhaustion
+        let huge = GgufBuilder::new().tensor("t", &[u64::MAX, 2], GgmlType::F32, Vec::new()).build();
+        assert!(GgufFile::parse(&huge).unwrap_err().to_string().contains("overflow"));
+        let far = GgufBuilder::new().tensor("t", &[u64::MAX / 4], GgmlType::F32, Vec::new()).build();
+        let gguf = GgufFile::parse(&far).unwrap();
+        assert!(gguf.tensor_range(&gguf.tensors[0], u64::MAX).unwrap_err().to_string().contains("overflows"));
+        let nested = (0..100).fold(GgufValue::U8(0), |value, _| GgufValue::Array(vec![value]));
+        let deep = GgufBuilder::new().kv("deep", nested).build();
+        assert!(GgufFile::parse(&deep).unwrap_err().to_string().contains("nested"));
+    }
+}
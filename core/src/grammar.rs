@@ -0,0 +1,814 @@
+use crate::{Error, ErrorKind, InferenceRequest};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+// One step of a grammar sequence
+#[derive(Debug, Clone, PartialEq)]
+enum Element {
+    // Inclusive char ranges; `negated` matches any char outside them
+    Chars { ranges: Vec<(char, char)>, negated: bool },
+    Rule(usize),
+}
+
+impl Element {
+    fn literal(c: char) -> Self {
+        Element::Chars { ranges: vec![(c, c)], negated: false }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Element::Chars { ranges, negated } => ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated,
+            Element::Rule(_) => false,
+        }
+    }
+}
+
+type Sequence = Vec<Element>;
+
+// Upper bound on the counts of `{m,n}`, which are expanded into copies of the symbol
+const MAX_REPETITION: usize = 1000;
+
+// Context-free grammar in a GBNF subset (the format llama.cpp uses): rules
+// `name ::= ...` built from "literals", [char classes], `.`, rule references,
+// ( groups ), alternatives `|` and the repetitions `*`, `+`, `?` and `{m,n}`
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    rules: Vec<Vec<Sequence>>,
+    root: usize,
+}
+
+impl Grammar {
+    // Malformed grammars are `InvalidRequest` errors, as they come with requests
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_rules(text).map_err(|e| Error::new(ErrorKind::InvalidRequest, format!("{:#}", e)).into())
+    }
+
+    fn parse_rules(text: &str) -> Result<Self> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+            rules: Vec::new(),
+            names: Vec::new(),
+            ids: HashMap::new(),
+            defined: Vec::new(),
+        };
+        parser.parse_rules()?;
+
+        if let Some(i) = parser.defined.iter().position(|d| !d) {
+            return Err(anyhow!("Grammar rule '{}' is referenced but never defined", parser.names[i]));
+        }
+        let root = *parser.ids.get("root").ok_or_else(|| anyhow!("Grammar has no 'root' rule"))?;
+        check_left_recursion(&parser.rules, &parser.names)?;
+        Ok(Self {
+            rules: parser.rules,
+            root,
+        })
+    }
+
+    // Grammar accepting JSON documents that satisfy `schema`
+    pub fn from_json_schema(schema: &Value) -> Result<Self> {
+        let gbnf = json_schema_to_gbnf(schema).map_err(|e| Error::new(ErrorKind::InvalidRequest, format!("{:#}", e)))?;
+        Self::parse(&gbnf)
+    }
+
+    // The grammar a request asks for, if any
+    pub fn from_request(request: &InferenceRequest) -> Result<Option<Self>> {
+        match (&request.grammar, &request.json_schema) {
+            (Some(_), Some(_)) => Err(anyhow!("Set either grammar or json_schema, not both")),
+            (Some(grammar), None) => Self::parse(grammar).map(Some),
+            (None, Some(schema)) => Self::from_json_schema(schema).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn start(&self) -> GrammarState<'_> {
+        let mut stacks = Vec::new();
+        for alt in 0..self.rules[self.root].len() {
+            self.expand(self.position_stack(Vec::new(), self.root, alt), &mut stacks);
+        }
+        GrammarState {
+            grammar: self,
+            stacks,
+            pending: Vec::new(),
+        }
+    }
+
+    // Whether the whole of `text` is a sentence of the grammar
+    pub fn matches(&self, text: &str) -> bool {
+        let mut state = self.start();
+        state.advance(text.as_bytes()) && state.is_complete()
+    }
+
+    fn position_stack(&self, mut stack: Stack, rule: usize, alt: usize) -> Stack {
+        if !self.rules[rule][alt].is_empty() {
+            stack.push((rule, alt, 0));
+        }
+        stack
+    }
+
+    // Replace rule references on top of `stack` by their alternatives until every
+    // resulting stack is empty (accepting) or has a char class on top
+    fn expand(&self, stack: Stack, out: &mut Vec<Stack>) {
+        let Some(&(rule, alt, index)) = stack.last() else {
+            if !out.contains(&stack) {
+                out.push(stack);
+            }
+            return;
+        };
+        match &self.rules[rule][alt][index] {
+            Element::Rule(target) => {
+                let mut base = stack[..stack.len() - 1].to_vec();
+                if index + 1 < self.rules[rule][alt].len() {
+                    base.push((rule, alt, index + 1));
+                }
+                for target_alt in 0..self.rules[*target].len() {
+                    self.expand(self.position_stack(base.clone(), *target, target_alt), out);
+                }
+            }
+            Element::Chars { .. } => {
+                if !out.contains(&stack) {
+                    out.push(stack);
+                }
+            }
+        }
+    }
+
+    fn step(&self, stacks: &[Stack], c: char) -> Vec<Stack> {
+        let mut out = Vec::new();
+        for stack in stacks {
+            let Some(&(rule, alt, index)) = stack.last() else { continue };
+            if self.rules[rule][alt][index].matches(c) {
+                let mut next = stack[..stack.len() - 1].to_vec();
+                if index + 1 < self.rules[rule][alt].len() {
+                    next.push((rule, alt, index + 1));
+                }
+                self.expand(next, &mut out);
+            }
+        }
+        out
+    }
+}
+
+// Reject rules that can reach themselves without consuming a character, which `expand`
+// would follow until the stack overflows
+fn check_left_recursion(rules: &[Vec<Sequence>], names: &[String]) -> Result<()> {
+    // Rules that can match the empty string
+    let mut nullable = vec![false; rules.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (id, alternatives) in rules.iter().enumerate() {
+            let empty = |seq: &Sequence| seq.iter().all(|e| matches!(e, Element::Rule(t) if nullable[*t]));
+            if !nullable[id] && alternatives.iter().any(empty) {
+                nullable[id] = true;
+                changed = true;
+            }
+        }
+    }
+
+    // Edges from each rule to the rules it can start with
+    let mut predecessors = vec![Vec::new(); rules.len()];
+    let mut successors = vec![Vec::new(); rules.len()];
+    let mut incoming = vec![0usize; rules.len()];
+    for (id, alternatives) in rules.iter().enumerate() {
+        for seq in alternatives {
+            for element in seq {
+                let Element::Rule(target) = *element else { break };
+                successors[id].push(target);
+                predecessors[target].push(id);
+                incoming[target] += 1;
+                if !nullable[target] {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Peel off rules no remaining edge leads to; what is left lies on or behind a cycle
+    let mut ready: Vec<usize> = (0..rules.len()).filter(|&id| incoming[id] == 0).collect();
+    while let Some(id) = ready.pop() {
+        for &target in &successors[id] {
+            incoming[target] -= 1;
+            if incoming[target] == 0 {
+                ready.push(target);
+            }
+        }
+    }
+    let Some(mut id) = (0..rules.len()).find(|&id| incoming[id] > 0) else {
+        return Ok(());
+    };
+    // Walking back that many steps from any leftover rule ends up on the cycle
+    for _ in 0..rules.len() {
+        id = predecessors[id].iter().copied().find(|&p| incoming[p] > 0).unwrap_or(id);
+    }
+    Err(anyhow!("Grammar rule '{}' is left-recursive: it can reach itself without matching a character", names[id]))
+}
+
+// Positions (rule, alternative, element) still to be matched; the top is last
+type Stack = Vec<(usize, usize, usize)>;
+
+// Where a partially generated output stands within a grammar
+#[derive(Debug, Clone)]
+pub struct GrammarState<'g> {
+    grammar: &'g Grammar,
+    stacks: Vec<Stack>,
+    // Leading bytes of a UTF-8 character split across tokens
+    pending: Vec<u8>,
+}
+
+impl GrammarState<'_> {
+    // Consume `bytes`; returns false (leaving the state unusable) if the grammar rejects them
+    pub fn advance(&mut self, bytes: &[u8]) -> bool {
+        let mut buffer = std::mem::take(&mut self.pending);
+        buffer.extend_from_slice(bytes);
+
+        let (text, rest) = match std::str::from_utf8(&buffer) {
+            Ok(text) => (text, &[][..]),
+            Err(e) if e.error_len().is_none() => {
+                let (valid, rest) = buffer.split_at(e.valid_up_to());
+                (std::str::from_utf8(valid).unwrap_or_default(), rest)
+            }
+            Err(_) => return false,
+        };
+        for c in text.chars() {
+            self.stacks = self.grammar.step(&self.stacks, c);
+            if self.stacks.is_empty() {
+                return false;
+            }
+        }
+        self.pending = rest.to_vec();
+        true
+    }
+
+    // Whether `bytes` could be consumed next
+    pub fn accepts(&self, bytes: &[u8]) -> bool {
+        !bytes.is_empty() && self.clone().advance(bytes)
+    }
+
+    // The output so far is a complete sentence, so generation may end here
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty() && self.stacks.iter().any(|s| s.is_empty())
+    }
+
+    // Some continuation is still possible
+    pub fn can_continue(&self) -> bool {
+        self.stacks.iter().any(|s| !s.is_empty())
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    rules: Vec<Vec<Sequence>>,
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    defined: Vec<bool>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        let line = self.chars[..self.pos.min(self.chars.len())].iter().filter(|&&c| c == '\n').count() + 1;
+        anyhow!("Grammar error on line {}: {}", line, message)
+    }
+
+    // Skip spaces and comments, and newlines too when `newlines` is set
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else if c == ' ' || c == '\t' || c == '\r' || (newlines && c == '\n') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.rules.len();
+        self.rules.push(Vec::new());
+        self.names.push(name.to_string());
+        self.defined.push(false);
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn new_rule(&mut self, hint: &str, alternatives: Vec<Sequence>) -> usize {
+        let id = self.rule_id(&format!("{}-{}", hint, self.rules.len()));
+        self.rules[id] = alternatives;
+        self.defined[id] = true;
+        id
+    }
+
+    fn parse_name(&mut self) -> Option<String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            self.pos += 1;
+        }
+        (self.pos > start).then(|| self.chars[start..self.pos].iter().collect())
+    }
+
+    fn parse_rules(&mut self) -> Result<()> {
+        loop {
+            self.skip_space(true);
+            if self.peek().is_none() {
+                return Ok(());
+            }
+            let name = self.parse_name().ok_or_else(|| self.error("expected a rule name"))?;
+            self.skip_space(false);
+            if !self.chars[self.pos..].starts_with(&[':', ':', '=']) {
+                return Err(self.error(&format!("expected '::=' after '{}'", name)));
+            }
+            self.pos += 3;
+
+            let id = self.rule_id(&name);
+            if self.defined[id] {
+                return Err(self.error(&format!("rule '{}' is defined twice", name)));
+            }
+            let alternatives = self.parse_alternatives(&name, false)?;
+            self.rules[id] = alternatives;
+            self.defined[id] = true;
+        }
+    }
+
+    fn parse_alternatives(&mut self, name: &str, nested: bool) -> Result<Vec<Sequence>> {
+        let mut alternatives = vec![self.parse_sequence(name, nested)?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.parse_sequence(name, nested)?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self, name: &str, nested: bool) -> Result<Sequence> {
+        let mut sequence = Vec::new();
+        // Start of the last symbol, which a repetition operator applies to
+        let mut last_start = 0;
+        loop {
+            self.skip_space(nested);
+            let Some(c) = self.peek() else { break };
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    last_start = sequence.len();
+                    loop {
+                        match self.peek() {
+                            None => return Err(self.error("unterminated string literal")),
+                            Some('"') => break,
+                            Some(_) => {
+                                let c = self.parse_char()?;
+                                sequence.push(Element::literal(c));
+                            }
+                        }
+                    }
+                    self.pos += 1;
+                }
+                '[' => {
+                    self.pos += 1;
+                    last_start = sequence.len();
+                    let negated = self.peek() == Some('^');
+                    if negated {
+                        self.pos += 1;
+                    }
+                    let mut ranges = Vec::new();
+                    while self.peek() != Some(']') {
+                        if self.peek().is_none() {
+                            return Err(self.error("unterminated character class"));
+                        }
+                        let lo = self.parse_char()?;
+                        let hi = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                            self.pos += 1;
+                            self.parse_char()?
+                        } else {
+                            lo
+                        };
+                        ranges.push((lo, hi));
+                    }
+                    self.pos += 1;
+                    sequence.push(Element::Chars { ranges, negated });
+                }
+                '.' => {
+                    self.pos += 1;
+                    last_start = sequence.len();
+                    sequence.push(Element::Chars { ranges: Vec::new(), negated: true });
+                }
+                '(' => {
+                    self.pos += 1;
+                    last_start = sequence.len();
+                    let alternatives = self.parse_alternatives(name, true)?;
+                    if self.peek() != Some(')') {
+                        return Err(self.error("expected ')'"));
+                    }
+                    self.pos += 1;
+                    let id = self.new_rule(name, alternatives);
+                    sequence.push(Element::Rule(id));
+                }
+                '*' | '+' | '?' | '{' => {
+                    if last_start >= sequence.len() {
+                        return Err(self.error(&format!("'{}' does not follow a symbol", c)));
+                    }
+                    let (min, max) = self.parse_repetition()?;
+                    let symbol = sequence.split_off(last_start);
+                    let repeated = self.repeat(name, symbol, min, max);
+                    sequence.extend(repeated);
+                    // Repeating a repetition needs parentheses, so copies never multiply
+                    last_start = sequence.len();
+                }
+                c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => {
+                    let start = self.pos;
+                    let reference = self.parse_name().unwrap_or_default();
+                    // A name followed by ::= starts the next rule
+                    self.skip_space(false);
+                    if self.chars[self.pos..].starts_with(&[':', ':', '=']) {
+                        self.pos = start;
+                        break;
+                    }
+                    last_start = sequence.len();
+                    let id = self.rule_id(&reference);
+                    sequence.push(Element::Rule(id));
+                }
+                _ => break,
+            }
+        }
+        Ok(sequence)
+    }
+
+    fn parse_repetition(&mut self) -> Result<(usize, Option<usize>)> {
+        let c = self.peek().unwrap_or_default();
+        self.pos += 1;
+        match c {
+            '*' => Ok((0, None)),
+            '+' => Ok((1, None)),
+            '?' => Ok((0, Some(1))),
+            _ => {
+                let close = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .ok_or_else(|| self.error("unterminated '{'"))?;
+                let body: String = self.chars[self.pos..self.pos + close].iter().collect();
+                self.pos += close + 1;
+                let number = |s: &str| match s.trim().parse::<usize>() {
+                    Ok(n) if n > MAX_REPETITION => {
+                        Err(self.error(&format!("repetition count {} is above the limit of {}", n, MAX_REPETITION)))
+                    }
+                    Ok(n) => Ok(n),
+                    Err(_) => Err(self.error(&format!("bad repetition '{{{}}}'", body))),
+                };
+                let (min, max) = match body.split_once(',') {
+                    None => number(&body).map(|n| (n, Some(n)))?,
+                    Some((min, max)) if max.trim().is_empty() => (number(min)?, None),
+                    Some((min, max)) => (number(min)?, Some(number(max)?)),
+                };
+                if max.is_some_and(|max| max < min) {
+                    return Err(self.error(&format!("bad repetition '{{{}}}'", body)));
+                }
+                Ok((min, max))
+            }
+        }
+    }
+
+    // Rewrite `symbol{min,max}` into plain sequences and helper rules
+    fn repeat(&mut self, name: &str, symbol: Sequence, min: usize, max: Option<usize>) -> Sequence {
+        let mut out: Sequence = Vec::new();
+        for _ in 0..min {
+            out.extend(symbol.iter().cloned());
+        }
+        match max {
+            // rest ::= symbol rest | ε
+            None => {
+                let id = self.new_rule(name, Vec::new());
+                let mut recursive = symbol.clone();
+                recursive.push(Element::Rule(id));
+                self.rules[id] = vec![recursive, Vec::new()];
+                out.push(Element::Rule(id));
+            }
+            // Nested optionals: (symbol (symbol ...)?)?
+            Some(max) => {
+                let mut tail: Option<usize> = None;
+                for _ in min..max {
+                    let mut seq = symbol.clone();
+                    if let Some(tail) = tail {
+                        seq.push(Element::Rule(tail));
+                    }
+                    tail = Some(self.new_rule(name, vec![seq, Vec::new()]));
+                }
+                out.extend(tail.map(Element::Rule));
+            }
+        }
+        out
+    }
+
+    fn parse_char(&mut self) -> Result<char> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end of grammar"))?;
+        self.pos += 1;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let escaped = self.peek().ok_or_else(|| self.error("unexpected end of grammar"))?;
+        self.pos += 1;
+        let hex = |parser: &mut Self, len: usize| -> Result<char> {
+            let digits: String = parser.chars.iter().skip(parser.pos).take(len).collect();
+            parser.pos += len;
+            u32::from_str_radix(&digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| parser.error(&format!("bad escape '{}'", digits)))
+        };
+        match escaped {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            'x' => hex(self, 2),
+            'u' => hex(self, 4),
+            'U' => hex(self, 8),
+            '\\' | '"' | '[' | ']' | '-' | '^' => Ok(escaped),
+            other => Err(self.error(&format!("unknown escape '\\{}'", other))),
+        }
+    }
+}
+
+// Shared rules for JSON primitives
+const JSON_PRIMITIVES: &str = r#"ws ::= [ \t\n]?
+string ::= "\"" ( [^"\\\x00-\x1f] | "\\" ( ["\\/bfnrt] | "u" [0-9a-fA-F]{4} ) )* "\""
+number ::= "-"? ( [0-9] | [1-9] [0-9]+ ) ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )?
+integer ::= "-"? ( [0-9] | [1-9] [0-9]+ )
+boolean ::= "true" | "false"
+null ::= "null"
+value ::= object | array | string | number | boolean | null
+object ::= "{" ws ( string ws ":" ws value ws ( "," ws string ws ":" ws value ws )* )? "}"
+array ::= "[" ws ( value ws ( "," ws value ws )* )? "]"
+"#;
+
+// Translate a JSON schema into GBNF. Supported: type (incl. lists), properties with
+// required (required ones first, in `required` order), items/minItems, enum, const,
+// anyOf/oneOf. Other keywords are ignored, so the grammar may accept a superset.
+pub fn json_schema_to_gbnf(schema: &Value) -> Result<String> {
+    let mut converter = SchemaConverter { rules: BTreeMap::new(), sources: HashMap::new() };
+    let root = converter.visit(schema, "root")?;
+    let mut out = String::new();
+    if root != "root" {
+        out.push_str(&format!("root ::= {}\n", root));
+    }
+    for (name, body) in &converter.rules {
+        out.push_str(&format!("{} ::= {}\n", name, body));
+    }
+    out.push_str(JSON_PRIMITIVES);
+    Ok(out)
+}
+
+struct SchemaConverter {
+    rules: BTreeMap<String, String>,
+    // The schema path each rule name was made from
+    sources: HashMap<String, String>,
+}
+
+impl SchemaConverter {
+    // Returns a GBNF expression matching `schema`, adding helper rules as needed
+    fn visit(&mut self, schema: &Value, name: &str) -> Result<String> {
+        let Some(obj) = schema.as_object() else {
+            // `true` or an empty schema accepts anything
+            return match schema {
+                Value::Bool(false) => Err(anyhow!("JSON schema 'false' accepts nothing")),
+                _ => Ok("value".to_string()),
+            };
+        };
+
+        if obj.contains_key("$ref") {
+            return Err(anyhow!("JSON schema $ref is not supported"));
+        }
+        if let Some(value) = obj.get("const") {
+            return Ok(json_literal(value));
+        }
+        if let Some(values) = obj.get("enum").and_then(Value::as_array) {
+            if values.is_empty() {
+                return Err(anyhow!("JSON schema enum at '{}' is empty", name));
+            }
+            let alternatives: Vec<String> = values.iter().map(json_literal).collect();
+            return self.rule(name, alternatives.join(" | "));
+        }
+        if let Some(options) = obj.get("anyOf").or_else(|| obj.get("oneOf")).and_then(Value::as_array) {
+            let mut alternatives = Vec::new();
+            for (i, option) in options.iter().enumerate() {
+                alternatives.push(self.visit(option, &format!("{}-{}", name, i))?);
+            }
+            return self.rule(name, alternatives.join(" | "));
+        }
+
+        match obj.get("type") {
+            Some(Value::Array(types)) => {
+                let mut alternatives = Vec::new();
+                for ty in types {
+                    let mut single = obj.clone();
+                    single.insert("type".to_string(), ty.clone());
+                    alternatives.push(self.visit(&Value::Object(single), &format!("{}-{}", name, ty.as_str().unwrap_or("any")))?);
+                }
+                self.rule(name, alternatives.join(" | "))
+            }
+            Some(Value::String(ty)) => match ty.as_str() {
+                "object" => self.object(obj, name),
+                "array" => self.array(obj, name),
+                "string" | "number" | "integer" | "boolean" | "null" => Ok(ty.clone()),
+                other => Err(anyhow!("Unknown JSON schema type '{}'", other)),
+            },
+            Some(_) => Err(anyhow!("Invalid JSON schema type at '{}'", name)),
+            None if obj.contains_key("properties") => self.object(obj, name),
+            None if obj.contains_key("items") => self.array(obj, name),
+            None => Ok("value".to_string()),
+        }
+    }
+
+    fn object(&mut self, obj: &serde_json::Map<String, Value>, name: &str) -> Result<String> {
+        let Some(properties) = obj.get("properties").and_then(Value::as_object) else {
+            return Ok("object".to_string());
+        };
+        let required: Vec<&str> = obj
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|r| r.iter().filter_map(Value::as_str).filter(|k| properties.contains_key(*k)).collect())
+            .unwrap_or_default();
+        let optional: Vec<&str> = properties.keys().map(String::as_str).filter(|k| !required.contains(k)).collect();
+
+        let pair = |this: &mut Self, key: &str| -> Result<String> {
+            let value = this.visit(&properties[key], &format!("{}-{}", name, sanitize(key)))?;
+            Ok(format!("{} ws \":\" ws {} ws", json_literal(&Value::String(key.to_string())), value))
+        };
+
+        let mut body = String::from("\"{\" ws ");
+        for (i, key) in required.iter().enumerate() {
+            if i > 0 {
+                body.push_str("\",\" ws ");
+            }
+            body.push_str(&pair(self, key)?);
+            body.push(' ');
+        }
+        // Optional properties may each be left out; without required ones the first
+        // optional property has to be present for any later one to appear
+        let mut optional_body = String::new();
+        for (i, key) in optional.iter().enumerate() {
+            let kv = pair(self, key)?;
+            if required.is_empty() && i == 0 {
+                optional_body.push_str(&format!("{} ", kv));
+            } else {
+                optional_body.push_str(&format!("( \",\" ws {} )? ", kv));
+            }
+        }
+        if required.is_empty() && !optional.is_empty() {
+            body.push_str(&format!("( {})? ", optional_body));
+        } else {
+            body.push_str(&optional_body);
+        }
+        body.push_str("\"}\"");
+        self.rule(name, body)
+    }
+
+    fn array(&mut self, obj: &serde_json::Map<String, Value>, name: &str) -> Result<String> {
+        let item = match obj.get("items") {
+            Some(items) => self.visit(items, &format!("{}-item", name))?,
+            None => "value".to_string(),
+        };
+        let min_items = obj.get("minItems").and_then(Value::as_u64).unwrap_or(0) as usize;
+
+        let more = format!("( \",\" ws {} ws )*", item);
+        let body = match min_items {
+            0 => format!("\"[\" ws ( {} ws {} )? \"]\"", item, more),
+            n => {
+                let mut body = format!("\"[\" ws {} ws ", item);
+                for _ in 1..n {
+                    body.push_str(&format!("\",\" ws {} ws ", item));
+                }
+                format!("{}{} \"]\"", body, more)
+            }
+        };
+        self.rule(name, body)
+    }
+
+    fn rule(&mut self, name: &str, body: String) -> Result<String> {
+        let rule = sanitize(name);
+        if let Some(other) = self.sources.insert(rule.clone(), name.to_string()) {
+            return Err(anyhow!("JSON schema names '{}' and '{}' both map to grammar rule '{}'", other, name, rule));
+        }
+        self.rules.insert(rule.clone(), body);
+        Ok(rule)
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' }).collect()
+}
+
+// GBNF string literal matching the compact JSON encoding of `value`
+fn json_literal(value: &Value) -> String {
+    let json = value.to_string();
+    let mut out = String::from("\"");
+    for c in json.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_and_match_gbnf() {
+        let grammar = Grammar::parse(
+            r#"
+            # yes/no answers, optionally shouted
+            root ::= answer "!"* ( " " [0-9]{1,3} )?
+            answer ::= "yes" | "no"
+            "#,
+        )
+        .unwrap();
+
+        assert!(grammar.matches("yes"));
+        assert!(grammar.matches("no!!! 42"));
+        assert!(!grammar.matches("maybe"));
+        assert!(!grammar.matches("yes 1234"));
+        assert!(!grammar.matches("ye"));
+
+        let state = grammar.start();
+        assert!(state.accepts(b"y") && state.accepts(b"no") && !state.accepts(b"x"));
+    }
+
+    #[test]
+    fn test_partial_utf8_across_tokens() {
+        let grammar = Grammar::parse(r#"root ::= "é" [^a]"#).unwrap();
+        let mut state = grammar.start();
+        assert!(state.advance(&[0xc3]));
+        assert!(!state.is_complete());
+        assert!(state.advance(&[0xa9, b'b']));
+        assert!(state.is_complete() && !state.can_continue());
+    }
+
+    #[test]
+    fn test_grammar_errors() {
+        assert!(Grammar::parse(r#"start ::= "a""#).unwrap_err().to_string().contains("root"));
+        assert!(Grammar::parse(r#"root ::= missing"#).unwrap_err().to_string().contains("missing"));
+        assert!(Grammar::parse(r#"root ::= "a"#).is_err());
+    }
+
+    #[test]
+    fn test_left_recursion_and_oversized_repeats_rejected() {
+        let invalid = |text: &str| {
+            let error = Grammar::parse(text).unwrap_err();
+            assert_eq!(Error::classify(&error, ErrorKind::Internal).kind(), ErrorKind::InvalidRequest, "{}", text);
+            error.to_string()
+        };
+        assert!(invalid(r#"root ::= root "a" | "a""#).contains("'root' is left-recursive"));
+        assert!(invalid("root ::= x\nx ::= y? x\ny ::= \"b\"").contains("'x' is left-recursive"));
+        assert!(invalid(r#"root ::= ( "a"? )*"#).contains("left-recursive"));
+        let right = Grammar::parse(r#"root ::= "a" root | "a""#).unwrap();
+        assert!(right.matches("aaa") && !right.matches(""));
+
+        assert!(invalid(r#"root ::= "a"{5000}"#).contains("limit"));
+        assert!(invalid(r#"root ::= "a"{3,2}"#).contains("bad repetition"));
+        assert!(invalid(r#"root ::= "a"{1000}{1000}"#).contains("does not follow a symbol"));
+        assert!(Grammar::parse(r#"root ::= ( "a"{2} ){3}"#).unwrap().matches("aaaaaa"));
+
+        let colliding = json!({"properties": {"a_b": {"enum": [1]}, "a-b": {"enum": [2]}}});
+        let error = Grammar::from_json_schema(&colliding).unwrap_err();
+        assert!(error.to_string().contains("'root-a-b'"), "{}", error);
+    }
+
+    #[test]
+    fn test_json_schema_grammar() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "reasoning": {"type": "string"},
+                "tool": {"enum": ["math", "shell"]},
+                "args": {"type": "array", "items": {"type": "string"}, "minItems": 1}
+            },
+            "required": ["tool", "args"]
+        });
+        let grammar = Grammar::from_json_schema(&schema).unwrap();
+
+        assert!(grammar.matches(r#"{"tool": "math", "args": ["2+2"]}"#));
+        assert!(grammar.matches(r#"{"tool":"shell","args":["ls","-la"],"reasoning":"list \"files\""}"#));
+        assert!(!grammar.matches(r#"{"tool": "fetch", "args": ["x"]}"#));
+        assert!(!grammar.matches(r#"{"tool": "math", "args": []}"#));
+        assert!(!grammar.matches(r#"{"args": ["2+2"], "tool": "math"}"#));
+
+        let numbers = Grammar::from_json_schema(&json!({"type": "array", "items": {"type": ["integer", "null"]}})).unwrap();
+        assert!(numbers.matches("[1, -20, null]"));
+        assert!(!numbers.matches("[1.5]"));
+    }
+}
@@ -1,228 +1,600 @@
+pub mod backend;
+pub mod batch;
+pub mod cancel;
+pub mod chat;
+pub mod embedding;
+pub mod error;
+pub mod gguf;
+pub mod grammar;
+pub mod logging;
+pub mod lora;
+pub mod model_data;
+pub mod models;
+pub mod native;
+pub mod prompt_cache;
+pub mod quant;
+pub mod registry;
+pub mod replay;
+pub mod sampling;
+pub mod server;
+pub mod tokenizer;
+
 pub use anyhow::{Context, Result};
 pub use serde::{Deserialize, Serialize};
 
+pub use batch::{BatchItem, BatchRequest, BatchResponse};
+pub use cancel::CancelToken;
+pub use chat::{ChatMessage, ChatTemplate};
+pub use embedding::{EmbeddingRequest, EmbeddingResponse, Pooling};
+pub use error::{Error, ErrorCategory, ErrorKind};
+pub use gguf::{GgmlType, GgufFile, GgufValue, ModelInfo, TensorInfo};
+pub use grammar::{Grammar, GrammarState};
+pub use logging::{LogConfig, LogFormat};
+pub use lora::{LoraAdapter, LoraInfo, LoraSelection, LoraSpec};
+pub use model_data::{LoadMode, ModelData, ModelMemory};
+pub use models::{CachedModel, ManifestEntry, ModelCache, ModelManifest, VerifyReport};
+pub use native::{GenerateOptions, LlamaConfig, NativeModel};
+pub use prompt_cache::{PromptCache, PromptCacheStats};
+pub use registry::{ModelRegistry, ModelSpec, ModelStatus, RegistryConfig};
+pub use backend::{
+    BackendKind, BackendOutput, DemoBackend, InferenceBackend, ModelFile, NativeBackend,
+    SimulationBackend, WasiNnBackend,
+};
+pub use replay::{PromptMatcher, RecordingBackend, ReplayBackend, ReplayFixture, ReplayRule};
+pub use sampling::{Sampler, SamplingParams, TokenLogprob, TopLogprob};
+pub use server::{ControlCommand, ControlRequest, ControlResponse};
+pub use tokenizer::{SpecialTokens, Tokenizer, TokenizerKind};
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 // WASI-NN imports for neural network inference
 pub use wasi_nn::{ExecutionTarget, GraphBuilder, GraphEncoding, TensorType};
 
-#[derive(Debug, Deserialize)]
+// Completion budget when a request does not set max_tokens
+pub const DEFAULT_MAX_TOKENS: u32 = 100;
+
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct InferenceRequest {
+    // Caller-chosen id, echoed in the response, stream events and errors
+    pub id: Option<String>,
+    // Registry model to route the request to; the default model if unset
+    pub model: Option<String>,
     pub prompt: String,
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f32>,
+    pub min_p: Option<f32>,
+    pub repeat_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    // Fixed RNG seed for reproducible sampling; one is chosen and echoed back if unset
+    pub seed: Option<u64>,
+    // Generation ends before the first occurrence of any of these
+    #[serde(default)]
+    pub stop: Vec<String>,
+    // Constrain the output to a GBNF grammar or to JSON matching a schema. The native and
+    // WASI-NN backends enforce this while sampling; the others ignore it.
+    pub grammar: Option<String>,
+    pub json_schema: Option<serde_json::Value>,
+    // Return each completion token's log probability with this many likeliest alternatives
+    // (0 for none). Only the native backend reports them.
+    pub logprobs: Option<usize>,
+    // LoRA adapters to apply, by name and optional scale, instead of the active ones;
+    // an empty list runs the base model. Only the native backend applies adapters.
+    pub lora: Option<Vec<LoraSelection>>,
+    // Emit newline-delimited token events before the final response
+    #[serde(default)]
+    pub stream: bool,
+    // Stop generating this long after the request starts, keeping what was produced
+    pub timeout_ms: Option<u64>,
+    // Absolute form of `timeout_ms`, which the engine fills in when unset
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+    #[serde(skip)]
+    pub cancel: Option<CancelToken>,
+}
+
+impl InferenceRequest {
+    // Why generation has to stop now, if it was cancelled or ran out of time
+    pub fn interrupted(&self) -> Option<FinishReason> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Some(FinishReason::Cancelled);
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Some(FinishReason::Deadline),
+            _ => None,
+        }
+    }
+}
+
+// Why a completion ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    // End of sequence, or a grammar that cannot continue
+    #[default]
+    Stop,
+    // max_tokens or the context window ran out
+    Length,
+    // One of the request's stop sequences was generated; it is not part of the output
+    StopSequence,
+    // The request's cancel token was set; the output is what came before
+    Cancelled,
+    // The request's deadline passed; the output is what came before
+    Deadline,
+    // The selected backend failed and the output is the demo fallback's
+    Error,
+}
+
+// Where the time of a request went, in milliseconds
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Timings {
+    // Opening the model file and setting up the backend, paid once per loaded model
+    pub load_ms: u64,
+    // Processing the prompt, for backends that report it; otherwise counted as generation
+    pub prompt_eval_ms: u64,
+    pub generation_ms: u64,
+    pub total_ms: u64,
+    // Completion tokens per second of generation time
+    pub tokens_per_second: f64,
 }
 
 #[derive(Debug, Serialize)]
 pub struct InferenceResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub response: String,
+    pub prompt_tokens: u32,
+    // Leading prompt tokens reused from the prompt cache instead of being processed
+    pub cached_prompt_tokens: u32,
     pub tokens_generated: u32,
+    pub finish_reason: FinishReason,
+    pub timings: Timings,
     pub model_info: String,
+    // The backend that produced the response: the demo backend after a fallback
+    pub backend: String,
+    // Why the selected backend failed, when finish_reason is "error"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend_error: Option<String>,
+    pub sampling: SamplingParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Vec<TokenLogprob>>,
 }
 
+// One line of a streamed response: token events as text is produced, then
+// a `done` event carrying the full InferenceResponse
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StreamEvent {
+    Token {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        index: usize,
+        text: String,
+    },
+    Done(Box<InferenceResponse>),
+}
+
+// Error line on stdout: the request id, if known, and the error's message, code, kind,
+// category and details
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
-    pub error: String,
-    pub code: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub error: Error,
 }
 
 pub struct SuperTinyWasmLLM {
     model_path: String,
     model_loaded: bool,
-    model_data: Option<Vec<u8>>,
+    model: Option<ModelFile>,
+    model_info: Option<ModelInfo>,
+    tokenizer: Option<Tokenizer>,
+    backend_kind: BackendKind,
+    backend: Option<Box<dyn InferenceBackend>>,
+    record_fixture: Option<String>,
+    chat_template: Option<ChatTemplate>,
+    load_mode: LoadMode,
+    context_length: Option<usize>,
+    lora: Vec<LoraSpec>,
+    load_time: Duration,
 }
 
 impl SuperTinyWasmLLM {
     pub fn new(model_path: String) -> Self {
+        let backend_kind = BackendKind::from_env().unwrap_or_else(|e| {
+            log::warn!("{}, using auto backend selection", e);
+            BackendKind::Auto
+        });
+
         Self {
             model_path,
             model_loaded: false,
-            model_data: None,
+            model: None,
+            model_info: None,
+            tokenizer: None,
+            backend_kind,
+            backend: None,
+            record_fixture: std::env::var(replay::RECORD_FIXTURE_ENV_VAR).ok(),
+            chat_template: ChatTemplate::from_env().unwrap_or_else(|e| {
+                log::warn!("{}, using the model's chat template", e);
+                None
+            }),
+            load_mode: LoadMode::from_env().unwrap_or_else(|e| {
+                log::warn!("{}, memory-mapping the model", e);
+                LoadMode::Mmap
+            }),
+            context_length: None,
+            lora: Vec::new(),
+            load_time: Duration::ZERO,
         }
     }
 
+    // Select a built-in backend instead of the SUPERTINYWASMLLM_BACKEND setting
+    pub fn with_backend_kind(mut self, kind: BackendKind) -> Self {
+        self.backend_kind = kind;
+        self
+    }
+
+    // Use a custom backend; it is handed the parsed model file on load_model
+    pub fn with_backend(mut self, backend: Box<dyn InferenceBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    // Record every prompt/response pair into a replay fixture at `path`
+    pub fn with_record_fixture(mut self, path: &str) -> Self {
+        self.record_fixture = Some(path.to_string());
+        self
+    }
+
+    // Use this chat template instead of the one named in the model metadata
+    pub fn with_chat_template(mut self, template: ChatTemplate) -> Self {
+        self.chat_template = Some(template);
+        self
+    }
+
+    // Memory-map the model file or read it whole, instead of the SUPERTINYWASMLLM_LOAD_MODE setting
+    pub fn with_load_mode(mut self, mode: LoadMode) -> Self {
+        self.load_mode = mode;
+        self
+    }
+
+    // Limit prompts plus completions to `tokens`, below the model's own context length
+    pub fn with_context_length(mut self, tokens: usize) -> Self {
+        self.context_length = Some(tokens);
+        self
+    }
+
+    // Load a LoRA adapter on top of the model; active ones apply to every request that
+    // does not select its own
+    pub fn with_lora(mut self, spec: LoraSpec) -> Self {
+        self.lora.push(spec);
+        self
+    }
+
     pub fn load_model(&mut self) -> Result<()> {
-        // If model path is empty or doesn't exist, use native simulation mode
-        if self.model_path.is_empty() || !std::path::Path::new(&self.model_path).exists() {
-            println!("Model path empty or file not found: '{}', using native simulation mode", self.model_path);
-            self.model_loaded = true;
-            return Ok(());
+        let started = Instant::now();
+        match read_model(&self.model_path, self.load_mode)? {
+            Some(loaded) => self.set_model(loaded),
+            // If model path is empty or doesn't exist, run the backend without a model file
+            None => log::info!("Model path empty or file not found: '{}', running without a model file", self.model_path),
         }
-        
-        println!("Loading model from: {}", self.model_path);
-        
-        // Read model file
-        let model_data = std::fs::read(&self.model_path)
-            .with_context(|| format!("Failed to read model file: {}", self.model_path))?;
-        
-        println!("Model size: {} bytes", model_data.len());
-        
-        // Basic GGUF format validation
-        if model_data.len() < 4 {
-            return Err(anyhow::anyhow!("Model file too small"));
+
+        self.install_backend()?;
+        self.model_loaded = true;
+        self.load_time = started.elapsed();
+        if let Some(memory) = self.memory_usage() {
+            log::info!(
+                "Model loaded successfully ({}, {} of {} bytes resident)",
+                memory.load_mode.as_str(),
+                memory.resident_bytes.map_or_else(|| "unknown".to_string(), |n| n.to_string()),
+                memory.file_bytes
+            );
         }
         
-        // Check for GGUF magic number (simplified)
-        if model_data.starts_with(b"GGUF") || model_data.len() > 1000000 {
-            println!("Detected GGUF format model");
-        } else {
-            println!("Warning: Model format may not be GGUF");
+        Ok(())
+    }
+
+    // Re-read the model file, optionally from a new path, into the running backend. On
+    // failure the previously loaded model stays in service.
+    pub fn reload_model(&mut self, model_path: Option<&str>) -> Result<()> {
+        if !self.model_loaded {
+            if let Some(path) = model_path {
+                self.model_path = path.to_string();
+            }
+            return self.load_model();
         }
-        
-        self.model_data = Some(model_data);
-        self.model_loaded = true;
-        println!("Model loaded successfully!");
-        
+
+        let path = model_path.unwrap_or(&self.model_path).to_string();
+        if path.is_empty() {
+            log::info!("No model path configured, nothing to reload");
+            return Ok(());
+        }
+        let started = Instant::now();
+        let loaded = read_model(&path, self.load_mode)?.ok_or_else(|| {
+            Error::new(ErrorKind::ModelNotFound, format!("Model file not found: '{}'", path)).with_detail("path", &path)
+        })?;
+        if let Some(backend) = self.backend.as_mut() {
+            backend.load(&loaded.0)?;
+            // The new weights start without adapters
+            load_lora(backend.as_mut(), &self.lora)?;
+        }
+        self.model_path = path;
+        self.set_model(loaded);
+        self.load_time = started.elapsed();
+        log::info!("Model reloaded successfully");
         Ok(())
     }
 
-    pub fn generate_response(&self, request: &InferenceRequest) -> Result<InferenceResponse> {
-        println!("Generating response for prompt: '{}'", request.prompt);
-        
-        // Try WASI-NN inference first
-        match self.try_wasi_nn_inference(request) {
-            Ok(response) => {
-                let tokens_count = response.split_whitespace().count() as u32;
-                println!("Generated response via WASI-NN: '{}'", response);
-                
-                Ok(InferenceResponse {
-                    response,
-                    tokens_generated: tokens_count,
-                    model_info: format!("SuperTinyWasmLLM v0.1.0 - Model: {} (WASI-NN)", 
-                                      std::path::Path::new(&self.model_path).file_name()
-                                        .unwrap_or_default().to_string_lossy()),
-                })
+    fn set_model(&mut self, (model, model_info, tokenizer): (ModelFile, ModelInfo, Option<Tokenizer>)) {
+        self.model = Some(model);
+        self.model_info = Some(model_info);
+        self.tokenizer = tokenizer;
+        log::info!("Chat template: {}", self.chat_template().as_str());
+    }
+
+    fn install_backend(&mut self) -> Result<()> {
+        match self.backend.as_mut() {
+            Some(custom) => {
+                if let Some(model) = &self.model {
+                    custom.load(model)?;
+                }
             }
-            Err(e) => {
-                println!("WASI-NN inference failed: {}, falling back to demo mode", e);
-                self.generate_demo_response(request)
+            None => self.backend = Some(self.backend_kind.create(self.model.as_ref())?),
+        }
+        if let Some(backend) = self.backend.as_mut() {
+            load_lora(backend.as_mut(), &self.lora)?;
+        }
+
+        // Capture prompt/response pairs into a replay fixture when requested
+        if let Some(path) = &self.record_fixture {
+            if let Some(inner) = self.backend.take() {
+                log::info!("Recording inference calls to {}", path);
+                self.backend = Some(Box::new(RecordingBackend::new(inner, path)?));
             }
         }
+
+        log::info!("Inference backend: {}", self.backend_name().unwrap_or("none"));
+        Ok(())
     }
 
-    fn try_wasi_nn_inference(&self, request: &InferenceRequest) -> Result<String> {
-        #[cfg(target_family = "wasm")]
-        {
-            // WASI-NN graph initialization using autodetect backend
-            let graph = GraphBuilder::new(GraphEncoding::Autodetec, ExecutionTarget::AUTO)
-                .build_from_cache("default")?;
-            
-            let mut context = graph.init_execution_context()?;
-
-            // Prepare input prompt
-            let prompt = &request.prompt;
-            let tensor_data = prompt.as_bytes().to_vec();
-            
-            // Set input tensor with dimensions [1]
-            context.set_input(0, TensorType::U8, &[1], &tensor_data)?;
-            
-            // Execute inference
-            context.compute()?;
-            
-            // Get output with larger buffer for safety
-            let max_tokens = request.max_tokens.unwrap_or(100);
-            let mut output_buffer = vec![0u8; (max_tokens * 10) as usize];
-            let output_size = context.get_output(0, &mut output_buffer)?;
-            
-            // Bounds check
-            let safe_output_size = output_size.min(output_buffer.len());
-            
-            // Convert output to text
-            let response_text = String::from_utf8_lossy(&output_buffer[..safe_output_size]).to_string();
-            
-            // Clean and trim response
-            let cleaned_response = response_text.trim().to_string();
-            
-            Ok(cleaned_response)
+    pub fn generate_response(&self, request: &InferenceRequest) -> Result<InferenceResponse> {
+        self.generate_response_stream(request, &mut |_| {})
+    }
+
+    // Answer every item of a batch, in order. Backends that cache prompt state process
+    // the prefix shared by all prompts once; a failed item does not stop the others.
+    pub fn generate_batch(&self, batch: &BatchRequest) -> BatchResponse {
+        let prompts: Vec<&str> = batch.items.iter().flatten().map(|request| request.prompt.as_str()).collect();
+        if let (true, Some(backend)) = (prompts.len() > 1, &self.backend) {
+            backend.prepare_batch(&prompts);
         }
-        
-        #[cfg(not(target_family = "wasm"))]
-        {
-            // Native mode: simulate intelligent response based on prompt analysis
-            let prompt = &request.prompt.to_lowercase();
-            
-            // Extract available tools from prompt (system prompt includes tool list)
-            let math_tool = if prompt.contains("math-native") {
-                "math-native"
-            } else if prompt.contains("- math:") {
-                "math"
-            } else {
-                "math-native" // Default to math-native if available
-            };
-            
-            let fetch_tool = if prompt.contains("fetch-native") {
-                "fetch-native"
-            } else {
-                "fetch"
-            };
-            
-            let shell_tool = if prompt.contains("shell-native") {
-                "shell-native"
-            } else {
-                "shell"
-            };
-            
-            if prompt.contains("2+2") || prompt.contains("2 + 2") {
-                Ok(format!(r#"{{"tool": "{}", "args": ["2+2"], "reasoning": "Simple addition calculation"}}"#, math_tool))
-            } else if prompt.contains("5*7") || prompt.contains("5 * 7") {
-                Ok(format!(r#"{{"tool": "{}", "args": ["5*7"], "reasoning": "Multiplication calculation"}}"#, math_tool))
-            } else if prompt.contains("4*5") || prompt.contains("4 * 5") {
-                Ok(format!(r#"{{"tool": "{}", "args": ["4*5"], "reasoning": "Multiplication calculation"}}"#, math_tool))
-            } else if prompt.contains("3*7") || prompt.contains("3 * 7") {
-                Ok(format!(r#"{{"tool": "{}", "args": ["3*7"], "reasoning": "Multiplication calculation"}}"#, math_tool))
-            } else if prompt.contains("math") && (prompt.contains("+") || prompt.contains("*") || prompt.contains("-") || prompt.contains("/")) {
-                // Extract simple math expression from user task
-                if let Some(task_start) = prompt.find("current_task:") {
-                    let task_part = &prompt[task_start..];
-                    if let Some(task_line) = task_part.lines().next() {
-                        let task = task_line.replace("current_task:", "").trim().to_string();
-                        if task.chars().any(|c| "+-*/".contains(c)) && task.len() < 20 {
-                            return Ok(format!(r#"{{"tool": "{}", "args": ["{}"], "reasoning": "Detected math expression"}}"#, math_tool, task));
-                        }
-                    }
-                }
-                
-                // Fallback: extract from user query
-                if let Some(user_start) = prompt.rfind("user:") {
-                    let user_part = &prompt[user_start + 5..].trim();
-                    if let Some(user_line) = user_part.lines().next() {
-                        let user_query = user_line.trim();
-                        if user_query.chars().any(|c| "+-*/".contains(c)) && user_query.len() < 20 {
-                            return Ok(format!(r#"{{"tool": "{}", "args": ["{}"], "reasoning": "Math operation requested"}}"#, math_tool, user_query));
-                        }
+
+        let results = batch
+            .items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let request = match item {
+                    Ok(request) => request,
+                    Err(error) => {
+                        return BatchItem::Error(ErrorResponse { id: None, error: error.clone().with_detail("index", index) })
                     }
+                };
+                match self.generate_response(request) {
+                    Ok(response) => BatchItem::Response(Box::new(response)),
+                    Err(e) => BatchItem::Error(ErrorResponse {
+                        id: request.id.clone(),
+                        error: Error::classify(&e.context("Inference failed"), ErrorKind::InferenceFailed)
+                            .with_detail("index", index),
+                    }),
                 }
-                
-                Ok(format!(r#"{{"tool": "{}", "args": ["calculation"], "reasoning": "Math operation requested"}}"#, math_tool))
-            } else if prompt.contains("http") {
-                Ok(format!(r#"{{"tool": "{}", "args": ["get", "http://example.com"], "reasoning": "HTTP request detected"}}"#, fetch_tool))
-            } else if prompt.contains("list") || prompt.contains("files") {
-                Ok(format!(r#"{{"tool": "{}", "args": ["ls", "-la"], "reasoning": "File listing requested"}}"#, shell_tool))
-            } else {
-                // Generic response
-                Ok(format!("I understand you want to: {}. Let me help with that.", request.prompt))
+            })
+            .collect();
+        BatchResponse { id: batch.id.clone(), results }
+    }
+
+    // Generate a response, handing each piece of text to `on_token` as the backend produces it
+    pub fn generate_response_stream(
+        &self,
+        request: &InferenceRequest,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<InferenceResponse> {
+        log::debug!("Generating response for prompt: '{}'", request.prompt);
+
+        let backend = self.backend.as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::ModelNotLoaded, "Model not loaded. Call load_model() first."))?;
+        // Reject a bad grammar here rather than letting it trigger the demo fallback
+        Grammar::from_request(request).map_err(|e| Error::new(ErrorKind::InvalidRequest, format!("{:#}", e)))?;
+        // And sampling options the backend would silently ignore
+        backend.check_request(request).map_err(|e| Error::new(ErrorKind::InvalidRequest, format!("{:#}", e)))?;
+        // Likewise an adapter the backend does not have
+        let adapters = backend.lora_adapters();
+        for selection in request.lora.iter().flatten() {
+            if !adapters.iter().any(|adapter| adapter.name == selection.name) {
+                let available: Vec<&str> = adapters.iter().map(|adapter| adapter.name.as_str()).collect();
+                return Err(Error::new(ErrorKind::InvalidRequest, format!("Unknown LoRA adapter '{}'", selection.name))
+                    .with_detail("available", available.join(", "))
+                    .into());
+            }
+        }
+
+        // Reject prompts that do not fit and keep the completion inside the context window
+        let prompt_tokens = self.count_prompt_tokens(&request.prompt);
+        let mut request = request.clone();
+        // Fix the seed up front so every backend uses, and the response reports, the same one
+        request.seed = Some(SamplingParams::from_request(&request).seed);
+        if let Some(context_length) = self.context_length() {
+            if prompt_tokens >= context_length {
+                return Err(Error::new(
+                    ErrorKind::ContextLengthExceeded,
+                    format!("Prompt is {} tokens, model context length is {}", prompt_tokens, context_length),
+                )
+                .with_detail("prompt_tokens", prompt_tokens)
+                .with_detail("context_length", context_length)
+                .into());
+            }
+            let available = (context_length - prompt_tokens) as u32;
+            request.max_tokens = Some(request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS).min(available));
+        }
+        if let (None, Some(timeout)) = (request.deadline, request.timeout_ms) {
+            request.deadline = Some(Instant::now() + Duration::from_millis(timeout));
+        }
+        // Nothing to generate for a request cancelled before it started
+        if let Some(reason) = request.interrupted() {
+            let output = BackendOutput { finish_reason: reason, ..Default::default() };
+            return Ok(self.build_response(output, &request, prompt_tokens, backend.name(), Duration::ZERO));
+        }
+
+        let started = Instant::now();
+        let mut filter = sampling::StopFilter::new(&request.stop);
+        let mut emitted = false;
+        let mut forward = |text: &str| {
+            emitted = true;
+            on_token(text);
+        };
+        let result = backend.generate_stream(&request, &mut |piece| filter.push(piece, &mut forward));
+        filter.finish(&mut forward);
+
+        match result {
+            Ok(output) => {
+                log::debug!("Generated response via {}: '{}'", backend.name(), output.text);
+                Ok(self.build_response(output, &request, prompt_tokens, backend.name(), started.elapsed()))
+            }
+            // Demo text must not follow tokens a streaming client already has from the real model
+            Err(e) if emitted && request.stream => Err(Error::new(
+                ErrorKind::InferenceFailed,
+                format!("{} inference failed after streaming output: {:#}", backend.name(), e),
+            )
+            .with_detail("backend", backend.name())
+            .into()),
+            Err(e) => {
+                log::warn!("{} inference failed: {}, falling back to demo mode", backend.name(), e);
+                let demo = DemoBackend;
+                let started = Instant::now();
+                let mut filter = sampling::StopFilter::new(&request.stop);
+                let output = demo
+                    .generate_stream(&request, &mut |piece| filter.push(piece, on_token))
+                    .map_err(|e| Error::new(ErrorKind::InferenceFailed, format!("{:#}", e)))?;
+                filter.finish(on_token);
+                log::debug!("Generated response: '{}'", output.text);
+                // Reported as a failure of the selected backend, not a normal completion
+                let mut response = self.build_response(output, &request, prompt_tokens, demo.name(), started.elapsed());
+                response.finish_reason = FinishReason::Error;
+                response.backend_error = Some(format!("{}: {:#}", backend.name(), e));
+                Ok(response)
             }
         }
     }
 
-    fn generate_demo_response(&self, request: &InferenceRequest) -> Result<InferenceResponse> {
-        // Demo mode fallback when WASI-NN is not available
-        let demo_response = format!("{} [Demo mode: max_tokens={}, temperature={}]", 
-                                   request.prompt,
-                                   request.max_tokens.unwrap_or(50),
-                                   request.temperature.unwrap_or(0.7));
+    // Embed every input text with the loaded model, or hashed vectors where the backend
+    // has no model to take them from
+    pub fn embed(&self, request: &EmbeddingRequest) -> Result<EmbeddingResponse> {
+        let backend = self.backend.as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::ModelNotLoaded, "Model not loaded. Call load_model() first."))?;
+        if request.input.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidRequest, "Embedding request has no input texts").into());
+        }
 
-        println!("Generated response: '{}'", demo_response);
+        let mut prompt_tokens = 0;
+        for (index, text) in request.input.iter().enumerate() {
+            let tokens = self.count_prompt_tokens(text);
+            if let Some(context_length) = self.context_length().filter(|&n| tokens > n) {
+                return Err(Error::new(
+                    ErrorKind::ContextLengthExceeded,
+                    format!("Input {} is {} tokens, model context length is {}", index, tokens, context_length),
+                )
+                .with_detail("input_index", index)
+                .with_detail("prompt_tokens", tokens)
+                .with_detail("context_length", context_length)
+                .into());
+            }
+            prompt_tokens += tokens;
+        }
 
-        let tokens_count = demo_response.len() as u32;
+        let mut embeddings = backend.embed(&request.input, request.pooling)
+            .map_err(|e| Error::new(ErrorKind::InferenceFailed, format!("{:#}", e)))?;
+        if request.normalize {
+            embeddings.iter_mut().for_each(|vector| embedding::normalize(vector));
+        }
+        log::debug!("Embedded {} texts via {}", embeddings.len(), backend.name());
 
-        Ok(InferenceResponse {
-            response: demo_response,
-            tokens_generated: tokens_count,
-            model_info: format!("SuperTinyWasmLLM v0.1.0 - Model: {} (Demo)", 
-                              std::path::Path::new(&self.model_path).file_name()
-                                .unwrap_or_default().to_string_lossy()),
+        Ok(EmbeddingResponse {
+            id: request.id.clone(),
+            dimensions: embeddings.first().map_or(0, Vec::len),
+            embeddings,
+            prompt_tokens: prompt_tokens as u32,
+            model_info: format!("SuperTinyWasmLLM v0.1.0 - Model: {} ({})", self.model_description(), backend.name()),
+            backend: backend.name().to_string(),
         })
     }
 
+    fn build_response(
+        &self,
+        output: BackendOutput,
+        request: &InferenceRequest,
+        prompt_tokens: usize,
+        backend: &str,
+        elapsed: Duration,
+    ) -> InferenceResponse {
+        let mut text = output.text;
+        let mut finish_reason = output.finish_reason;
+        // Backends that cannot stop early still have their output cut at the stop sequence
+        if let Some(at) = sampling::find_stop(&text, &request.stop) {
+            text.truncate(at);
+            finish_reason = FinishReason::StopSequence;
+        }
+        let mut completion_tokens = output.tokens_generated.map(|n| n as usize);
+
+        // Backends that do not tokenize can overshoot max_tokens; cut them back to the budget
+        if let (None, Some(tokenizer), Some(max_tokens)) = (completion_tokens, &self.tokenizer, request.max_tokens) {
+            let ids = tokenizer.encode(&text, false);
+            if ids.len() > max_tokens as usize {
+                text = tokenizer.decode(&ids[..max_tokens as usize]);
+                completion_tokens = Some(max_tokens as usize);
+                finish_reason = FinishReason::Length;
+            }
+        }
+        let completion_tokens = completion_tokens.unwrap_or_else(|| self.count_tokens(&text));
+        // Backends that only produce text cannot tell a full budget from a natural end
+        if output.tokens_generated.is_none()
+            && finish_reason == FinishReason::Stop
+            && completion_tokens >= request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS) as usize
+        {
+            finish_reason = FinishReason::Length;
+        }
+
+        let prompt_eval = output.prompt_eval.unwrap_or_default().min(elapsed);
+        let generation = elapsed - prompt_eval;
+        let timings = Timings {
+            load_ms: self.load_time.as_millis() as u64,
+            prompt_eval_ms: prompt_eval.as_millis() as u64,
+            generation_ms: generation.as_millis() as u64,
+            total_ms: elapsed.as_millis() as u64,
+            tokens_per_second: if generation.is_zero() { 0.0 } else { completion_tokens as f64 / generation.as_secs_f64() },
+        };
+
+        InferenceResponse {
+            id: request.id.clone(),
+            response: text,
+            prompt_tokens: prompt_tokens as u32,
+            cached_prompt_tokens: output.cached_prompt_tokens,
+            tokens_generated: completion_tokens as u32,
+            finish_reason,
+            timings,
+            model_info: format!("SuperTinyWasmLLM v0.1.0 - Model: {} ({})", self.model_description(), backend),
+            backend: backend.to_string(),
+            backend_error: None,
+            sampling: SamplingParams::from_request(request),
+            logprobs: output.logprobs,
+        }
+    }
+
     pub fn is_loaded(&self) -> bool {
         self.model_loaded
     }
@@ -230,16 +602,499 @@ impl SuperTinyWasmLLM {
     pub fn model_path(&self) -> &str {
         &self.model_path
     }
+
+    // Time the last load or reload took
+    pub fn load_time(&self) -> Duration {
+        self.load_time
+    }
+
+    pub fn model_info(&self) -> Option<&ModelInfo> {
+        self.model_info.as_ref()
+    }
+
+    pub fn backend_name(&self) -> Option<&str> {
+        self.backend.as_ref().map(|b| b.name())
+    }
+
+    // LoRA adapters loaded on top of the model
+    pub fn lora_adapters(&self) -> Vec<LoraInfo> {
+        self.backend.as_ref().map(|b| b.lora_adapters()).unwrap_or_default()
+    }
+
+    // Prompt cache hits, misses and estimated time saved; None if the backend has no cache
+    pub fn prompt_cache_stats(&self) -> Option<PromptCacheStats> {
+        self.backend.as_ref().and_then(|b| b.prompt_cache_stats())
+    }
+
+    // How much of the model file is in RAM, and the process total; None without a model
+    pub fn memory_usage(&self) -> Option<ModelMemory> {
+        self.model.as_ref().map(|model| model.data.memory_report())
+    }
+
+    pub fn tokenizer(&self) -> Option<&Tokenizer> {
+        self.tokenizer.as_ref()
+    }
+
+    // An explicitly chosen template, else the one the model metadata describes, else plain
+    pub fn chat_template(&self) -> ChatTemplate {
+        self.chat_template
+            .or_else(|| {
+                let jinja = self.model.as_ref()?.gguf.get_str(chat::CHAT_TEMPLATE_METADATA_KEY)?;
+                ChatTemplate::detect(jinja)
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn context_length(&self) -> Option<usize> {
+        let model = self.model_info.as_ref().and_then(|info| info.context_length).map(|n| n as usize);
+        match (self.context_length, model) {
+            (Some(limit), Some(model)) => Some(limit.min(model)),
+            (limit, model) => limit.or(model),
+        }
+    }
+
+    // Tokens in `text` under the model vocabulary, or an estimate without one
+    pub fn count_tokens(&self, text: &str) -> usize {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.count(text, false),
+            None => tokenizer::estimate_tokens(text),
+        }
+    }
+
+    // Like `count_tokens`, including the BOS/EOS the model adds around a prompt
+    fn count_prompt_tokens(&self, prompt: &str) -> usize {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.count(prompt, true),
+            None => tokenizer::estimate_tokens(prompt),
+        }
+    }
+
+    fn model_description(&self) -> String {
+        match &self.model_info {
+            Some(info) => info.to_string(),
+            None => std::path::Path::new(&self.model_path).file_name()
+                .unwrap_or_default().to_string_lossy().to_string(),
+        }
+    }
 }
 
-pub fn send_error_response(error: &str, code: u32) -> Result<()> {
-    let error_response = ErrorResponse {
-        error: error.to_string(),
-        code,
+// Open and validate a GGUF model file; None if `path` is empty or missing. Only the
+// header, metadata and tensor table are read here; tensor data stays on disk until
+// a backend touches it when the file is mapped.
+fn read_model(path: &str, mode: LoadMode) -> Result<Option<(ModelFile, ModelInfo, Option<Tokenizer>)>> {
+    if path.is_empty() || !std::path::Path::new(path).exists() {
+        return Ok(None);
+    }
+
+    log::info!("Loading model from: {}", path);
+    
+    let model_data = ModelData::open(path, mode).map_err(|e| load_failed(e, path))?;
+    
+    log::info!(
+        "Model size: {} bytes ({})",
+        model_data.len(),
+        if model_data.is_mapped() { "memory-mapped" } else { "read into memory" }
+    );
+    
+    // Parse GGUF header, metadata and tensor table
+    let gguf = GgufFile::parse(&model_data)
+        .with_context(|| format!("Invalid GGUF model file: {}", path))
+        .map_err(|e| load_failed(e, path))?;
+
+    for tensor in &gguf.tensors {
+        gguf.tensor_range(tensor, model_data.len() as u64).map_err(|e| load_failed(e, path))?;
+    }
+
+    let file_name = std::path::Path::new(path).file_name()
+        .unwrap_or_default().to_string_lossy().to_string();
+    let model_info = ModelInfo::from_gguf(&gguf, &file_name, model_data.len() as u64);
+
+    log::info!("Detected GGUF v{} model: {} tensors, {} metadata entries",
+             model_info.gguf_version, model_info.tensor_count, model_info.metadata_count);
+    log::info!("Architecture: {}, quantization: {}",
+             model_info.architecture.as_deref().unwrap_or("unknown"),
+             model_info.quantization.as_deref().unwrap_or("unknown"));
+
+    // Token accounting falls back to an estimate if the vocabulary is unusable
+    let tokenizer = match Tokenizer::from_gguf(&gguf) {
+        Ok(tokenizer) => {
+            log::info!("Tokenizer: {:?}, {} tokens", tokenizer.kind(), tokenizer.vocab_size());
+            Some(tokenizer)
+        }
+        Err(e) => {
+            log::warn!("No usable tokenizer in model ({}), estimating token counts", e);
+            None
+        }
     };
+
+    let model = ModelFile {
+        path: path.to_string(),
+        gguf,
+        data: Arc::new(model_data),
+    };
+    Ok(Some((model, model_info, tokenizer)))
+}
+
+// Read every adapter file and hand them to the backend; nothing to do without any
+fn load_lora(backend: &mut dyn InferenceBackend, specs: &[LoraSpec]) -> Result<()> {
+    if specs.is_empty() {
+        return Ok(());
+    }
+    let adapters = specs.iter().map(LoraAdapter::load).collect::<Result<Vec<_>>>()?;
+    backend.load_lora(adapters).map_err(|e| Error::classify(&e, ErrorKind::ModelLoadFailed).into())
+}
+
+fn load_failed(error: anyhow::Error, path: &str) -> Error {
+    Error::new(ErrorKind::ModelLoadFailed, format!("{:#}", error)).with_detail("path", path)
+}
+
+// Print `event` as one JSON line, flushing so readers see tokens immediately
+pub fn send_stream_event(event: &StreamEvent) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, event)?;
+    writeln!(stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+pub fn send_error_response(id: Option<String>, error: Error) -> Result<()> {
+    let error_response = ErrorResponse { id, error };
     
     let json_response = serde_json::to_string(&error_response)?;
     println!("{}", json_response);
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::tiny_llama;
+
+    // Tiny llama (context 64) written to a temp file and served by the given backend
+    fn load(kind: BackendKind, name: &str) -> SuperTinyWasmLLM {
+        let path = std::env::temp_dir().join(format!("tinyedge-{}-{}.gguf", name, std::process::id()));
+        std::fs::write(&path, tiny_llama(false)).unwrap();
+        let mut llm = SuperTinyWasmLLM::new(path.to_string_lossy().to_string()).with_backend_kind(kind);
+        llm.load_model().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        llm
+    }
+
+    fn request(prompt: &str, max_tokens: Option<u32>) -> InferenceRequest {
+        InferenceRequest {
+            prompt: prompt.to_string(),
+            max_tokens,
+            temperature: Some(0.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_chat_template_from_metadata_or_override() {
+        // The fixture carries a TinyLlama-style template
+        assert_eq!(load(BackendKind::Demo, "chat").chat_template(), ChatTemplate::Zephyr);
+
+        let path = std::env::temp_dir().join(format!("tinyedge-chat-override-{}.gguf", std::process::id()));
+        std::fs::write(&path, tiny_llama(false)).unwrap();
+        let mut llm = SuperTinyWasmLLM::new(path.to_string_lossy().to_string()).with_chat_template(ChatTemplate::ChatMl);
+        llm.load_model().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(llm.chat_template(), ChatTemplate::ChatMl);
+
+        assert_eq!(SuperTinyWasmLLM::new(String::new()).chat_template(), ChatTemplate::Plain);
+    }
+
+    #[test]
+    fn test_token_accounting_uses_model_vocabulary() {
+        let llm = load(BackendKind::Demo, "accounting");
+        assert_eq!(llm.count_tokens("ab b"), 2);
+
+        // The demo echo is far longer than 3 tokens, so it is cut back to the budget
+        let response = llm.generate_response(&request("ab b", Some(3))).unwrap();
+        assert_eq!(response.prompt_tokens, 3);
+        assert_eq!(response.tokens_generated, 3);
+        assert_eq!(response.response, "ab b ");
+    }
+
+    #[test]
+    fn test_stream_events() {
+        let llm = load(BackendKind::Native, "stream");
+
+        let mut pieces = Vec::new();
+        let response = llm
+            .generate_response_stream(&request("ab", Some(8)), &mut |piece| pieces.push(piece.to_string()))
+            .unwrap();
+        assert_eq!(pieces.concat(), response.response);
+
+        let token = StreamEvent::Token { id: None, index: 0, text: "ab".to_string() };
+        assert_eq!(serde_json::to_string(&token).unwrap(), r#"{"event":"token","index":0,"text":"ab"}"#);
+        let done = serde_json::to_value(StreamEvent::Done(Box::new(response))).unwrap();
+        assert_eq!(done["event"], "done");
+        assert_eq!(done["prompt_tokens"], 2);
+        assert_eq!(done["cached_prompt_tokens"], 0);
+        assert!(done["timings"]["tokens_per_second"].as_f64().unwrap() > 0.0);
+
+        // The same prompt again resumes from its cached prefix
+        let again = llm.generate_response(&request("ab", Some(8))).unwrap();
+        assert_eq!(again.cached_prompt_tokens, 1);
+        assert_eq!(llm.prompt_cache_stats().map(|s| s.hits), Some(1));
+    }
+
+    #[test]
+    fn test_cancel_and_deadline_keep_partial_output() {
+        let llm = load(BackendKind::Native, "cancel");
+        let free = llm.generate_response(&request("ab", Some(16))).unwrap();
+        assert_eq!(free.finish_reason, FinishReason::Length);
+
+        // Cancelling from the token callback stops before the next token
+        let cancel = CancelToken::new();
+        let mut pieces = 0;
+        let cancelled = llm
+            .generate_response_stream(&InferenceRequest { cancel: Some(cancel.clone()), ..request("ab", Some(16)) }, &mut |_| {
+                pieces += 1;
+                cancel.cancel();
+            })
+            .unwrap();
+        assert_eq!((cancelled.finish_reason, pieces), (FinishReason::Cancelled, 1));
+        assert!(cancelled.tokens_generated >= 1 && cancelled.tokens_generated < free.tokens_generated);
+        assert!(free.response.starts_with(&cancelled.response));
+
+        let late = llm.generate_response(&InferenceRequest { timeout_ms: Some(0), ..request("ab", Some(16)) }).unwrap();
+        assert_eq!((late.finish_reason, late.response.as_str()), (FinishReason::Deadline, ""));
+        let json = serde_json::to_value(&late).unwrap();
+        assert_eq!(json["finish_reason"], "deadline");
+    }
+
+    #[test]
+    fn test_logprobs_for_each_completion_token() {
+        let llm = load(BackendKind::Native, "logprobs");
+        let response = llm.generate_response(&InferenceRequest { logprobs: Some(3), ..request("ab", Some(6)) }).unwrap();
+        let logprobs = response.logprobs.unwrap();
+        assert_eq!(logprobs.len(), response.tokens_generated as usize);
+        for step in &logprobs {
+            assert_eq!(step.top_logprobs.len(), 3);
+            assert!(step.top_logprobs.windows(2).all(|w| w[0].logprob >= w[1].logprob));
+            // Greedy decoding picks the likeliest token
+            assert_eq!((&step.token, step.logprob), (&step.top_logprobs[0].token, step.top_logprobs[0].logprob));
+            assert!(step.logprob <= 0.0);
+        }
+        assert!(llm.generate_response(&request("ab", Some(6))).unwrap().logprobs.is_none());
+    }
+
+    #[test]
+    fn test_batch_shares_prefix_and_reports_item_errors() {
+        let llm = load(BackendKind::Native, "batch");
+        let batch = BatchRequest::from_value(serde_json::json!([
+            {"prompt": "abc ab", "max_tokens": 4, "temperature": 0.0},
+            {"prompt": "ab ".repeat(80)},
+            {"prompt": "abc abc", "max_tokens": 4, "temperature": 0.0},
+        ]))
+        .unwrap();
+
+        let response = llm.generate_batch(&batch);
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["results"].as_array().unwrap().len(), 3);
+        let BatchItem::Error(error) = &response.results[1] else { panic!("second item should fail") };
+        assert_eq!((error.error.kind(), &error.error.details()["index"]), (ErrorKind::ContextLengthExceeded, &serde_json::json!(1)));
+        // Both prompts that fit resume after the prefix they share, the first one included
+        for item in [&response.results[0], &response.results[2]] {
+            let BatchItem::Response(item) = item else { panic!("item should succeed") };
+            assert!(item.cached_prompt_tokens >= 2);
+        }
+    }
+
+    #[test]
+    fn test_backend_failure_reported_with_fallback() {
+        let fixture = ReplayFixture::from_json(r#"{"rules": []}"#).unwrap();
+        let mut llm = SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(ReplayBackend::new(fixture).unwrap()));
+        llm.load_model().unwrap();
+
+        let response = llm.generate_response(&request("ab", Some(4))).unwrap();
+        assert_eq!((response.finish_reason, response.backend.as_str()), (FinishReason::Error, "demo"));
+        assert!(response.backend_error.unwrap().starts_with("replay: No replay rule matches"));
+    }
+
+    // Streams a piece of a completion, then fails
+    struct FlakyBackend;
+
+    impl InferenceBackend for FlakyBackend {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput> {
+            self.generate_stream(request, &mut |_| {})
+        }
+
+        fn generate_stream(&self, _request: &InferenceRequest, on_token: &mut dyn FnMut(&str)) -> Result<BackendOutput> {
+            on_token("The answer");
+            Err(anyhow::anyhow!("device lost"))
+        }
+    }
+
+    #[test]
+    fn test_no_fallback_after_streamed_tokens() {
+        let mut llm = SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(FlakyBackend));
+        llm.load_model().unwrap();
+
+        let mut pieces = Vec::new();
+        let streaming = InferenceRequest { stream: true, ..request("ab", Some(4)) };
+        let error = llm.generate_response_stream(&streaming, &mut |piece| pieces.push(piece.to_string())).unwrap_err();
+        assert_eq!(pieces, ["The answer"]);
+        let error = Error::classify(&error, ErrorKind::Internal);
+        assert_eq!(error.kind(), ErrorKind::InferenceFailed);
+        assert!(error.message().contains("device lost"), "{}", error.message());
+
+        // Without streaming nothing reached the client, so the demo backend still answers
+        let response = llm.generate_response(&request("ab", Some(4))).unwrap();
+        assert_eq!((response.finish_reason, response.backend.as_str()), (FinishReason::Error, "demo"));
+    }
+
+    // Ends of its own accord after exactly three counted tokens
+    struct EosBackend;
+
+    impl InferenceBackend for EosBackend {
+        fn name(&self) -> &str {
+            "eos"
+        }
+
+        fn generate(&self, _request: &InferenceRequest) -> Result<BackendOutput> {
+            Ok(BackendOutput { text: "a b c".to_string(), tokens_generated: Some(3), ..Default::default() })
+        }
+    }
+
+    #[test]
+    fn test_counted_stop_at_budget_is_not_length() {
+        let mut llm = SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(EosBackend));
+        llm.load_model().unwrap();
+        let response = llm.generate_response(&request("ab", Some(3))).unwrap();
+        assert_eq!((response.finish_reason, response.tokens_generated), (FinishReason::Stop, 3));
+    }
+
+    #[test]
+    fn test_sampling_echoed_and_stop_applied() {
+        let llm = load(BackendKind::Demo, "sampling");
+
+        let response = llm
+            .generate_response(&InferenceRequest {
+                seed: Some(99),
+                stop: vec!["b".to_string()],
+                ..request("ab b", Some(10))
+            })
+            .unwrap();
+        assert_eq!(response.response, "a");
+        assert_eq!(response.finish_reason, FinishReason::StopSequence);
+        assert_eq!(response.sampling.seed, 99);
+        assert_eq!(response.sampling.top_p, sampling::DEFAULT_TOP_P);
+
+        // Fixed output takes any option and echoes it; a sampling backend that would
+        // ignore one rejects it instead
+        let shaped = InferenceRequest { top_k: Some(5), min_p: Some(0.1), top_p: Some(0.5), ..request("ab b", Some(10)) };
+        assert_eq!(llm.generate_response(&shaped).unwrap().sampling.top_k, 5);
+        let native = load(BackendKind::Native, "sampling-native");
+        assert_eq!(native.generate_response(&shaped).unwrap().sampling.top_k, 5);
+        let error = backend::WasiNnBackend.check_request(&shaped).unwrap_err();
+        assert!(error.to_string().contains("top_k, min_p"), "{}", error);
+
+        // Without a seed one is picked and reported
+        let response = llm.generate_response(&request("ab", Some(4))).unwrap();
+        assert_ne!(response.sampling.seed, 0);
+    }
+
+    #[test]
+    fn test_lora_adapters_loaded_and_selected() {
+        let dir = std::env::temp_dir().join(format!("tinyedge-lora-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (model, adapter) = (dir.join("tiny.gguf"), dir.join("tools.gguf"));
+        std::fs::write(&model, tiny_llama(false)).unwrap();
+        std::fs::write(&adapter, lora::testing::tiny_adapter(0.05, 4)).unwrap();
+        let spec = LoraSpec { active: false, ..LoraSpec::new(&adapter.to_string_lossy()) };
+        let mut llm = SuperTinyWasmLLM::new(model.to_string_lossy().to_string())
+            .with_backend_kind(BackendKind::Native)
+            .with_lora(spec.clone());
+        llm.load_model().unwrap();
+        assert_eq!(llm.lora_adapters(), [LoraInfo { name: "tools".to_string(), scale: 1.0, active: false, tensors: 2 }]);
+
+        let select = |name: &str| Some(vec![LoraSelection { name: name.to_string(), scale: None }]);
+        let tools = llm.generate_response(&InferenceRequest { lora: select("tools"), ..request("abc", Some(4)) }).unwrap();
+        assert_eq!(tools.backend, "native");
+        let error = llm.generate_response(&InferenceRequest { lora: select("nope"), ..request("abc", Some(4)) }).unwrap_err();
+        assert_eq!(Error::classify(&error, ErrorKind::InferenceFailed).kind(), ErrorKind::InvalidRequest);
+
+        // Reloading keeps the adapters; backends without LoRA support refuse them
+        llm.reload_model(None).unwrap();
+        assert_eq!(llm.lora_adapters().len(), 1);
+        let mut demo = SuperTinyWasmLLM::new(model.to_string_lossy().to_string())
+            .with_backend_kind(BackendKind::Demo)
+            .with_lora(spec);
+        assert!(demo.load_model().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mapped_and_read_models_agree() {
+        let path = std::env::temp_dir().join(format!("tinyedge-load-mode-{}.gguf", std::process::id()));
+        std::fs::write(&path, tiny_llama(false)).unwrap();
+        let load = |mode| {
+            let mut llm = SuperTinyWasmLLM::new(path.to_string_lossy().to_string())
+                .with_backend_kind(BackendKind::Native)
+                .with_load_mode(mode);
+            llm.load_model().unwrap();
+            llm
+        };
+        let (mapped, read) = (load(LoadMode::Mmap), load(LoadMode::Read));
+        std::fs::remove_file(&path).unwrap();
+
+        let memory = mapped.memory_usage().unwrap();
+        assert_eq!(memory.load_mode, LoadMode::Mmap);
+        assert_eq!(memory.file_bytes, mapped.model_info().unwrap().file_size);
+        assert_eq!(read.memory_usage().unwrap().resident_bytes, Some(memory.file_bytes));
+        assert_eq!(
+            mapped.generate_response(&request("ab", Some(6))).unwrap().response,
+            read.generate_response(&request("ab", Some(6))).unwrap().response
+        );
+        assert!(SuperTinyWasmLLM::new(String::new()).memory_usage().is_none());
+    }
+
+    #[test]
+    fn test_embeddings_from_hidden_states() {
+        let llm = load(BackendKind::Native, "embed");
+        let texts = vec!["ab b".to_string(), "ba".to_string(), "ab b".to_string()];
+        let response = llm.embed(&EmbeddingRequest::new(texts.clone())).unwrap();
+
+        // One unit vector per text, as wide as the model's hidden state
+        assert_eq!((response.embeddings.len(), response.dimensions, response.backend.as_str()), (3, 32, "native"));
+        assert_eq!(response.embeddings[0], response.embeddings[2]);
+        assert_ne!(response.embeddings[0], response.embeddings[1]);
+        assert!(response.embeddings.iter().all(|v| (v.iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 1e-4));
+
+        let last = llm.embed(&EmbeddingRequest { pooling: Pooling::Last, ..EmbeddingRequest::new(texts) }).unwrap();
+        assert_ne!(last.embeddings[0], response.embeddings[0]);
+
+        // Without a model the simulation backend hashes the text
+        let mut simulated = SuperTinyWasmLLM::new(String::new()).with_backend_kind(BackendKind::Simulation);
+        simulated.load_model().unwrap();
+        let hashed = simulated.embed(&EmbeddingRequest::new(vec!["ab b".to_string()])).unwrap();
+        assert_eq!(hashed.dimensions, embedding::HASH_EMBEDDING_DIMS);
+
+        let error = llm.embed(&EmbeddingRequest::new(vec![])).unwrap_err();
+        assert_eq!(Error::classify(&error, ErrorKind::Internal).kind(), ErrorKind::InvalidRequest);
+        let error = llm.embed(&EmbeddingRequest::new(vec!["ab ".repeat(64)])).unwrap_err();
+        assert_eq!(Error::classify(&error, ErrorKind::Internal).kind(), ErrorKind::ContextLengthExceeded);
+    }
+
+    #[test]
+    fn test_context_length_enforced() {
+        let llm = load(BackendKind::Native, "context");
+
+        let too_long = "ab ".repeat(64);
+        let error = llm.generate_response(&request(&too_long, None)).unwrap_err();
+        assert_eq!(Error::classify(&error, ErrorKind::InferenceFailed).kind(), ErrorKind::ContextLengthExceeded);
+
+        // 60 prompt tokens leave room for at most 4 more in the 64-token window
+        let response = llm.generate_response(&request("ab ".repeat(59).trim_end(), Some(50))).unwrap();
+        assert_eq!(response.prompt_tokens, 60);
+        assert!(response.tokens_generated <= 4);
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+// Log filter: a default level plus per-target overrides, e.g. "warn,tinyedgellmagents_core::native=debug"
+pub const LOG_ENV_VAR: &str = "TINYEDGELLMAGENTS_LOG";
+// "text" (default) or "json", one object per line
+pub const LOG_FORMAT_ENV_VAR: &str = "TINYEDGELLMAGENTS_LOG_FORMAT";
+// Append logs to this file instead of stderr
+pub const LOG_FILE_ENV_VAR: &str = "TINYEDGELLMAGENTS_LOG_FILE";
+// Any value lowers the default level to warnings
+pub const QUIET_ENV_VAR: &str = "TINYEDGELLMAGENTS_QUIET";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(anyhow!("Unknown log format '{}' (expected text or json)", other)),
+        }
+    }
+}
+
+// Where logs go and which ones are kept. stdout is never used: it carries only protocol output.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    // Overrides for targets (module paths) starting with the given prefix; the longest match wins
+    pub targets: Vec<(String, LevelFilter)>,
+    pub format: LogFormat,
+    // stderr when unset
+    pub file: Option<PathBuf>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            targets: Vec::new(),
+            format: LogFormat::Text,
+            file: None,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if std::env::var_os(QUIET_ENV_VAR).is_some() {
+            config.level = LevelFilter::Warn;
+        }
+        if let Ok(filter) = std::env::var(LOG_ENV_VAR) {
+            config.apply_filter(&filter)?;
+        }
+        if let Ok(format) = std::env::var(LOG_FORMAT_ENV_VAR) {
+            config.format = format.parse()?;
+        }
+        if let Ok(file) = std::env::var(LOG_FILE_ENV_VAR) {
+            config.file = Some(PathBuf::from(file));
+        }
+        Ok(config)
+    }
+
+    // Parse "level" and "target=level" items separated by commas
+    pub fn apply_filter(&mut self, filter: &str) -> Result<()> {
+        for item in filter.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let parse = |level: &str| {
+                LevelFilter::from_str(level.trim()).map_err(|_| anyhow!("Unknown log level '{}'", level.trim()))
+            };
+            match item.split_once('=') {
+                Some((target, level)) => self.targets.push((target.trim().to_string(), parse(level)?)),
+                None => self.level = parse(item)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|&(_, level)| level)
+            .unwrap_or(self.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|&(_, level)| level).fold(self.level, |a, b| a.max(b))
+    }
+}
+
+struct Logger {
+    config: LogConfig,
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.config.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_line(
+            self.config.format,
+            &timestamp(),
+            record.level(),
+            record.target(),
+            &record.args().to_string(),
+        );
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        // Logging must never take the process down
+        let _ = writeln!(sink, "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = self.sink.lock().unwrap_or_else(|e| e.into_inner()).flush();
+    }
+}
+
+// Install the process-wide logger. Only the first call takes effect.
+pub fn init(config: LogConfig) -> Result<()> {
+    let sink: Box<dyn Write + Send> = match &config.file {
+        Some(path) => Box::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stderr()),
+    };
+    let max_level = config.max_level();
+    log::set_boxed_logger(Box::new(Logger { config, sink: Mutex::new(sink) }))
+        .map_err(|_| anyhow!("A logger is already installed"))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+// Logging from the environment, falling back to defaults if the settings are invalid
+pub fn init_from_env() {
+    let config = LogConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("{}, using default logging", e);
+        LogConfig::default()
+    });
+    if let Err(e) = init(config) {
+        eprintln!("{}", e);
+    }
+}
+
+fn format_line(format: LogFormat, timestamp: &str, level: log::Level, target: &str, message: &str) -> String {
+    match format {
+        LogFormat::Text => format!("{} {:<5} {}: {}", timestamp, level, target, message),
+        LogFormat::Json => serde_json::json!({
+            "ts": timestamp,
+            "level": level.as_str(),
+            "target": target,
+            "message": message,
+        })
+        .to_string(),
+    }
+}
+
+// UTC time as RFC 3339 with milliseconds
+fn timestamp() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60,
+        now.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_targets_and_levels() {
+        let mut config = LogConfig::default();
+        config.apply_filter("warn, tinyedgellmagents_core=info,tinyedgellmagents_core::native=trace").unwrap();
+
+        assert_eq!(config.level_for("tinyedgellmagents"), LevelFilter::Warn);
+        assert_eq!(config.level_for("tinyedgellmagents_core::backend"), LevelFilter::Info);
+        assert_eq!(config.level_for("tinyedgellmagents_core::native"), LevelFilter::Trace);
+        assert_eq!(config.max_level(), LevelFilter::Trace);
+        assert!(config.apply_filter("loud").is_err());
+    }
+
+    #[test]
+    fn test_line_formats() {
+        let ts = "2026-01-02T03:04:05.006Z";
+        assert_eq!(
+            format_line(LogFormat::Text, ts, log::Level::Info, "core", "Model loaded"),
+            "2026-01-02T03:04:05.006Z INFO  core: Model loaded"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&format_line(LogFormat::Json, ts, log::Level::Warn, "core", "say \"hi\"")).unwrap();
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["message"], "say \"hi\"");
+
+        assert_eq!(timestamp().len(), ts.len());
+        assert!("JSON".parse::<LogFormat>().is_ok() && "xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::gguf::{GgufFile, TensorInfo};
+use crate::quant;
+use crate::{Error, ErrorKind};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+// A LoRA adapter to load on top of the base model, e.g. {"name": "weather", "path":
+// "adapters/weather.gguf", "scale": 0.8}. Active adapters apply to every request that
+// does not pick its own; inactive ones only to requests that name them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoraSpec {
+    // The file name without its extension if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub path: String,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl LoraSpec {
+    pub fn new(path: &str) -> Self {
+        Self { name: None, path: path.to_string(), scale: default_scale(), active: default_active() }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            Path::new(&self.path).file_stem().unwrap_or_default().to_string_lossy().into_owned()
+        })
+    }
+}
+
+// Command-line form: `path` or `path:scale`
+impl FromStr for LoraSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(anyhow!("LoRA adapter path is empty"));
+        }
+        match s.rsplit_once(':').and_then(|(path, scale)| Some((path, scale.parse::<f32>().ok()?))) {
+            Some((path, scale)) => Ok(Self { scale, ..Self::new(path) }),
+            None => Ok(Self::new(s)),
+        }
+    }
+}
+
+// An adapter picked by a request, e.g. {"name": "weather", "scale": 0.5}; without a scale
+// the adapter's configured one is used
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoraSelection {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+}
+
+// A loaded adapter as listed in status output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoraInfo {
+    pub name: String,
+    pub scale: f32,
+    pub active: bool,
+    // Base weight matrices it changes
+    pub tensors: usize,
+}
+
+// Low-rank update of one weight matrix, W' = W + scale * B A, kept factored: A is
+// `rank` rows of `cols`, B is `rows` rows of `rank`
+pub struct LoraPair {
+    a: Vec<f32>,
+    b: Vec<f32>,
+    rank: usize,
+    pub cols: usize,
+    pub rows: usize,
+    // alpha / rank from the adapter metadata, applied on top of the user scale
+    factor: f32,
+}
+
+impl LoraPair {
+    // out += scale * B (A x)
+    pub fn apply(&self, x: &[f32], scale: f32, out: &mut [f32]) {
+        let ax: Vec<f32> = self.a.chunks_exact(self.cols).map(|row| dot(row, x)).collect();
+        let scale = scale * self.factor;
+        for (o, row) in out.iter_mut().zip(self.b.chunks_exact(self.rank)) {
+            *o += scale * dot(row, &ax);
+        }
+    }
+}
+
+// Adapter weights from a GGUF LoRA file (as written by llama.cpp's convert_lora_to_gguf):
+// `<base tensor>.lora_a` and `.lora_b` pairs, with the alpha in `adapter.lora.alpha`
+pub struct LoraAdapter {
+    pub name: String,
+    pub scale: f32,
+    pub active: bool,
+    pub architecture: Option<String>,
+    // Keyed by the name of the base tensor they update
+    pub tensors: HashMap<String, LoraPair>,
+}
+
+impl LoraAdapter {
+    pub fn load(spec: &LoraSpec) -> Result<Self> {
+        let bytes = std::fs::read(&spec.path).map_err(|e| {
+            Error::new(ErrorKind::ModelLoadFailed, format!("Failed to read LoRA adapter {}: {}", spec.path, e))
+                .with_detail("path", &spec.path)
+        })?;
+        let gguf = GgufFile::parse(&bytes).with_context(|| format!("Invalid LoRA adapter file: {}", spec.path))?;
+        Self::from_gguf(spec, &gguf, &bytes).with_context(|| format!("In LoRA adapter {}", spec.path))
+    }
+
+    pub fn from_gguf(spec: &LoraSpec, gguf: &GgufFile, data: &[u8]) -> Result<Self> {
+        if let Some(kind) = gguf.get_str("general.type").filter(|&kind| kind != "adapter") {
+            return Err(anyhow!("File is a {}, not an adapter", kind));
+        }
+        if let Some(kind) = gguf.get_str("adapter.type").filter(|&kind| kind != "lora") {
+            return Err(anyhow!("Unsupported adapter type '{}'", kind));
+        }
+        let alpha = gguf.get_f32("adapter.lora.alpha").filter(|&alpha| alpha > 0.0);
+
+        let mut tensors = HashMap::new();
+        for info in &gguf.tensors {
+            let Some(base) = info.name.strip_suffix(".lora_a") else {
+                continue;
+            };
+            let b_name = format!("{}.lora_b", base);
+            let b_info = gguf.tensor(&b_name).ok_or_else(|| anyhow!("Missing tensor {}", b_name))?;
+            let (a, cols, rank) = read_matrix(gguf, data, info)?;
+            let (b, b_cols, rows) = read_matrix(gguf, data, b_info)?;
+            if b_cols != rank || rank == 0 {
+                return Err(anyhow!("Tensors {} and {} have mismatched ranks {} and {}", info.name, b_name, rank, b_cols));
+            }
+            let factor = alpha.map_or(1.0, |alpha| alpha / rank as f32);
+            tensors.insert(base.to_string(), LoraPair { a, b, rank, cols, rows, factor });
+        }
+        if tensors.is_empty() {
+            return Err(anyhow!("No LoRA tensors found"));
+        }
+
+        Ok(Self {
+            name: spec.name(),
+            scale: spec.scale,
+            active: spec.active,
+            architecture: gguf.architecture().map(str::to_string),
+            tensors,
+        })
+    }
+
+    pub fn info(&self) -> LoraInfo {
+        LoraInfo { name: self.name.clone(), scale: self.scale, active: self.active, tensors: self.tensors.len() }
+    }
+}
+
+// A whole tensor as f32, with its row length and row count
+fn read_matrix(gguf: &GgufFile, data: &[u8], info: &TensorInfo) -> Result<(Vec<f32>, usize, usize)> {
+    if !quant::is_supported(info.ggml_type) {
+        return Err(anyhow!("Tensor '{}' uses {} which cannot be decoded", info.name, info.ggml_type.name()));
+    }
+    let range = gguf.tensor_range(info, data.len() as u64)?;
+    let (cols, rows) = info.matrix_shape()?;
+    let mut values = vec![0f32; cols * rows];
+    quant::dequantize(info.ggml_type, &data[range], &mut values)?;
+    Ok((values, cols, rows))
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use crate::gguf::testing::GgufBuilder;
+    use crate::gguf::GgufValue;
+
+    // Adapter changing the query and output projections of the first layer of
+    // `native::testing::tiny_llama`, with every weight set to `value`
+    pub fn tiny_adapter(value: f32, rank: u64) -> Vec<u8> {
+        let n_embd = 32u64;
+        let mut builder = GgufBuilder::new()
+            .kv("general.architecture", GgufValue::String("llama".to_string()))
+            .kv("general.type", GgufValue::String("adapter".to_string()))
+            .kv("adapter.type", GgufValue::String("lora".to_string()))
+            .kv("adapter.lora.alpha", GgufValue::F32(rank as f32));
+        for base in ["blk.0.attn_q.weight", "blk.0.attn_output.weight"] {
+            let a = vec![value; (n_embd * rank) as usize];
+            let b = vec![value; (rank * n_embd) as usize];
+            builder = builder
+                .tensor_f32(&format!("{}.lora_a", base), &[n_embd, rank], &a)
+                .tensor_f32(&format!("{}.lora_b", base), &[rank, n_embd], &b);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::tiny_adapter;
+    use super::*;
+
+    #[test]
+    fn test_adapter_tensors_and_spec_forms() {
+        let bytes = tiny_adapter(0.5, 2);
+        let gguf = GgufFile::parse(&bytes).unwrap();
+        let adapter = LoraAdapter::from_gguf(&"adapters/weather.gguf:0.5".parse().unwrap(), &gguf, &bytes).unwrap();
+        assert_eq!(adapter.info(), LoraInfo { name: "weather".to_string(), scale: 0.5, active: true, tensors: 2 });
+
+        // With every weight 0.5, A x is 16 per rank and B (A x) is 16 per output; alpha / rank is 1
+        let pair = &adapter.tensors["blk.0.attn_q.weight"];
+        assert_eq!((pair.cols, pair.rows), (32, 32));
+        let mut out = vec![1.0; 32];
+        pair.apply(&[1.0; 32], 1.0, &mut out);
+        assert!(out.iter().all(|&o| (o - 17.0).abs() < 1e-4), "{:?}", &out[..4]);
+
+        let plain: LoraSpec = "C:/adapters/a.gguf".parse().unwrap();
+        assert_eq!((plain.path.as_str(), plain.scale, plain.name().as_str()), ("C:/adapters/a.gguf", 1.0, "a"));
+        let spec: LoraSpec = serde_json::from_str(r#"{"path": "x.gguf", "active": false}"#).unwrap();
+        assert_eq!((spec.scale, spec.active), (1.0, false));
+        assert!("".parse::<LoraSpec>().is_err());
+
+        let model = crate::native::testing::tiny_llama(false);
+        let not_adapter = GgufFile::parse(&model).unwrap();
+        assert!(LoraAdapter::from_gguf(&spec, &not_adapter, &model).is_err());
+    }
+}
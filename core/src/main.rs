@@ -1,5 +1,10 @@
 use std::io::{self, Read};
-use supertinywasmllm::{SuperTinyWasmLLM, InferenceRequest, send_error_response, Result};
+use tinyedgellmagents_core::logging;
+use tinyedgellmagents_core::server;
+use tinyedgellmagents_core::{
+    SuperTinyWasmLLM, BatchRequest, EmbeddingRequest, Error, ErrorKind, InferenceRequest, StreamEvent, send_error_response,
+    send_stream_event, Result,
+};
 
 fn read_stdin() -> Result<String> {
     let mut buffer = String::new();
@@ -7,59 +12,103 @@ fn read_stdin() -> Result<String> {
     Ok(buffer.trim().to_string())
 }
 
+// Report `error` on stdout and exit with the code documented for its category
+fn fail(id: Option<String>, error: Error) -> ! {
+    log::error!("{} ({})", error, error.kind());
+    if let Err(e) = send_error_response(id, error.clone()) {
+        log::error!("Failed to write error response: {}", e);
+    }
+    std::process::exit(error.exit_code());
+}
+
 fn main() -> Result<()> {
-    println!("SuperTinyWasmLLM starting up...");
-    
+    // Progress goes to the log (stderr by default); stdout carries only JSON
+    logging::init_from_env();
+    log::info!("SuperTinyWasmLLM starting up...");
+
     // Get model path from environment or use default
     let model_path = std::env::var("SUPERTINYWASMLLM_MODEL_PATH")
         .unwrap_or_else(|_| "model.gguf".to_string());
-    
-    println!("Model path: {}", model_path);
-    
+
+    log::info!("Model path: {}", model_path);
+
     // Initialize SuperTinyWasmLLM
     let mut llm = SuperTinyWasmLLM::new(model_path);
-    
+
     // Load model
     if let Err(e) = llm.load_model() {
-        eprintln!("Failed to load model: {}", e);
-        send_error_response(&format!("Model loading failed: {}", e), 1)?;
-        return Err(e);
+        fail(None, Error::classify(&e.context("Model loading failed"), ErrorKind::ModelLoadFailed));
     }
-    
-    println!("Ready for inference! Send JSON to stdin...");
-    
+
+    // Persistent mode: keep the model warm and answer one request per stdin line
+    if std::env::args().skip(1).any(|arg| arg == "--serve") {
+        log::info!("Ready for inference! Serving one JSON request per line...");
+        return server::serve(&mut llm, io::stdin().lock(), &mut io::stdout());
+    }
+
+    log::info!("Ready for inference! Send JSON to stdin...");
+
     // Read JSON input from stdin
     let input = match read_stdin() {
         Ok(input) => input,
-        Err(e) => {
-            eprintln!("Failed to read stdin: {}", e);
-            send_error_response(&format!("Stdin read failed: {}", e), 2)?;
-            return Err(e);
-        }
+        Err(e) => fail(None, Error::new(ErrorKind::InputFailed, format!("Stdin read failed: {}", e))),
     };
-    
+
     // Parse JSON request
-    let request: InferenceRequest = match serde_json::from_str(&input) {
-        Ok(req) => req,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            send_error_response(&format!("Failed to parse JSON: {}", e), 3)?;
-            return Err(e.into());
+    let value: serde_json::Value = match serde_json::from_str(&input) {
+        Ok(value) => value,
+        Err(e) => fail(None, Error::new(ErrorKind::InvalidRequest, format!("Failed to parse JSON: {}", e))),
+    };
+
+    // Texts to embed instead of a prompt: answer with their vectors
+    if value.get("input").is_some() {
+        let request: EmbeddingRequest = match serde_json::from_value(value) {
+            Ok(req) => req,
+            Err(e) => fail(None, Error::new(ErrorKind::InvalidRequest, format!("Failed to parse JSON: {}", e))),
+        };
+        match llm.embed(&request) {
+            Ok(response) => println!("{}", serde_json::to_string(&response)?),
+            Err(e) => fail(request.id, Error::classify(&e.context("Embedding failed"), ErrorKind::InferenceFailed)),
         }
+        return Ok(());
+    }
+
+    // Several prompts in one run: one response line with a result or error per item
+    if BatchRequest::is_batch(&value) {
+        let batch = BatchRequest::from_value(value).unwrap_or_else(|error| fail(None, error));
+        println!("{}", serde_json::to_string(&llm.generate_batch(&batch))?);
+        return Ok(());
+    }
+
+    let request: InferenceRequest = match serde_json::from_value(value) {
+        Ok(req) => req,
+        Err(e) => fail(None, Error::new(ErrorKind::InvalidRequest, format!("Failed to parse JSON: {}", e))),
     };
-    
-    // Generate response
-            match llm.generate_response(&request) {
+
+    // Generate response, as token events followed by a summary when streaming
+    let result = if request.stream {
+        let mut index = 0;
+        llm.generate_response_stream(&request, &mut |text| {
+            let event = StreamEvent::Token { id: request.id.clone(), index, text: text.to_string() };
+            index += 1;
+            if let Err(e) = send_stream_event(&event) {
+                log::error!("Failed to write token event: {}", e);
+            }
+        })
+    } else {
+        llm.generate_response(&request)
+    };
+
+    match result {
+        Ok(response) if request.stream => {
+            send_stream_event(&StreamEvent::Done(Box::new(response)))?;
+        }
         Ok(response) => {
             let json_response = serde_json::to_string(&response)?;
             println!("{}", json_response);
         }
-        Err(e) => {
-            eprintln!("Inference failed: {}", e);
-            send_error_response(&format!("Inference failed: {}", e), 4)?;
-            return Err(e);
-        }
+        Err(e) => fail(request.id.clone(), Error::classify(&e.context("Inference failed"), ErrorKind::InferenceFailed)),
     }
-    
+
     Ok(())
-}
\ No newline at end of file
+}
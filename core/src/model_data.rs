@@ -0,0 +1,223 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const LOAD_MODE_ENV_VAR: &str = "SUPERTINYWASMLLM_LOAD_MODE";
+
+// How model files are brought into memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadMode {
+    // Map the file and let the OS page tensors in as they are used, falling back to
+    // reading it where mapping is unavailable (e.g. under WASI)
+    #[default]
+    Mmap,
+    // Read the whole file into memory up front
+    Read,
+}
+
+impl LoadMode {
+    // Read the load mode from SUPERTINYWASMLLM_LOAD_MODE, defaulting to mmap
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(LOAD_MODE_ENV_VAR) {
+            Ok(value) if !value.trim().is_empty() => value.parse(),
+            _ => Ok(LoadMode::Mmap),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadMode::Mmap => "mmap",
+            LoadMode::Read => "read",
+        }
+    }
+}
+
+impl FromStr for LoadMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "mmap" | "map" | "lazy" => Ok(LoadMode::Mmap),
+            "read" | "eager" => Ok(LoadMode::Read),
+            other => Err(anyhow!("Unknown load mode '{}' (expected mmap or read)", other)),
+        }
+    }
+}
+
+// The bytes of a model file. A mapped file costs no heap: only the pages that have
+// been touched (the GGUF header, then tensor rows as inference reads them) are
+// resident, and the OS can drop them again under memory pressure.
+pub enum ModelData {
+    #[cfg(not(target_family = "wasm"))]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+
+impl ModelData {
+    pub fn open(path: impl AsRef<Path>, mode: LoadMode) -> Result<Self> {
+        let path = path.as_ref();
+        #[cfg(not(target_family = "wasm"))]
+        if mode == LoadMode::Mmap {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("Failed to open model file: {}", path.display()))?;
+            // Safety: the mapping is read-only; like llama.cpp we assume model files are
+            // not truncated or rewritten while in use
+            match unsafe { memmap2::Mmap::map(&file) } {
+                Ok(map) => return Ok(ModelData::Mapped(map)),
+                Err(e) => log::warn!("Cannot memory-map {} ({}), reading it instead", path.display(), e),
+            }
+        }
+        #[cfg(target_family = "wasm")]
+        let _ = mode; // Nothing to map with under WASI
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read model file: {}", path.display()))?;
+        Ok(ModelData::Owned(bytes))
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self, ModelData::Owned(_))
+    }
+
+    pub fn load_mode(&self) -> LoadMode {
+        if self.is_mapped() {
+            LoadMode::Mmap
+        } else {
+            LoadMode::Read
+        }
+    }
+
+    // Bytes of the file currently held in RAM: all of it when read, the resident
+    // pages when mapped (None if the platform cannot tell)
+    pub fn resident_bytes(&self) -> Option<u64> {
+        match self {
+            #[cfg(not(target_family = "wasm"))]
+            ModelData::Mapped(map) => resident_pages(map),
+            ModelData::Owned(bytes) => Some(bytes.len() as u64),
+        }
+    }
+
+    pub fn memory_report(&self) -> ModelMemory {
+        ModelMemory {
+            load_mode: self.load_mode(),
+            file_bytes: self.len() as u64,
+            resident_bytes: self.resident_bytes(),
+            process_rss_bytes: process_rss_bytes(),
+        }
+    }
+}
+
+impl Deref for ModelData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(not(target_family = "wasm"))]
+            ModelData::Mapped(map) => map,
+            ModelData::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl From<Vec<u8>> for ModelData {
+    fn from(bytes: Vec<u8>) -> Self {
+        ModelData::Owned(bytes)
+    }
+}
+
+// Memory cost of the loaded model, as shown in status output
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelMemory {
+    pub load_mode: LoadMode,
+    pub file_bytes: u64,
+    // Part of the file in RAM now
+    pub resident_bytes: Option<u64>,
+    // Resident set of the whole process, including the runtime
+    pub process_rss_bytes: Option<u64>,
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // Safety: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(all(unix, not(target_family = "wasm")))]
+fn resident_pages(map: &memmap2::Mmap) -> Option<u64> {
+    if map.is_empty() {
+        return Some(0);
+    }
+    let page = page_size();
+    let mut pages = vec![0u8; map.len().div_ceil(page)];
+    // Safety: the range is exactly the live mapping, which is page aligned, and
+    // `pages` has one entry per page in it
+    let status = unsafe { libc::mincore(map.as_ptr() as *mut libc::c_void, map.len(), pages.as_mut_ptr() as *mut _) };
+    if status != 0 {
+        return None;
+    }
+    let resident = pages.iter().filter(|&&p| p & 1 != 0).count();
+    Some((resident * page).min(map.len()) as u64)
+}
+
+#[cfg(all(not(unix), not(target_family = "wasm")))]
+fn resident_pages(_map: &memmap2::Mmap) -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn process_rss_bytes() -> Option<u64> {
+    // Second field of statm is the resident set in pages
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * page_size() as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_rss_bytes() -> Option<u64> {
+    None
+}
+
+// Highest resident set of the process so far, for benchmarks
+#[cfg(target_os = "linux")]
+pub fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn peak_rss_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapped_and_read_files_match() {
+        let path = std::env::temp_dir().join(format!("tinyedge-model-data-{}.bin", std::process::id()));
+        let bytes: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mapped = ModelData::open(&path, LoadMode::Mmap).unwrap();
+        let read = ModelData::open(&path, LoadMode::Read).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(mapped.is_mapped() && !read.is_mapped());
+        assert_eq!(&mapped[..], &bytes[..]);
+        assert_eq!(&read[..], &bytes[..]);
+        assert_eq!(read.resident_bytes(), Some(bytes.len() as u64));
+
+        let report = mapped.memory_report();
+        assert_eq!((report.load_mode, report.file_bytes), (LoadMode::Mmap, bytes.len() as u64));
+        assert!(report.resident_bytes.unwrap() <= report.file_bytes);
+        assert!(ModelData::open("/nonexistent.gguf", LoadMode::Mmap).is_err());
+        assert_eq!("READ".parse::<LoadMode>().unwrap(), LoadMode::Read);
+    }
+}
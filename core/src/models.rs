@@ -0,0 +1,741 @@
+use crate::{Error, ErrorKind};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+pub const MODELS_DIR_ENV_VAR: &str = "SUPERTINYWASMLLM_MODELS_DIR";
+pub const MODELS_MANIFEST_ENV_VAR: &str = "SUPERTINYWASMLLM_MODELS_MANIFEST";
+
+// Redirects followed per download, e.g. from a model hub to its CDN
+const MAX_REDIRECTS: usize = 5;
+
+// Connect and read timeout for plain HTTP downloads
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Models that can be pulled, e.g. {"models": [{"name": "tinyllama", "url": "https://...",
+// "sha256": "<hex>", "size": 668788096, "quantization": "Q4_K_M"}]}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelManifest {
+    pub models: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub url: String,
+    // Hex digest of the whole file
+    pub sha256: String,
+    // Bytes
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    // Tried in order when `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+}
+
+impl ModelManifest {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| anyhow!("Invalid model manifest: {}", e))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|e| Error::new(ErrorKind::InputFailed, format!("Failed to read model manifest {}: {}", path.display(), e)))?;
+        Self::from_json(&json).with_context(|| format!("In model manifest {}", path.display()))
+    }
+
+    // SUPERTINYWASMLLM_MODELS_MANIFEST, else manifest.json in the cache directory
+    pub fn default_path(cache: &ModelCache) -> PathBuf {
+        std::env::var_os(MODELS_MANIFEST_ENV_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| cache.dir().join("manifest.json"))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&ManifestEntry> {
+        self.models.iter().find(|entry| entry.name == name).ok_or_else(|| {
+            Error::new(ErrorKind::ModelNotFound, format!("Model '{}' is not in the manifest", name))
+                .with_detail("model", name)
+                .into()
+        })
+    }
+}
+
+// A model installed in the cache
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedModel {
+    pub name: String,
+    pub sha256: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    // URL or path the file was installed from
+    pub source: String,
+    // Filled in from the cache directory when read, so the cache can be moved
+    #[serde(skip_deserializing)]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub name: String,
+    pub path: PathBuf,
+    pub expected: String,
+    // None when the file is missing
+    pub actual: Option<String>,
+    pub ok: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheIndex {
+    models: Vec<CachedModel>,
+}
+
+// Content-addressed model store: files live under blobs/sha256-<digest>, and index.json
+// maps model names onto them, so models sharing a file store it once. Downloads go to a
+// .partial file next to their blob and resume from it; a file only becomes a blob after
+// its size and checksum match the manifest.
+pub struct ModelCache {
+    dir: PathBuf,
+}
+
+impl ModelCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    // SUPERTINYWASMLLM_MODELS_DIR, else the user cache directory
+    pub fn from_env() -> Self {
+        if let Some(dir) = std::env::var_os(MODELS_DIR_ENV_VAR) {
+            return Self::new(dir);
+        }
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")));
+        match base {
+            Some(base) => Self::new(base.join("tinyedgellmagents").join("models")),
+            None => Self::new("models"),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn list(&self) -> Result<Vec<CachedModel>> {
+        Ok(self.read_index()?.models)
+    }
+
+    pub fn get(&self, name: &str) -> Result<CachedModel> {
+        self.list()?.into_iter().find(|model| model.name == name).ok_or_else(|| {
+            Error::new(ErrorKind::ModelNotFound, format!("Model '{}' is not installed", name))
+                .with_detail("model", name)
+                .into()
+        })
+    }
+
+    // Path of an installed model, so a model name can stand in for a model path
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        self.get(name).ok().map(|model| model.path).filter(|path| path.is_file())
+    }
+
+    // Install `entry` from `from` (a URL or local path), or else from its URL and then its
+    // mirrors in order. A file already in the cache is not fetched again.
+    pub fn pull(&self, entry: &ManifestEntry, from: Option<&str>) -> Result<CachedModel> {
+        let digest = entry.sha256.to_ascii_lowercase();
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::new(ErrorKind::InvalidRequest, format!("Invalid sha256 for model '{}'", entry.name))
+                .with_detail("model", &entry.name)
+                .into());
+        }
+        let blob = self.blob_path(&digest);
+        let sources: Vec<&str> = match from {
+            Some(source) => vec![source],
+            None => std::iter::once(entry.url.as_str()).chain(entry.mirrors.iter().map(String::as_str)).collect(),
+        };
+
+        // A blob of the right size can still be corrupted; only its hash shows it is this model
+        let cached = blob.metadata().is_ok_and(|meta| meta.len() == entry.size) && {
+            let matches = sha256_file(&blob).is_ok_and(|actual| actual == digest);
+            if !matches {
+                log::warn!("Cached file for model '{}' does not match its sha256, fetching it again", entry.name);
+            }
+            matches
+        };
+        let source = if cached {
+            log::info!("Model '{}' is already cached", entry.name);
+            match self.get(&entry.name) {
+                Ok(existing) if existing.sha256 == digest => existing.source,
+                _ => sources[0].to_string(),
+            }
+        } else {
+            fs::create_dir_all(self.dir.join("blobs"))
+                .with_context(|| format!("Failed to create model cache {}", self.dir.display()))?;
+            let partial = partial_path(&blob);
+            let mut installed = None;
+            let mut last_error = None;
+            for source in &sources {
+                match fetch_verified(entry, &digest, source, &partial) {
+                    Ok(()) => {
+                        installed = Some(source.to_string());
+                        break;
+                    }
+                    Err(e) => {
+                        log::warn!("{:#}", e);
+                        last_error = Some(e);
+                    }
+                }
+            }
+            let Some(source) = installed else {
+                return Err(last_error.unwrap_or_else(|| anyhow!("No source for model '{}'", entry.name)));
+            };
+            fs::rename(&partial, &blob).with_context(|| format!("Failed to move download into {}", blob.display()))?;
+            source
+        };
+
+        let model = CachedModel {
+            name: entry.name.clone(),
+            sha256: digest,
+            size: entry.size,
+            quantization: entry.quantization.clone(),
+            source,
+            path: blob,
+        };
+        let mut index = self.read_index()?;
+        index.models.retain(|m| m.name != model.name);
+        index.models.push(model.clone());
+        self.write_index(&index)?;
+        Ok(model)
+    }
+
+    // Hash an installed model's file again and compare it with the digest it was installed under
+    pub fn verify(&self, name: &str) -> Result<VerifyReport> {
+        let model = self.get(name)?;
+        let actual = match sha256_file(&model.path) {
+            Ok(digest) => Some(digest),
+            Err(e) if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(VerifyReport {
+            ok: actual.as_deref() == Some(model.sha256.as_str()),
+            name: model.name,
+            path: model.path,
+            expected: model.sha256,
+            actual,
+        })
+    }
+
+    // Forget a model, deleting its file unless another installed model shares it
+    pub fn remove(&self, name: &str) -> Result<CachedModel> {
+        let model = self.get(name)?;
+        let mut index = self.read_index()?;
+        index.models.retain(|m| m.name != name);
+        self.write_index(&index)?;
+        if !index.models.iter().any(|m| m.sha256 == model.sha256) {
+            for path in [partial_path(&model.path), model.path.clone()] {
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                        return Err(anyhow!("Failed to delete {}: {}", path.display(), e));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(model)
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.dir.join("blobs").join(format!("sha256-{}", digest))
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.json")
+    }
+
+    fn read_index(&self) -> Result<CacheIndex> {
+        let path = self.index_path();
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(CacheIndex::default()),
+            Err(e) => return Err(anyhow!("Failed to read model cache index {}: {}", path.display(), e)),
+        };
+        let mut index: CacheIndex = serde_json::from_str(&json)
+            .map_err(|e| anyhow!("Invalid model cache index {}: {}", path.display(), e))?;
+        for model in &mut index.models {
+            model.path = self.blob_path(&model.sha256);
+        }
+        Ok(index)
+    }
+
+    // Written to a temporary file and renamed, so an interrupted write keeps the old index
+    fn write_index(&self, index: &CacheIndex) -> Result<()> {
+        fs::create_dir_all(&self.dir).with_context(|| format!("Failed to create model cache {}", self.dir.display()))?;
+        let path = self.index_path();
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string_pretty(index)?)
+            .with_context(|| format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+fn partial_path(blob: &Path) -> PathBuf {
+    blob.with_extension("partial")
+}
+
+// Bring `partial` up to the full file from `source`, then check its size and checksum.
+// A file that fails the check is deleted; one cut short is kept to resume from.
+fn fetch_verified(entry: &ManifestEntry, digest: &str, source: &str, partial: &Path) -> Result<()> {
+    let offset = match partial.metadata() {
+        Ok(meta) if meta.len() <= entry.size => meta.len(),
+        Ok(_) => {
+            fs::remove_file(partial)?;
+            0
+        }
+        Err(_) => 0,
+    };
+    if offset < entry.size {
+        if offset > 0 {
+            log::info!("Resuming model '{}' at {} of {} bytes from {}", entry.name, offset, entry.size, source);
+        } else {
+            log::info!("Fetching model '{}' ({} bytes) from {}", entry.name, entry.size, source);
+        }
+        fetch(source, partial, offset).map_err(|e| {
+            let error = Error::new(ErrorKind::DownloadFailed, format!("Failed to fetch {}: {:#}", source, e))
+                .with_detail("model", &entry.name)
+                .with_detail("source", source);
+            anyhow::Error::from(error)
+        })?;
+    }
+
+    let size = partial.metadata()?.len();
+    let actual = if size == entry.size { sha256_file(partial)? } else { String::new() };
+    if actual != digest {
+        fs::remove_file(partial)?;
+        let message = if size == entry.size {
+            format!("Checksum mismatch for model '{}' from {}", entry.name, source)
+        } else {
+            format!("Model '{}' from {} is {} bytes, expected {}", entry.name, source, size, entry.size)
+        };
+        return Err(Error::new(ErrorKind::ChecksumMismatch, message)
+            .with_detail("model", &entry.name)
+            .with_detail("source", source)
+            .with_detail("expected", digest)
+            .with_detail("actual", actual)
+            .into());
+    }
+    Ok(())
+}
+
+// Append the bytes of `source` from `offset` on to `partial`. Plain HTTP is fetched
+// directly; HTTPS goes through curl or wget, as the core has no TLS stack.
+fn fetch(source: &str, partial: &Path, offset: u64) -> Result<()> {
+    if source.starts_with("http://") {
+        http_fetch(source, partial, offset)
+    } else if source.starts_with("https://") {
+        external_fetch(source, partial)
+    } else {
+        copy_fetch(source.strip_prefix("file://").unwrap_or(source), partial, offset)
+    }
+}
+
+fn copy_fetch(path: &str, partial: &Path, offset: u64) -> Result<()> {
+    let mut input = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    input.seek(SeekFrom::Start(offset))?;
+    let mut output = OpenOptions::new().create(true).append(true).open(partial)?;
+    io::copy(&mut input, &mut output)?;
+    Ok(())
+}
+
+fn external_fetch(url: &str, partial: &Path) -> Result<()> {
+    let status = match Command::new("curl").args(["-fL", "--retry", "3", "-C", "-", "-o"]).arg(partial).arg(url).status() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Command::new("wget")
+            .args(["-q", "-c", "-O"])
+            .arg(partial)
+            .arg(url)
+            .status()
+            .map_err(|e| anyhow!("Neither curl nor wget could be run: {}", e))?,
+        result => result?,
+    };
+    if !status.success() {
+        return Err(anyhow!("Downloader exited with {}", status));
+    }
+    Ok(())
+}
+
+fn http_fetch(url: &str, partial: &Path, offset: u64) -> Result<()> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let (host, port, path) = parse_http_url(&url)?;
+        let address = std::net::ToSocketAddrs::to_socket_addrs(&(host.as_str(), port))?
+            .next()
+            .ok_or_else(|| anyhow!("Could not resolve {}", host))?;
+        let mut stream = TcpStream::connect_timeout(&address, HTTP_TIMEOUT)?;
+        stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        // HTTP/1.0 keeps the body a plain byte stream, without chunked encoding
+        let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: tinyedgellmagents\r\n", path, host);
+        if offset > 0 {
+            request.push_str(&format!("Range: bytes={}-\r\n", offset));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status: u16 = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| anyhow!("Malformed HTTP status line: {}", line.trim()))?;
+        let mut content_length = None;
+        let mut location = None;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-length" => content_length = value.trim().parse::<u64>().ok(),
+                    "location" => location = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut output = match status {
+            301 | 302 | 303 | 307 | 308 => {
+                let location = location.ok_or_else(|| anyhow!("HTTP {} without a Location", status))?;
+                url = redirect_target(&url, &location)?;
+                if url.starts_with("https://") {
+                    return external_fetch(&url, partial);
+                }
+                continue;
+            }
+            206 => OpenOptions::new().create(true).append(true).open(partial)?,
+            200 => {
+                if offset > 0 {
+                    log::info!("{} does not support resuming, downloading from the start", url);
+                }
+                File::create(partial)?
+            }
+            // The range starts at the end of the file: nothing is left to fetch
+            416 => return Ok(()),
+            _ => return Err(anyhow!("HTTP {}", status)),
+        };
+        let copied = io::copy(&mut reader.by_ref().take(content_length.unwrap_or(u64::MAX)), &mut output)?;
+        if let Some(expected) = content_length.filter(|&expected| copied < expected) {
+            return Err(anyhow!("Connection closed after {} of {} bytes", copied, expected));
+        }
+        return Ok(());
+    }
+    Err(anyhow!("More than {} redirects", MAX_REDIRECTS))
+}
+
+// Absolute URL a redirect from `url` to `location` leads to. Only http and https are
+// followed, so a server cannot point a download at a local file.
+fn redirect_target(url: &str, location: &str) -> Result<String> {
+    let scheme_end = location.find(':').filter(|&i| {
+        let scheme = &location[..i];
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    if let Some(end) = scheme_end {
+        let scheme = location[..end].to_ascii_lowercase();
+        if scheme != "http" && scheme != "https" {
+            return Err(Error::new(ErrorKind::DownloadFailed, format!("Refusing to follow a redirect to {}", location))
+                .with_detail("location", location)
+                .into());
+        }
+        return Ok(format!("{}{}", scheme, &location[end..]));
+    }
+
+    // Relative: `//host/path`, `/path`, `?query` or a path next to the current one
+    let origin_start = url.find("://").map(|i| i + 3).unwrap_or(0);
+    let origin_end = url[origin_start..].find('/').map(|i| i + origin_start).unwrap_or(url.len());
+    let target = if location.starts_with("//") {
+        format!("{}{}", &url[..origin_start - 2], location)
+    } else if location.starts_with('/') {
+        format!("{}{}", &url[..origin_end], location)
+    } else {
+        let path = url[origin_end..].split(['?', '#']).next().unwrap_or_default();
+        if location.starts_with('?') {
+            format!("{}{}{}", &url[..origin_end], path, location)
+        } else {
+            let directory = path.rfind('/').map_or("/", |i| &path[..=i]);
+            format!("{}{}{}", &url[..origin_end], directory, location)
+        }
+    };
+    Ok(target)
+}
+
+// Host, port and path of an http:// URL
+fn parse_http_url(url: &str) -> Result<(String, u16, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| anyhow!("Not an http:// URL: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| anyhow!("Invalid port in {}", url))?),
+        None => (authority, 80),
+    };
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+pub fn sha256_file(path: impl AsRef<Path>) -> Result<String> {
+    let mut file = File::open(path.as_ref())?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// SHA-256 (FIPS 180-4), enough to check downloads without a crypto dependency
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    length: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            block: [0; 64],
+            filled: 0,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.filled).min(data.len());
+            self.block[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    // Lowercase hex digest
+    fn finish(mut self) -> String {
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        self.state.iter().map(|word| format!("{:08x}", word)).collect()
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    fn digest(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    // Serves `body` over HTTP, honouring Range headers. The first response is cut off
+    // halfway, like a dropped connection. Returns the URL and the ranges requested.
+    fn serve(body: Vec<u8>) -> (String, Arc<Mutex<Vec<Option<u64>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/models/tiny.gguf", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&ranges);
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut range = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && !line.trim().is_empty() {
+                    if let Some(value) = line.strip_prefix("Range: bytes=") {
+                        range = value.trim().trim_end_matches('-').parse::<u64>().ok();
+                    }
+                    line.clear();
+                }
+                seen.lock().unwrap().push(range);
+                let start = range.unwrap_or(0) as usize;
+                let status = if range.is_some() { "206 Partial Content" } else { "200 OK" };
+                let rest = &body[start..];
+                let sent = if i == 0 { &rest[..rest.len() / 2] } else { rest };
+                let header = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n", status, rest.len());
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(sent).unwrap();
+            }
+        });
+        (url, ranges)
+    }
+
+    fn entry(name: &str, body: &[u8], url: &str) -> ManifestEntry {
+        ManifestEntry {
+            name: name.to_string(),
+            url: url.to_string(),
+            sha256: digest(body),
+            size: body.len() as u64,
+            quantization: Some("Q4_0".to_string()),
+            mirrors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_redirects_only_to_http() {
+        let base = "http://mirror.example/models/tiny.gguf?v=1";
+        let target = |location| redirect_target(base, location);
+        assert_eq!(target("HTTPS://cdn.example/a.gguf").unwrap(), "https://cdn.example/a.gguf");
+        assert_eq!(target("//cdn.example/a.gguf").unwrap(), "http://cdn.example/a.gguf");
+        assert_eq!(target("/b/tiny.gguf").unwrap(), "http://mirror.example/b/tiny.gguf");
+        assert_eq!(target("tiny-v2.gguf").unwrap(), "http://mirror.example/models/tiny-v2.gguf");
+        assert_eq!(target("?v=2").unwrap(), "http://mirror.example/models/tiny.gguf?v=2");
+
+        for location in ["file:///etc/shadow", "ftp://mirror.example/tiny.gguf", "data:,hello"] {
+            let error = Error::classify(&target(location).unwrap_err(), ErrorKind::Internal);
+            assert_eq!(error.kind(), ErrorKind::DownloadFailed);
+        }
+
+        // A server redirecting a pull to a local file gets nothing from it
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tiny.gguf", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && !line.trim().is_empty() {
+                line.clear();
+            }
+            stream.write_all(b"HTTP/1.1 302 Found\r\nLocation: file:///etc/hostname\r\n\r\n").unwrap();
+        });
+        let partial = std::env::temp_dir().join(format!("tinyedge-redirect-{}.partial", std::process::id()));
+        assert!(http_fetch(&url, &partial, 0).is_err());
+        assert!(!partial.exists());
+    }
+
+    #[test]
+    fn test_sha256_known_digests() {
+        assert_eq!(digest(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(digest(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(digest(long), "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+    }
+
+    #[test]
+    fn test_pull_resumes_verifies_and_removes() {
+        let dir = std::env::temp_dir().join(format!("tinyedge-models-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ModelCache::new(&dir);
+        let body: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let (url, ranges) = serve(body.clone());
+        let tiny = entry("tiny", &body, &url);
+
+        // The dropped connection leaves half the file behind, which the next pull resumes
+        let error = Error::classify(&cache.pull(&tiny, None).unwrap_err(), ErrorKind::Internal);
+        assert_eq!(error.kind(), ErrorKind::DownloadFailed);
+        let installed = cache.pull(&tiny, None).unwrap();
+        assert_eq!(*ranges.lock().unwrap(), [None, Some(5_000)]);
+        assert_eq!(fs::read(&installed.path).unwrap(), body);
+        assert!(installed.path.ends_with(format!("blobs/sha256-{}", tiny.sha256)));
+
+        // A second name for the same file, installed from a local path, shares the blob
+        let local = dir.join("copy.gguf");
+        fs::write(&local, &body).unwrap();
+        let alias = entry("alias", &body, "http://127.0.0.1:9/unused");
+        cache.remove("tiny").unwrap();
+        assert!(!installed.path.exists());
+        cache.pull(&alias, Some(local.to_str().unwrap())).unwrap();
+        cache.pull(&tiny, None).unwrap();
+        let names: Vec<_> = cache.list().unwrap().into_iter().map(|m| m.name).collect();
+        assert_eq!(names, ["alias", "tiny"]);
+        assert_eq!(cache.resolve("tiny"), Some(installed.path.clone()));
+
+        assert!(cache.verify("tiny").unwrap().ok);
+        // A damaged blob of the right size is fetched again rather than reused
+        fs::write(&installed.path, vec![0u8; body.len()]).unwrap();
+        cache.pull(&alias, Some(local.to_str().unwrap())).unwrap();
+        assert_eq!(fs::read(&installed.path).unwrap(), body);
+        fs::write(&installed.path, b"corrupted").unwrap();
+        let report = cache.verify("alias").unwrap();
+        assert!(!report.ok && report.actual.is_some());
+
+        // Content that does not match the manifest never enters the cache
+        let mut wrong = entry("wrong", &body, &url);
+        wrong.sha256 = digest(b"something else");
+        let error = Error::classify(&cache.pull(&wrong, Some(local.to_str().unwrap())).unwrap_err(), ErrorKind::Internal);
+        assert_eq!(error.kind(), ErrorKind::ChecksumMismatch);
+        assert!(!cache.blob_path(&wrong.sha256).exists() && cache.get("wrong").is_err());
+
+        cache.remove("tiny").unwrap();
+        assert!(installed.path.exists());
+        cache.remove("alias").unwrap();
+        assert!(!installed.path.exists() && cache.list().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1179 @@
+use crate::embedding::Pooling;
+use crate::gguf::{GgmlType, GgufFile, TensorInfo};
+use crate::grammar::{Grammar, GrammarState};
+use crate::lora::{LoraAdapter, LoraInfo, LoraPair, LoraSelection};
+use crate::model_data::ModelData;
+use crate::prompt_cache::{KvSnapshot, PromptCache, PromptCacheStats};
+use crate::quant;
+use crate::sampling::{find_stop, log_softmax, top_logprobs, Sampler, SamplingParams, TokenLogprob, TopLogprob};
+use crate::tokenizer::Tokenizer;
+use crate::FinishReason;
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Hyperparameters of a llama-family model, read from `llama.*` metadata
+#[derive(Debug, Clone)]
+pub struct LlamaConfig {
+    pub n_vocab: usize,
+    pub n_embd: usize,
+    pub n_layer: usize,
+    pub n_head: usize,
+    pub n_head_kv: usize,
+    pub n_ff: usize,
+    pub n_ctx: usize,
+    pub rms_eps: f32,
+    pub rope_base: f32,
+    pub rope_dims: usize,
+}
+
+impl LlamaConfig {
+    pub fn from_gguf(gguf: &GgufFile) -> Result<Self> {
+        match gguf.architecture() {
+            Some("llama") => {}
+            Some(other) => {
+                return Err(anyhow!("Architecture '{}' is not supported by the native backend (llama only)", other))
+            }
+            None => return Err(anyhow!("Model has no general.architecture metadata")),
+        }
+
+        let required = |key: &str| {
+            gguf.arch_u64(key)
+                .map(|v| v as usize)
+                .ok_or_else(|| anyhow!("Missing llama.{} metadata", key))
+        };
+
+        let n_embd = required("embedding_length")?;
+        let n_head = required("attention.head_count")?;
+        if n_head == 0 || !n_embd.is_multiple_of(n_head) {
+            return Err(anyhow!("Invalid head count {} for embedding length {}", n_head, n_embd));
+        }
+        let n_head_kv = gguf.arch_u64("attention.head_count_kv").map(|v| v as usize).unwrap_or(n_head);
+        if n_head_kv == 0 || !n_head.is_multiple_of(n_head_kv) {
+            return Err(anyhow!("Invalid KV head count {} for {} heads", n_head_kv, n_head));
+        }
+
+        let token_embd = gguf
+            .tensor("token_embd.weight")
+            .ok_or_else(|| anyhow!("Missing tensor token_embd.weight"))?;
+        let n_vocab = token_embd.dims.get(1).copied().unwrap_or(0) as usize;
+
+        Ok(Self {
+            n_vocab,
+            n_embd,
+            n_layer: required("block_count")?,
+            n_head,
+            n_head_kv,
+            n_ff: required("feed_forward_length")?,
+            n_ctx: gguf.arch_u64("context_length").unwrap_or(2048) as usize,
+            rms_eps: gguf.arch_f32("attention.layer_norm_rms_epsilon").unwrap_or(1e-5),
+            rope_base: gguf.arch_f32("rope.freq_base").unwrap_or(10000.0),
+            rope_dims: gguf.arch_u64("rope.dimension_count").map(|v| v as usize).unwrap_or(n_embd / n_head),
+        })
+    }
+
+    pub fn head_dim(&self) -> usize {
+        self.n_embd / self.n_head
+    }
+
+    pub fn kv_dim(&self) -> usize {
+        self.head_dim() * self.n_head_kv
+    }
+}
+
+// 2D weight matrix kept in its on-disk encoding and dequantized row by row
+struct QTensor {
+    data: Arc<ModelData>,
+    start: usize,
+    ggml_type: GgmlType,
+    cols: usize,
+    rows: usize,
+    row_bytes: usize,
+}
+
+impl QTensor {
+    fn new(gguf: &GgufFile, data: &Arc<ModelData>, info: &TensorInfo) -> Result<Self> {
+        if !quant::is_supported(info.ggml_type) {
+            return Err(anyhow!(
+                "Tensor '{}' uses {} which the native backend cannot decode",
+                info.name,
+                info.ggml_type.name()
+            ));
+        }
+        let range = gguf.tensor_range(info, data.len() as u64)?;
+        let (cols, rows) = info.matrix_shape()?;
+        let (block_size, type_size) = info.ggml_type.block_layout().unwrap_or((1, 4));
+        if !cols.is_multiple_of(block_size as usize) {
+            return Err(anyhow!("Tensor '{}' row length {} is not a multiple of the block size", info.name, cols));
+        }
+
+        Ok(Self {
+            data: Arc::clone(data),
+            start: range.start,
+            ggml_type: info.ggml_type,
+            cols,
+            rows,
+            row_bytes: cols / block_size as usize * type_size as usize,
+        })
+    }
+
+    fn row(&self, row: usize, out: &mut [f32]) {
+        let start = self.start + row * self.row_bytes;
+        let bytes = &self.data[start..start + self.row_bytes];
+        // Shape and type were validated in `new`
+        quant::dequantize(self.ggml_type, bytes, &mut out[..self.cols]).expect("validated tensor row");
+    }
+
+    fn to_vec(&self) -> Vec<f32> {
+        let mut out = vec![0f32; self.rows * self.cols];
+        for (r, chunk) in out.chunks_exact_mut(self.cols).enumerate() {
+            self.row(r, chunk);
+        }
+        out
+    }
+
+    // out = W x, where each row of W has `cols` elements
+    fn matvec(&self, x: &[f32], out: &mut [f32]) {
+        let work = |first_row: usize, out: &mut [f32]| {
+            let mut scratch = vec![0f32; self.cols];
+            for (i, o) in out.iter_mut().enumerate() {
+                self.row(first_row + i, &mut scratch);
+                *o = dot(&scratch, x);
+            }
+        };
+
+        #[cfg(not(target_family = "wasm"))]
+        if self.rows * self.cols >= 1 << 16 {
+            if let Some(pool) = WorkerPool::global() {
+                let chunk = self.rows.div_ceil(pool.threads());
+                let chunks: Vec<_> = out[..self.rows].chunks_mut(chunk).map(std::sync::Mutex::new).collect();
+                let ran = pool.run(&|i| {
+                    if let Some(out_chunk) = chunks.get(i) {
+                        work(i * chunk, &mut out_chunk.lock().unwrap());
+                    }
+                });
+                if ran {
+                    return;
+                }
+            }
+        }
+
+        work(0, &mut out[..self.rows]);
+    }
+}
+
+// Threads shared by every matvec, started once instead of on each call. Worker `i` runs
+// part `i + 1` of a job while the caller runs part 0.
+#[cfg(not(target_family = "wasm"))]
+struct WorkerPool {
+    // Taken for the length of a job; a caller that finds it taken works alone
+    channels: std::sync::Mutex<PoolChannels>,
+    workers: usize,
+}
+
+#[cfg(not(target_family = "wasm"))]
+struct PoolChannels {
+    jobs: Vec<std::sync::mpsc::Sender<PoolJob>>,
+    // One message per finished part: false if it panicked
+    done: std::sync::mpsc::Receiver<bool>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+type PoolJob = &'static (dyn Fn(usize) + Sync);
+
+#[cfg(not(target_family = "wasm"))]
+impl WorkerPool {
+    // The process-wide pool, or None on a single core
+    fn global() -> Option<&'static WorkerPool> {
+        static POOL: std::sync::OnceLock<Option<WorkerPool>> = std::sync::OnceLock::new();
+        POOL.get_or_init(|| {
+            let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+            WorkerPool::new(threads - 1)
+        })
+        .as_ref()
+    }
+
+    fn new(workers: usize) -> Option<Self> {
+        let (done_tx, done) = std::sync::mpsc::channel();
+        let mut jobs = Vec::with_capacity(workers);
+        for i in 0..workers {
+            let (job_tx, job_rx) = std::sync::mpsc::channel::<PoolJob>();
+            let done_tx = done_tx.clone();
+            let spawned = std::thread::Builder::new().name(format!("matvec-{}", i + 1)).spawn(move || {
+                for job in job_rx {
+                    let ok = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(i + 1))).is_ok();
+                    if done_tx.send(ok).is_err() {
+                        break;
+                    }
+                }
+            });
+            if spawned.is_err() {
+                break;
+            }
+            jobs.push(job_tx);
+        }
+        if jobs.is_empty() {
+            return None;
+        }
+        let workers = jobs.len();
+        Some(Self { channels: std::sync::Mutex::new(PoolChannels { jobs, done }), workers })
+    }
+
+    // Parts a job is split into, counting the caller's
+    fn threads(&self) -> usize {
+        self.workers + 1
+    }
+
+    // Run `job(0)` to `job(threads() - 1)` in parallel and wait for all of them. Returns
+    // false without running anything if another caller holds the pool.
+    fn run(&self, job: &(dyn Fn(usize) + Sync)) -> bool {
+        let channels = match self.channels.try_lock() {
+            Ok(channels) => channels,
+            Err(std::sync::TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(std::sync::TryLockError::WouldBlock) => return false,
+        };
+        // Safety: `Pending` below waits for every part sent to a worker before this
+        // function returns or unwinds, so no worker uses `job` past its lifetime
+        let shared: PoolJob = unsafe { std::mem::transmute::<&(dyn Fn(usize) + Sync), PoolJob>(job) };
+        let mut pending = Pending { done: &channels.done, count: 0, panicked: false };
+        let mut unsent = Vec::new();
+        for (i, sender) in channels.jobs.iter().enumerate() {
+            match sender.send(shared) {
+                Ok(()) => pending.count += 1,
+                Err(_) => unsent.push(i + 1),
+            }
+        }
+        job(0);
+        // Parts of workers that are gone are run here
+        for part in unsent {
+            job(part);
+        }
+        pending.wait();
+        assert!(!pending.panicked, "matvec worker panicked");
+        true
+    }
+}
+
+// Parts of a pool job still running on workers
+#[cfg(not(target_family = "wasm"))]
+struct Pending<'a> {
+    done: &'a std::sync::mpsc::Receiver<bool>,
+    count: usize,
+    panicked: bool,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Pending<'_> {
+    fn wait(&mut self) {
+        while self.count > 0 {
+            self.panicked |= !self.done.recv().unwrap_or(false);
+            self.count -= 1;
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.wait();
+    }
+}
+
+struct LayerWeights {
+    attn_norm: Vec<f32>,
+    wq: QTensor,
+    wk: QTensor,
+    wv: QTensor,
+    wo: QTensor,
+    ffn_norm: Vec<f32>,
+    w_gate: QTensor,
+    w_up: QTensor,
+    w_down: QTensor,
+}
+
+// Layer matrices LoRA adapters can change, in the order of their slots
+const ADAPTABLE: [&str; 7] = ["attn_q", "attn_k", "attn_v", "attn_output", "ffn_gate", "ffn_up", "ffn_down"];
+
+// An attached LoRA adapter. Slot `layer * 7 + i` holds the update of the layer's i-th
+// ADAPTABLE matrix, and the last slot the output projection's.
+struct Adapter {
+    info: LoraInfo,
+    slots: Vec<Option<LoraPair>>,
+}
+
+// The adapters one generation runs with, and their scales
+struct Lora<'a> {
+    adapters: Vec<(&'a Adapter, f32)>,
+    // Prompt cache variant: 0 for the base model
+    variant: u64,
+}
+
+impl<'a> Lora<'a> {
+    fn new(adapters: Vec<(&'a Adapter, f32)>) -> Self {
+        let adapters: Vec<_> = adapters.into_iter().filter(|&(_, scale)| scale != 0.0).collect();
+        // FNV-1a over the names and scales
+        let variant = adapters
+            .iter()
+            .flat_map(|(adapter, scale)| adapter.info.name.bytes().chain([0]).chain(scale.to_le_bytes()))
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3));
+        Self { variant: if adapters.is_empty() { 0 } else { variant }, adapters }
+    }
+
+    // out += the updates of every adapter with a pair in `slot`
+    fn apply(&self, slot: usize, x: &[f32], out: &mut [f32]) {
+        for (adapter, scale) in &self.adapters {
+            if let Some(pair) = &adapter.slots[slot] {
+                pair.apply(x, *scale, out);
+            }
+        }
+    }
+}
+
+// Per-generation state: KV cache and activation buffers
+struct State {
+    key_cache: Vec<Vec<f32>>,
+    value_cache: Vec<Vec<f32>>,
+    capacity: usize,
+    x: Vec<f32>,
+    xb: Vec<f32>,
+    xb2: Vec<f32>,
+    q: Vec<f32>,
+    k: Vec<f32>,
+    v: Vec<f32>,
+    att: Vec<f32>,
+    hb: Vec<f32>,
+    hb2: Vec<f32>,
+    logits: Vec<f32>,
+}
+
+impl State {
+    fn new(config: &LlamaConfig, capacity: usize) -> Self {
+        let kv = config.kv_dim();
+        Self {
+            key_cache: (0..config.n_layer).map(|_| vec![0.0; capacity * kv]).collect(),
+            value_cache: (0..config.n_layer).map(|_| vec![0.0; capacity * kv]).collect(),
+            capacity,
+            x: vec![0.0; config.n_embd],
+            xb: vec![0.0; config.n_embd],
+            xb2: vec![0.0; config.n_embd],
+            q: vec![0.0; config.n_embd],
+            k: vec![0.0; kv],
+            v: vec![0.0; kv],
+            att: vec![0.0; capacity],
+            hb: vec![0.0; config.n_ff],
+            hb2: vec![0.0; config.n_ff],
+            logits: vec![0.0; config.n_vocab],
+        }
+    }
+}
+
+// Grammar position of the completion plus the text of every vocabulary token
+struct Constraint<'g> {
+    state: GrammarState<'g>,
+    pieces: Vec<Vec<u8>>,
+}
+
+impl<'g> Constraint<'g> {
+    fn new(grammar: &'g Grammar, tokenizer: &Tokenizer) -> Self {
+        Self {
+            state: grammar.start(),
+            pieces: (0..tokenizer.vocab_size() as u32).map(|id| tokenizer.token_bytes(id)).collect(),
+        }
+    }
+
+    fn allows(&self, token: u32, eos: Option<u32>) -> bool {
+        if Some(token) == eos {
+            return self.state.is_complete();
+        }
+        self.pieces.get(token as usize).is_some_and(|piece| self.state.accepts(piece))
+    }
+
+    // Sample a token the grammar allows and advance past it; None if nothing fits.
+    // The unconstrained pick is tried first since masking checks the whole vocabulary.
+    fn sample(&mut self, sampler: &mut Sampler, logits: &mut [f32], history: &[u32], eos: Option<u32>) -> Option<u32> {
+        let mut next = sampler.sample(&mut logits.to_vec(), history);
+        if !self.allows(next, eos) {
+            let mut any = false;
+            for (token, logit) in logits.iter_mut().enumerate() {
+                if self.allows(token as u32, eos) {
+                    any = true;
+                } else {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+            if !any {
+                return None;
+            }
+            next = sampler.sample(logits, history);
+        }
+        if Some(next) != eos {
+            self.state.advance(&self.pieces[next as usize]);
+        }
+        Some(next)
+    }
+}
+
+// Per-request controls of a generation besides sampling
+#[derive(Default)]
+pub struct GenerateOptions<'a> {
+    // Only sample tokens that keep the output a valid prefix of this grammar
+    pub grammar: Option<&'a Grammar>,
+    // Polled before every token; generation ends when it gives a reason
+    pub interrupt: Option<&'a dyn Fn() -> Option<FinishReason>>,
+    // Report each token's log probability with this many alternatives
+    pub logprobs: Option<usize>,
+    // LoRA adapters to apply, instead of the active ones; empty for the base model
+    pub lora: Option<&'a [LoraSelection]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NativeOutput {
+    pub text: String,
+    pub prompt_tokens: usize,
+    // Prompt tokens restored from the prompt cache rather than processed
+    pub cached_tokens: usize,
+    pub completion_tokens: usize,
+    pub finish_reason: FinishReason,
+    // Time spent on the prompt before the first sampled token
+    pub prompt_eval: Duration,
+    // One entry per completion token, when requested
+    pub logprobs: Option<Vec<TokenLogprob>>,
+}
+
+// Pure-Rust CPU implementation of the llama forward pass over GGUF weights
+pub struct NativeModel {
+    config: LlamaConfig,
+    tokenizer: Tokenizer,
+    token_embd: QTensor,
+    layers: Vec<LayerWeights>,
+    output_norm: Vec<f32>,
+    output: QTensor,
+    prompt_cache: PromptCache,
+    adapters: Vec<Adapter>,
+}
+
+impl NativeModel {
+    pub fn load(gguf: &GgufFile, data: Arc<ModelData>) -> Result<Self> {
+        let config = LlamaConfig::from_gguf(gguf)?;
+        let tokenizer = Tokenizer::from_gguf(gguf)?;
+
+        let tensor = |name: &str| -> Result<QTensor> {
+            let info = gguf.tensor(name).ok_or_else(|| anyhow!("Missing tensor {}", name))?;
+            QTensor::new(gguf, &data, info)
+        };
+        let vector = |name: &str, len: usize| -> Result<Vec<f32>> {
+            let values = tensor(name)?.to_vec();
+            if values.len() != len {
+                return Err(anyhow!("Tensor {} has {} elements, expected {}", name, values.len(), len));
+            }
+            Ok(values)
+        };
+        let matrix = |name: &str, cols: usize, rows: usize| -> Result<QTensor> {
+            let t = tensor(name)?;
+            if t.cols != cols || t.rows != rows {
+                return Err(anyhow!("Tensor {} is {}x{}, expected {}x{}", name, t.cols, t.rows, cols, rows));
+            }
+            Ok(t)
+        };
+
+        let (n_embd, n_ff, kv_dim) = (config.n_embd, config.n_ff, config.kv_dim());
+        let mut layers = Vec::with_capacity(config.n_layer);
+        for i in 0..config.n_layer {
+            let name = |suffix: &str| format!("blk.{}.{}", i, suffix);
+            layers.push(LayerWeights {
+                attn_norm: vector(&name("attn_norm.weight"), n_embd)?,
+                wq: matrix(&name("attn_q.weight"), n_embd, n_embd)?,
+                wk: matrix(&name("attn_k.weight"), n_embd, kv_dim)?,
+                wv: matrix(&name("attn_v.weight"), n_embd, kv_dim)?,
+                wo: matrix(&name("attn_output.weight"), n_embd, n_embd)?,
+                ffn_norm: vector(&name("ffn_norm.weight"), n_embd)?,
+                w_gate: matrix(&name("ffn_gate.weight"), n_embd, n_ff)?,
+                w_up: matrix(&name("ffn_up.weight"), n_embd, n_ff)?,
+                w_down: matrix(&name("ffn_down.weight"), n_ff, n_embd)?,
+            });
+        }
+
+        let token_embd = matrix("token_embd.weight", n_embd, config.n_vocab)?;
+        // Models with tied embeddings have no separate output matrix
+        let output = match gguf.tensor("output.weight") {
+            Some(_) => matrix("output.weight", n_embd, config.n_vocab)?,
+            None => matrix("token_embd.weight", n_embd, config.n_vocab)?,
+        };
+
+        Ok(Self {
+            output_norm: vector("output_norm.weight", n_embd)?,
+            config,
+            tokenizer,
+            token_embd,
+            layers,
+            output,
+            prompt_cache: PromptCache::from_env(),
+            adapters: Vec::new(),
+        })
+    }
+
+    pub fn config(&self) -> &LlamaConfig {
+        &self.config
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    pub fn prompt_cache_stats(&self) -> PromptCacheStats {
+        self.prompt_cache.stats()
+    }
+
+    // Attach a LoRA adapter. Its updates stay factored and are added to the matching
+    // projections during the forward pass, so the base weights are never rewritten.
+    pub fn add_adapter(&mut self, adapter: LoraAdapter) -> Result<()> {
+        if let Some(arch) = adapter.architecture.as_deref().filter(|&arch| arch != "llama") {
+            return Err(anyhow!("Adapter '{}' is for {} models, not llama", adapter.name, arch));
+        }
+        if self.adapters.iter().any(|a| a.info.name == adapter.name) {
+            return Err(anyhow!("An adapter named '{}' is already loaded", adapter.name));
+        }
+        let info = adapter.info();
+        let mut slots: Vec<Option<LoraPair>> = (0..=self.layers.len() * ADAPTABLE.len()).map(|_| None).collect();
+        for (name, pair) in adapter.tensors {
+            let (slot, weight) = self
+                .adaptable(&name)
+                .ok_or_else(|| anyhow!("Adapter '{}' changes {}, which the native backend cannot adapt", info.name, name))?;
+            if (pair.cols, pair.rows) != (weight.cols, weight.rows) {
+                return Err(anyhow!(
+                    "Adapter '{}' tensor {} is {}x{}, the model's is {}x{}",
+                    info.name, name, pair.cols, pair.rows, weight.cols, weight.rows
+                ));
+            }
+            slots[slot] = Some(pair);
+        }
+        log::info!(
+            "LoRA adapter '{}' loaded ({} tensors, scale {}, {})",
+            info.name,
+            info.tensors,
+            info.scale,
+            if info.active { "active" } else { "on request" }
+        );
+        self.adapters.push(Adapter { info, slots });
+        Ok(())
+    }
+
+    pub fn adapters(&self) -> Vec<LoraInfo> {
+        self.adapters.iter().map(|adapter| adapter.info.clone()).collect()
+    }
+
+    // Slot and base matrix of a tensor name such as blk.3.attn_q.weight
+    fn adaptable(&self, name: &str) -> Option<(usize, &QTensor)> {
+        if name == "output.weight" {
+            return Some((self.layers.len() * ADAPTABLE.len(), &self.output));
+        }
+        let (layer, matrix) = name.strip_prefix("blk.")?.strip_suffix(".weight")?.split_once('.')?;
+        let (layer, index): (usize, usize) = (layer.parse().ok()?, ADAPTABLE.iter().position(|&m| m == matrix)?);
+        let w = self.layers.get(layer)?;
+        let weight = [&w.wq, &w.wk, &w.wv, &w.wo, &w.w_gate, &w.w_up, &w.w_down][index];
+        Some((layer * ADAPTABLE.len() + index, weight))
+    }
+
+    // The adapters a request runs with: the ones it names, or else the active ones
+    fn select_lora(&self, selection: Option<&[LoraSelection]>) -> Result<Lora<'_>> {
+        let Some(selection) = selection else {
+            return Ok(self.default_lora());
+        };
+        let adapters = selection
+            .iter()
+            .map(|selected| {
+                let adapter = self
+                    .adapters
+                    .iter()
+                    .find(|a| a.info.name == selected.name)
+                    .ok_or_else(|| anyhow!("Unknown LoRA adapter '{}'", selected.name))?;
+                Ok((adapter, selected.scale.unwrap_or(adapter.info.scale)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Lora::new(adapters))
+    }
+
+    fn default_lora(&self) -> Lora<'_> {
+        Lora::new(self.adapters.iter().filter(|a| a.info.active).map(|a| (a, a.info.scale)).collect())
+    }
+
+    pub fn generate(&self, prompt: &str, max_tokens: u32, params: &SamplingParams) -> Result<NativeOutput> {
+        self.generate_stream(prompt, max_tokens, params, &GenerateOptions::default(), &mut |_| {})
+    }
+
+    // Generate, passing each newly decoded piece of text to `on_token` as it is sampled
+    pub fn generate_stream(
+        &self,
+        prompt: &str,
+        max_tokens: u32,
+        params: &SamplingParams,
+        options: &GenerateOptions,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<NativeOutput> {
+        let prompt_tokens = self.tokenizer.encode(prompt, true);
+        if prompt_tokens.len() >= self.config.n_ctx {
+            return Err(anyhow!(
+                "Prompt is {} tokens, model context length is {}",
+                prompt_tokens.len(),
+                self.config.n_ctx
+            ));
+        }
+
+        let lora = self.select_lora(options.lora)?;
+        let capacity = (prompt_tokens.len() + max_tokens as usize).min(self.config.n_ctx);
+        let mut state = State::new(&self.config, capacity);
+        let mut sampler = Sampler::new(params);
+        let mut constraint = options.grammar.map(|g| Constraint::new(g, &self.tokenizer));
+        let interrupt = || options.interrupt.and_then(|interrupt| interrupt());
+        let mut logprobs = options.logprobs.map(|_| Vec::new());
+
+        let cached = self.restore_prompt(&mut state, &prompt_tokens, lora.variant);
+        let started = Instant::now();
+        for (pos, &token) in prompt_tokens.iter().enumerate().skip(cached) {
+            if let Some(reason) = interrupt() {
+                return Ok(NativeOutput {
+                    text: String::new(),
+                    prompt_tokens: prompt_tokens.len(),
+                    cached_tokens: cached,
+                    completion_tokens: 0,
+                    finish_reason: reason,
+                    prompt_eval: started.elapsed(),
+                    logprobs,
+                });
+            }
+            self.forward(&mut state, token, pos, &lora);
+        }
+        let prompt_eval = started.elapsed();
+        self.prompt_cache.record_processing(prompt_tokens.len() - cached, prompt_eval);
+        self.save_prompt(&state, &prompt_tokens, lora.variant);
+
+        // Prompt and completion together, as the penalties look at both
+        let n_prompt = prompt_tokens.len();
+        let mut tokens = prompt_tokens;
+        let mut text = String::new();
+        let mut emitted = 0;
+        let mut finish_reason = FinishReason::Length;
+        while tokens.len() - n_prompt < max_tokens as usize && tokens.len() < capacity {
+            if let Some(reason) = interrupt() {
+                finish_reason = reason;
+                break;
+            }
+            // Taken before the sampler applies penalties to the logits in place
+            let distribution = logprobs.is_some().then(|| log_softmax(&state.logits));
+            let next = match constraint.as_mut() {
+                None => sampler.sample(&mut state.logits, &tokens),
+                Some(constraint) => match constraint.sample(&mut sampler, &mut state.logits, &tokens, self.tokenizer.eos_id()) {
+                    Some(next) => next,
+                    None => {
+                        finish_reason = FinishReason::Stop;
+                        break;
+                    }
+                },
+            };
+            if Some(next) == self.tokenizer.eos_id() {
+                finish_reason = FinishReason::Stop;
+                break;
+            }
+            if let (Some(logprobs), Some(distribution)) = (logprobs.as_mut(), distribution) {
+                logprobs.push(self.token_logprob(&distribution, next, options.logprobs.unwrap_or(0)));
+            }
+            let pos = tokens.len();
+            tokens.push(next);
+
+            text = self.tokenizer.decode(&tokens[n_prompt..]);
+            let stop_at = find_stop(&text, &params.stop);
+            if let Some(at) = stop_at {
+                text.truncate(at);
+            }
+            // Hold back a partial UTF-8 sequence until the rest of its byte tokens arrive
+            if !text.ends_with('\u{fffd}') && text.len() > emitted {
+                on_token(&text[emitted..]);
+                emitted = text.len();
+            }
+            if stop_at.is_some() {
+                finish_reason = FinishReason::StopSequence;
+                break;
+            }
+            if constraint.as_ref().is_some_and(|c| !c.state.can_continue()) {
+                finish_reason = FinishReason::Stop;
+                break;
+            }
+
+            self.forward(&mut state, next, pos, &lora);
+        }
+        if text.len() > emitted {
+            on_token(&text[emitted..]);
+        }
+
+        Ok(NativeOutput {
+            text,
+            prompt_tokens: n_prompt,
+            cached_tokens: cached,
+            completion_tokens: tokens.len() - n_prompt,
+            finish_reason,
+            prompt_eval,
+            logprobs,
+        })
+    }
+
+    fn token_logprob(&self, distribution: &[f32], token: u32, alternatives: usize) -> TokenLogprob {
+        let text = |id: u32| String::from_utf8_lossy(&self.tokenizer.token_bytes(id)).into_owned();
+        TokenLogprob {
+            token: text(token),
+            logprob: distribution[token as usize],
+            top_logprobs: top_logprobs(distribution, alternatives)
+                .into_iter()
+                .map(|(id, logprob)| TopLogprob { token: text(id), logprob })
+                .collect(),
+        }
+    }
+
+    // Embedding of `text`: the final normalized hidden states of its tokens, pooled
+    pub fn embed(&self, text: &str, pooling: Pooling) -> Result<Vec<f32>> {
+        let tokens = self.tokenizer.encode(text, true);
+        if tokens.len() > self.config.n_ctx {
+            return Err(anyhow!("Text is {} tokens, model context length is {}", tokens.len(), self.config.n_ctx));
+        }
+
+        let lora = self.default_lora();
+        let mut state = State::new(&self.config, tokens.len());
+        let mut pooled = vec![0f32; self.config.n_embd];
+        for (pos, &token) in tokens.iter().enumerate() {
+            self.forward_hidden(&mut state, token, pos, &lora);
+            match pooling {
+                Pooling::Mean => pooled.iter_mut().zip(&state.xb).for_each(|(p, h)| *p += h / tokens.len() as f32),
+                Pooling::Last => pooled.copy_from_slice(&state.xb),
+            }
+        }
+        Ok(pooled)
+    }
+
+    // Process the tokens all `prompts` start with into the prompt cache, so each of them
+    // resumes after the shared part
+    pub fn cache_shared_prefix(&self, prompts: &[&str]) {
+        let mut encoded = prompts.iter().map(|prompt| self.tokenizer.encode(prompt, true));
+        let Some(mut shared) = encoded.next() else {
+            return;
+        };
+        for tokens in encoded {
+            let common = shared.iter().zip(&tokens).take_while(|(a, b)| a == b).count();
+            shared.truncate(common);
+        }
+        // The BOS token alone is not worth a cache entry
+        if shared.len() < 2 || shared.len() >= self.config.n_ctx || !self.prompt_cache.is_enabled() {
+            return;
+        }
+        // Prepared for requests running with the active adapters
+        let lora = self.default_lora();
+        if self.prompt_cache.contains(lora.variant, &shared) {
+            return;
+        }
+
+        let mut state = State::new(&self.config, shared.len());
+        let started = Instant::now();
+        for (pos, &token) in shared.iter().enumerate() {
+            self.forward_hidden(&mut state, token, pos, &lora);
+        }
+        self.prompt_cache.record_processing(shared.len(), started.elapsed());
+        self.save_prompt(&state, &shared, lora.variant);
+        log::debug!("Cached {} prompt tokens shared by a batch of {}", shared.len(), prompts.len());
+    }
+
+    // Copy the KV state of the longest cached prefix of `tokens` into `state`; returns
+    // the number of positions that no longer need a forward pass
+    fn restore_prompt(&self, s: &mut State, tokens: &[u32], variant: u64) -> usize {
+        let Some((snapshot, shared)) = self.prompt_cache.lookup(variant, tokens) else {
+            return 0;
+        };
+        let len = shared * self.config.kv_dim();
+        for (cache, saved) in s.key_cache.iter_mut().zip(&snapshot.keys) {
+            cache[..len].copy_from_slice(&saved[..len]);
+        }
+        for (cache, saved) in s.value_cache.iter_mut().zip(&snapshot.values) {
+            cache[..len].copy_from_slice(&saved[..len]);
+        }
+        shared
+    }
+
+    // Keep the KV state of a processed prompt for later requests sharing its prefix
+    fn save_prompt(&self, s: &State, tokens: &[u32], variant: u64) {
+        if !self.prompt_cache.is_enabled() || self.prompt_cache.contains(variant, tokens) {
+            return;
+        }
+        let len = tokens.len() * self.config.kv_dim();
+        self.prompt_cache.insert(KvSnapshot {
+            variant,
+            tokens: tokens.to_vec(),
+            keys: s.key_cache.iter().map(|k| k[..len].to_vec()).collect(),
+            values: s.value_cache.iter().map(|v| v[..len].to_vec()).collect(),
+        });
+    }
+
+    // Run one token through the network; leaves next-token logits in `state.logits`
+    fn forward(&self, s: &mut State, token: u32, pos: usize, lora: &Lora) {
+        self.forward_hidden(s, token, pos, lora);
+        self.output.matvec(&s.xb, &mut s.logits);
+        lora.apply(self.layers.len() * ADAPTABLE.len(), &s.xb, &mut s.logits);
+    }
+
+    // The transformer layers without the output projection; leaves the normalized
+    // final hidden state in `state.xb`
+    fn forward_hidden(&self, s: &mut State, token: u32, pos: usize, lora: &Lora) {
+        let c = &self.config;
+        let (head_dim, kv_dim) = (c.head_dim(), c.kv_dim());
+        let group = c.n_head / c.n_head_kv;
+
+        self.token_embd.row(token as usize, &mut s.x);
+
+        for (l, layer) in self.layers.iter().enumerate() {
+            rms_norm(&mut s.xb, &s.x, &layer.attn_norm, c.rms_eps);
+
+            let slot = l * ADAPTABLE.len();
+            layer.wq.matvec(&s.xb, &mut s.q);
+            layer.wk.matvec(&s.xb, &mut s.k);
+            layer.wv.matvec(&s.xb, &mut s.v);
+            lora.apply(slot, &s.xb, &mut s.q);
+            lora.apply(slot + 1, &s.xb, &mut s.k);
+            lora.apply(slot + 2, &s.xb, &mut s.v);
+
+            for head in s.q.chunks_exact_mut(head_dim) {
+                rope(head, pos, c.rope_dims, c.rope_base);
+            }
+            for head in s.k.chunks_exact_mut(head_dim) {
+                rope(head, pos, c.rope_dims, c.rope_base);
+            }
+
+            s.key_cache[l][pos * kv_dim..(pos + 1) * kv_dim].copy_from_slice(&s.k);
+            s.value_cache[l][pos * kv_dim..(pos + 1) * kv_dim].copy_from_slice(&s.v);
+
+            let scale = 1.0 / (head_dim as f32).sqrt();
+            for h in 0..c.n_head {
+                let q = &s.q[h * head_dim..(h + 1) * head_dim];
+                let kv_offset = (h / group) * head_dim;
+                let att = &mut s.att[..=pos];
+                for (t, a) in att.iter_mut().enumerate() {
+                    let k = &s.key_cache[l][t * kv_dim + kv_offset..t * kv_dim + kv_offset + head_dim];
+                    *a = dot(q, k) * scale;
+                }
+                softmax(att);
+
+                let out = &mut s.xb[h * head_dim..(h + 1) * head_dim];
+                out.fill(0.0);
+                for (t, a) in att.iter().enumerate() {
+                    let v = &s.value_cache[l][t * kv_dim + kv_offset..t * kv_dim + kv_offset + head_dim];
+                    for (o, v) in out.iter_mut().zip(v) {
+                        *o += a * v;
+                    }
+                }
+            }
+
+            layer.wo.matvec(&s.xb, &mut s.xb2);
+            lora.apply(slot + 3, &s.xb, &mut s.xb2);
+            for (x, d) in s.x.iter_mut().zip(&s.xb2) {
+                *x += d;
+            }
+
+            rms_norm(&mut s.xb, &s.x, &layer.ffn_norm, c.rms_eps);
+            layer.w_gate.matvec(&s.xb, &mut s.hb);
+            layer.w_up.matvec(&s.xb, &mut s.hb2);
+            lora.apply(slot + 4, &s.xb, &mut s.hb);
+            lora.apply(slot + 5, &s.xb, &mut s.hb2);
+            for (g, u) in s.hb.iter_mut().zip(&s.hb2) {
+                *g = *g / (1.0 + (-*g).exp()) * u;
+            }
+            layer.w_down.matvec(&s.hb, &mut s.xb2);
+            lora.apply(slot + 6, &s.hb, &mut s.xb2);
+            for (x, d) in s.x.iter_mut().zip(&s.xb2) {
+                *x += d;
+            }
+        }
+
+        debug_assert!(pos < s.capacity);
+        rms_norm(&mut s.xb, &s.x, &self.output_norm, c.rms_eps);
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn rms_norm(out: &mut [f32], x: &[f32], weight: &[f32], eps: f32) {
+    let mean_sq = x.iter().map(|v| v * v).sum::<f32>() / x.len() as f32;
+    let scale = 1.0 / (mean_sq + eps).sqrt();
+    for ((o, v), w) in out.iter_mut().zip(x).zip(weight) {
+        *o = v * scale * w;
+    }
+}
+
+// Rotary embedding over adjacent pairs, as ggml's "normal" rope mode
+fn rope(head: &mut [f32], pos: usize, dims: usize, base: f32) {
+    for i in (0..dims.min(head.len())).step_by(2) {
+        let theta = pos as f32 * base.powf(-(i as f32) / dims as f32);
+        let (sin, cos) = theta.sin_cos();
+        let (x0, x1) = (head[i], head[i + 1]);
+        head[i] = x0 * cos - x1 * sin;
+        head[i + 1] = x0 * sin + x1 * cos;
+    }
+}
+
+fn softmax(x: &mut [f32]) {
+    let max = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for v in x.iter_mut() {
+        *v = (*v - max).exp();
+        sum += *v;
+    }
+    for v in x.iter_mut() {
+        *v /= sum;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use crate::gguf::testing::GgufBuilder;
+    use crate::gguf::{GgmlType, GgufValue};
+    use crate::quant::testing::quantize_q8_0;
+    use crate::tokenizer::testing::{spm_metadata, SPM_VOCAB};
+
+    // Deterministic pseudo-random weights in [-0.5, 0.5)
+    fn weights(seed: u64, n: usize) -> Vec<f32> {
+        let mut state = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (0..n)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                ((state >> 40) as f32 / (1u64 << 24) as f32) - 0.5
+            })
+            .collect()
+    }
+
+    // Two-layer llama with 32-wide embeddings; Q8_0 matrices when `quantized`
+    pub fn tiny_llama(quantized: bool) -> Vec<u8> {
+        let (n_embd, n_ff, n_vocab, kv_dim) = (32u64, 64u64, SPM_VOCAB.len() as u64, 16u64);
+        let mut builder = spm_metadata(GgufBuilder::new())
+            .kv("general.architecture", GgufValue::String("llama".to_string()))
+            .kv("llama.context_length", GgufValue::U32(64))
+            .kv("llama.embedding_length", GgufValue::U32(n_embd as u32))
+            .kv("llama.block_count", GgufValue::U32(2))
+            .kv("llama.feed_forward_length", GgufValue::U32(n_ff as u32))
+            .kv("llama.attention.head_count", GgufValue::U32(4))
+            .kv("llama.attention.head_count_kv", GgufValue::U32(2))
+            .kv(
+                "tokenizer.chat_template",
+                GgufValue::String("{% for m in messages %}{{ '<|user|>\n' + m['content'] + eos_token }}{% endfor %}".to_string()),
+            );
+
+        let mut seed = 0;
+        let mut matrix = |builder: GgufBuilder, name: &str, cols: u64, rows: u64| {
+            seed += 1;
+            let values = weights(seed, (cols * rows) as usize);
+            if quantized {
+                builder.tensor(name, &[cols, rows], GgmlType::Q8_0, quantize_q8_0(&values))
+            } else {
+                builder.tensor_f32(name, &[cols, rows], &values)
+            }
+        };
+
+        builder = matrix(builder, "token_embd.weight", n_embd, n_vocab);
+        for i in 0..2 {
+            let name = |s: &str| format!("blk.{}.{}", i, s);
+            builder = builder.tensor_f32(&name("attn_norm.weight"), &[n_embd], &vec![1.0; n_embd as usize]);
+            builder = matrix(builder, &name("attn_q.weight"), n_embd, n_embd);
+            builder = matrix(builder, &name("attn_k.weight"), n_embd, kv_dim);
+            builder = matrix(builder, &name("attn_v.weight"), n_embd, kv_dim);
+            builder = matrix(builder, &name("attn_output.weight"), n_embd, n_embd);
+            builder = builder.tensor_f32(&name("ffn_norm.weight"), &[n_embd], &vec![1.0; n_embd as usize]);
+            builder = matrix(builder, &name("ffn_gate.weight"), n_embd, n_ff);
+            builder = matrix(builder, &name("ffn_up.weight"), n_embd, n_ff);
+            builder = matrix(builder, &name("ffn_down.weight"), n_ff, n_embd);
+        }
+        builder = builder.tensor_f32("output_norm.weight", &[n_embd], &vec![1.0; n_embd as usize]);
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+    use crate::gguf::GgufValue;
+    use crate::tokenizer::testing::SPM_VOCAB;
+
+    fn load(quantized: bool) -> NativeModel {
+        let bytes = tiny_llama(quantized);
+        let gguf = GgufFile::parse(&bytes).unwrap();
+        NativeModel::load(&gguf, Arc::new(bytes.into())).unwrap()
+    }
+
+    #[test]
+    fn test_forward_is_deterministic_and_finite() {
+        let model = load(false);
+        assert_eq!(model.config().n_vocab, SPM_VOCAB.len());
+        assert_eq!(model.tokenizer().vocab_size(), SPM_VOCAB.len());
+
+        let greedy = SamplingParams { temperature: 0.0, ..Default::default() };
+        let first = model.generate("ab", 8, &greedy).unwrap();
+        let second = model.generate("ab", 8, &greedy).unwrap();
+        assert_eq!(first.text, second.text);
+        assert_eq!(first.prompt_tokens, 2);
+        assert!(first.completion_tokens <= 8);
+
+        // Streamed pieces add up to the final text
+        let mut streamed = String::new();
+        let third = model.generate_stream("ab", 8, &greedy, &GenerateOptions::default(), &mut |piece| streamed.push_str(piece)).unwrap();
+        assert_eq!(streamed, third.text);
+        assert_eq!(third.text, first.text);
+
+        let mut state = State::new(model.config(), 4);
+        model.forward(&mut state, 1, 0, &model.default_lora());
+        assert!(state.logits.iter().all(|l| l.is_finite()));
+    }
+
+    #[test]
+    fn test_prompt_prefix_reused_from_cache() {
+        let model = load(false);
+        let mut uncached = load(false);
+        uncached.prompt_cache = PromptCache::new(0);
+        let greedy = SamplingParams { temperature: 0.0, ..Default::default() };
+
+        let first = model.generate("abc ab", 6, &greedy).unwrap();
+        assert_eq!(first.cached_tokens, 0);
+        // A prompt extending the first resumes after its shared tokens
+        let second = model.generate("abc abc", 6, &greedy).unwrap();
+        assert!(second.cached_tokens > 0 && second.cached_tokens < second.prompt_tokens);
+        assert_eq!(second.text, uncached.generate("abc abc", 6, &greedy).unwrap().text);
+        assert_eq!(model.generate("abc ab", 6, &greedy).unwrap().text, first.text);
+
+        let stats = model.prompt_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 1, 2));
+        assert_eq!(uncached.prompt_cache_stats().entries, 0);
+    }
+
+    #[test]
+    fn test_lora_adapters_applied_on_request() {
+        use crate::lora::{testing::tiny_adapter, LoraSpec};
+
+        let mut model = load(false);
+        let adapter = |name: &str, active: bool| {
+            let bytes = tiny_adapter(0.05, 4);
+            let spec = LoraSpec { name: Some(name.to_string()), active, ..LoraSpec::new("unused.gguf") };
+            LoraAdapter::from_gguf(&spec, &GgufFile::parse(&bytes).unwrap(), &bytes).unwrap()
+        };
+        model.add_adapter(adapter("tools", false)).unwrap();
+        assert!(model.add_adapter(adapter("tools", true)).is_err());
+        assert_eq!(model.adapters()[0].tensors, 2);
+
+        let logits = |model: &NativeModel, selection: Option<&[LoraSelection]>| {
+            let lora = model.select_lora(selection).unwrap();
+            let mut state = State::new(model.config(), 4);
+            for (pos, token) in [1u32, 10, 8].into_iter().enumerate() {
+                model.forward(&mut state, token, pos, &lora);
+            }
+            state.logits
+        };
+        let select = |scale: Option<f32>| vec![LoraSelection { name: "tools".to_string(), scale }];
+        // An inactive adapter only changes requests that name it; a zero scale is the base model
+        let base = logits(&model, None);
+        assert_ne!(logits(&model, Some(&select(None))), base);
+        assert_eq!(logits(&model, Some(&select(Some(0.0)))), base);
+        assert!(model.select_lora(Some(&[LoraSelection { name: "nope".to_string(), scale: None }])).is_err());
+
+        // KV state computed under the adapter is cached apart from the base model's
+        let greedy = SamplingParams { temperature: 0.0, ..Default::default() };
+        let tools = select(None);
+        let with_tools = GenerateOptions { lora: Some(&tools), ..Default::default() };
+        model.generate("abc ab", 4, &greedy).unwrap();
+        let first = model.generate_stream("abc ab", 4, &greedy, &with_tools, &mut |_| {}).unwrap();
+        let second = model.generate_stream("abc ab", 4, &greedy, &with_tools, &mut |_| {}).unwrap();
+        assert_eq!((first.cached_tokens, second.text), (0, first.text));
+        assert!(second.cached_tokens > 0);
+
+        model.add_adapter(adapter("default", true)).unwrap();
+        let default = [LoraSelection { name: "default".to_string(), scale: None }];
+        assert_eq!(logits(&model, None), logits(&model, Some(&default)));
+        assert_ne!(logits(&model, None), base);
+    }
+
+    #[test]
+    fn test_seed_and_stop_sequences() {
+        let model = load(false);
+
+        let seeded = SamplingParams { temperature: 1.5, top_k: 0, min_p: 0.0, seed: 1234, ..Default::default() };
+        let a = model.generate("ab", 16, &seeded).unwrap();
+        let b = model.generate("ab", 16, &seeded).unwrap();
+        assert_eq!(a.text, b.text);
+
+        // Stopping on a piece of the text keeps only what came before it
+        let stop = a.text.trim_start().chars().nth(2).unwrap().to_string();
+        let stopped = model.generate("ab", 16, &SamplingParams { stop: vec![stop.clone()], ..seeded }).unwrap();
+        assert_eq!(stopped.text, a.text[..a.text.find(&stop).unwrap()]);
+        assert!(stopped.completion_tokens < a.completion_tokens);
+    }
+
+    #[test]
+    fn test_grammar_constrains_sampling() {
+        let model = load(false);
+        let grammar = Grammar::parse(r#"root ::= "ab"{1,3} "!" | "c""#).unwrap();
+
+        // Whatever the sampler prefers, only grammar-valid text comes out, and generation
+        // ends once the grammar cannot continue
+        for seed in 1..6 {
+            let params = SamplingParams { temperature: 1.5, top_k: 0, min_p: 0.0, seed, ..Default::default() };
+            let output = model.generate_stream("ab", 16, &params, &GenerateOptions { grammar: Some(&grammar), ..Default::default() }, &mut |_| {}).unwrap();
+            assert!(grammar.matches(&output.text), "{:?}", output.text);
+        }
+    }
+
+    #[test]
+    fn test_quantized_weights_track_f32() {
+        let exact = load(false);
+        let quantized = load(true);
+
+        let mut a = State::new(exact.config(), 4);
+        let mut b = State::new(quantized.config(), 4);
+        for (pos, token) in [1u32, 10, 8].into_iter().enumerate() {
+            exact.forward(&mut a, token, pos, &exact.default_lora());
+            quantized.forward(&mut b, token, pos, &quantized.default_lora());
+        }
+        for (x, y) in a.logits.iter().zip(&b.logits) {
+            assert!((x - y).abs() < 0.05, "{} vs {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_rejects_unsupported_architecture() {
+        let bytes = crate::gguf::testing::GgufBuilder::new()
+            .kv("general.architecture", GgufValue::String("gptneox".to_string()))
+            .build();
+        let gguf = GgufFile::parse(&bytes).unwrap();
+        let err = NativeModel::load(&gguf, Arc::new(bytes.into())).err().unwrap();
+        assert!(err.to_string().contains("gptneox"));
+    }
+
+    #[test]
+    fn test_pooled_matvec_matches_single_thread() {
+        let (rows, cols) = (320usize, 256usize);
+        let weights: Vec<f32> = (0..rows * cols).map(|i| ((i * 7919) % 101) as f32 / 50.0 - 1.0).collect();
+        let bytes = crate::gguf::testing::GgufBuilder::new()
+            .tensor_f32("w", &[cols as u64, rows as u64], &weights)
+            .build();
+        let gguf = GgufFile::parse(&bytes).unwrap();
+        let data = Arc::new(bytes.into());
+        let tensor = QTensor::new(&gguf, &data, gguf.tensor("w").unwrap()).unwrap();
+
+        let x: Vec<f32> = (0..cols).map(|i| (i % 13) as f32 / 13.0).collect();
+        let expected: Vec<f32> = weights.chunks_exact(cols).map(|row| dot(row, &x)).collect();
+        // Repeated calls reuse the same workers
+        for _ in 0..3 {
+            let mut out = vec![0f32; rows];
+            tensor.matvec(&x, &mut out);
+            assert_eq!(out, expected);
+        }
+
+        let pool = WorkerPool::new(3).unwrap();
+        let seen = std::sync::Mutex::new(Vec::new());
+        assert!(pool.run(&|i| seen.lock().unwrap().push(i)));
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        assert_eq!(seen, vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,203 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const PROMPT_CACHE_ENV_VAR: &str = "SUPERTINYWASMLLM_PROMPT_CACHE";
+
+// Prompts kept when SUPERTINYWASMLLM_PROMPT_CACHE is unset
+pub const DEFAULT_PROMPT_CACHE_ENTRIES: usize = 4;
+
+// Attention keys and values of every layer for the first `tokens.len()` positions of a prompt
+pub struct KvSnapshot {
+    // Weights the state was computed with: 0 for the base model, otherwise a hash of the
+    // LoRA adapters applied. Prompts only resume from snapshots of the same variant.
+    pub variant: u64,
+    pub tokens: Vec<u32>,
+    pub keys: Vec<Vec<f32>>,
+    pub values: Vec<Vec<f32>>,
+}
+
+// Running totals, as reported in status output
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PromptCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    // Prompt tokens whose forward pass was skipped
+    pub reused_tokens: u64,
+    // Estimated from the measured per-token prompt processing time
+    pub time_saved_ms: u64,
+}
+
+struct Entry {
+    snapshot: Arc<KvSnapshot>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Keyed by a hash of the variant and prompt tokens
+    entries: HashMap<u64, Entry>,
+    clock: u64,
+    stats: PromptCacheStats,
+    processed_tokens: u64,
+    processing_time: Duration,
+}
+
+// KV state of recent prompts, one cache per loaded model. A new prompt resumes from the
+// entry sharing its longest token prefix, so a system prompt repeated across requests is
+// processed once. Entries are replaced least recently used first.
+pub struct PromptCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl PromptCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, inner: Mutex::new(Inner::default()) }
+    }
+
+    // Capacity from SUPERTINYWASMLLM_PROMPT_CACHE; 0 disables caching
+    pub fn from_env() -> Self {
+        let capacity = match std::env::var(PROMPT_CACHE_ENV_VAR) {
+            Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+                log::warn!("Invalid {} '{}', keeping {} prompts", PROMPT_CACHE_ENV_VAR, value, DEFAULT_PROMPT_CACHE_ENTRIES);
+                DEFAULT_PROMPT_CACHE_ENTRIES
+            }),
+            Err(_) => DEFAULT_PROMPT_CACHE_ENTRIES,
+        };
+        Self::new(capacity)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    // The cached snapshot sharing the longest prefix with `tokens`, and how many of its
+    // positions can be reused. At least the last prompt token is always left to run, as
+    // its logits start generation. Counts a hit or a miss.
+    pub fn lookup(&self, variant: u64, tokens: &[u32]) -> Option<(Arc<KvSnapshot>, usize)> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let mut inner = self.lock();
+        let limit = tokens.len().saturating_sub(1);
+        let best = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.snapshot.variant == variant)
+            .map(|(&key, entry)| (key, common_prefix(&entry.snapshot.tokens, tokens).min(limit)))
+            .filter(|&(_, shared)| shared > 0)
+            .max_by_key(|&(_, shared)| shared);
+
+        let Some((key, shared)) = best else {
+            inner.stats.misses += 1;
+            return None;
+        };
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.get_mut(&key).expect("entry just found");
+        entry.last_used = clock;
+        let snapshot = Arc::clone(&entry.snapshot);
+
+        inner.stats.hits += 1;
+        inner.stats.reused_tokens += shared as u64;
+        if inner.processed_tokens > 0 {
+            let per_token = inner.processing_time / inner.processed_tokens as u32;
+            inner.stats.time_saved_ms += (per_token * shared as u32).as_millis() as u64;
+        }
+        Some((snapshot, shared))
+    }
+
+    // Record how long `tokens` prompt positions took to process, for time-saved estimates
+    pub fn record_processing(&self, tokens: usize, elapsed: Duration) {
+        let mut inner = self.lock();
+        inner.processed_tokens += tokens as u64;
+        inner.processing_time += elapsed;
+    }
+
+    // Keep the KV state of a fully processed prompt
+    pub fn insert(&self, snapshot: KvSnapshot) {
+        if !self.is_enabled() || snapshot.tokens.is_empty() {
+            return;
+        }
+        let key = hash_tokens(snapshot.variant, &snapshot.tokens);
+        let mut inner = self.lock();
+        inner.clock += 1;
+        let clock = inner.clock;
+        if let Some(entry) = inner.entries.get_mut(&key) {
+            entry.last_used = clock;
+            return;
+        }
+        if inner.entries.len() >= self.capacity {
+            if let Some(oldest) = inner.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(&k, _)| k) {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(key, Entry { snapshot: Arc::new(snapshot), last_used: clock });
+    }
+
+    pub fn contains(&self, variant: u64, tokens: &[u32]) -> bool {
+        self.lock().entries.contains_key(&hash_tokens(variant, tokens))
+    }
+
+    pub fn stats(&self) -> PromptCacheStats {
+        let inner = self.lock();
+        PromptCacheStats { entries: inner.entries.len(), ..inner.stats.clone() }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn common_prefix(a: &[u32], b: &[u32]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+// FNV-1a over the variant and token ids
+fn hash_tokens(variant: u64, tokens: &[u32]) -> u64 {
+    variant
+        .to_le_bytes()
+        .into_iter()
+        .chain(tokens.iter().flat_map(|t| t.to_le_bytes()))
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tokens: &[u32]) -> KvSnapshot {
+        KvSnapshot { variant: 0, tokens: tokens.to_vec(), keys: vec![vec![0.0; tokens.len()]], values: vec![vec![0.0; tokens.len()]] }
+    }
+
+    #[test]
+    fn test_longest_prefix_reused_and_oldest_evicted() {
+        let cache = PromptCache::new(2);
+        assert!(cache.lookup(0, &[1, 2, 3]).is_none());
+        cache.insert(snapshot(&[1, 2, 3, 4]));
+        cache.insert(snapshot(&[1, 2, 9]));
+
+        let (hit, shared) = cache.lookup(0, &[1, 2, 3, 4, 5]).unwrap();
+        assert_eq!((hit.tokens.len(), shared), (4, 4));
+        // The identical prompt still runs its last token
+        assert_eq!(cache.lookup(0, &[1, 2, 9]).unwrap().1, 2);
+        assert!(cache.lookup(0, &[7, 1]).is_none());
+
+        // [1, 2, 3, 4] was used least recently
+        cache.insert(snapshot(&[5, 6]));
+        cache.insert(snapshot(&[1, 2, 9]));
+        assert!(!cache.contains(0, &[1, 2, 3, 4]) && cache.contains(0, &[5, 6]));
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses, stats.reused_tokens), (2, 2, 2, 6));
+        assert!(PromptCache::new(0).lookup(0, &[1, 2]).is_none());
+
+        // State computed under other weights is never reused
+        assert!(cache.lookup(7, &[1, 2, 9, 4]).is_none());
+        cache.insert(KvSnapshot { variant: 7, ..snapshot(&[1, 2, 9]) });
+        assert!(cache.contains(7, &[1, 2, 9]) && cache.contains(0, &[1, 2, 9]));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::gguf::GgmlType;
+use anyhow::{anyhow, Result};
+
+// Decode an IEEE half-precision value
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exp = ((bits >> 10) & 0x1f) as u32;
+    let mant = (bits & 0x3ff) as u32;
+
+    let out = match (exp, mant) {
+        (0, 0) => sign,
+        (0, _) => {
+            // Subnormal: renormalise the mantissa
+            let mut e = 127 - 15 + 1;
+            let mut m = mant;
+            while m & 0x400 == 0 {
+                m <<= 1;
+                e -= 1;
+            }
+            sign | (e << 23) | ((m & 0x3ff) << 13)
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | (mant << 13),
+        _ => sign | ((exp + 127 - 15) << 23) | (mant << 13),
+    };
+    f32::from_bits(out)
+}
+
+// Encode an f32 as half precision (round to nearest, used for fixtures and adapters)
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7f_ffff;
+
+    if exp == 0xff {
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        if e < -10 {
+            return sign;
+        }
+        let m = (mant | 0x80_0000) >> (1 - e);
+        return sign | ((m + 0x1000) >> 13) as u16;
+    }
+    let half = sign | ((e as u16) << 10) | (mant >> 13) as u16;
+    // Round half up on the dropped mantissa bits; overflow carries into the exponent
+    if mant & 0x1000 != 0 {
+        half + 1
+    } else {
+        half
+    }
+}
+
+fn read_f16(bytes: &[u8], at: usize) -> f32 {
+    f16_to_f32(u16::from_le_bytes([bytes[at], bytes[at + 1]]))
+}
+
+pub fn is_supported(ggml_type: GgmlType) -> bool {
+    matches!(
+        ggml_type,
+        GgmlType::F32
+            | GgmlType::F16
+            | GgmlType::BF16
+            | GgmlType::Q4_0
+            | GgmlType::Q8_0
+            | GgmlType::Q4_K
+            | GgmlType::Q6_K
+    )
+}
+
+// Dequantize `out.len()` consecutive elements stored in `bytes`
+pub fn dequantize(ggml_type: GgmlType, bytes: &[u8], out: &mut [f32]) -> Result<()> {
+    let (block_size, type_size) = ggml_type
+        .block_layout()
+        .ok_or_else(|| anyhow!("Unsupported tensor type {}", ggml_type.name()))?;
+    let (block_size, type_size) = (block_size as usize, type_size as usize);
+
+    if !out.len().is_multiple_of(block_size) || bytes.len() < out.len() / block_size * type_size {
+        return Err(anyhow!(
+            "Cannot dequantize {} elements of {} from {} bytes",
+            out.len(),
+            ggml_type.name(),
+            bytes.len()
+        ));
+    }
+
+    let blocks = bytes.chunks_exact(type_size).zip(out.chunks_exact_mut(block_size));
+    match ggml_type {
+        GgmlType::F32 => {
+            for (b, y) in blocks {
+                y[0] = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            }
+        }
+        GgmlType::F16 => {
+            for (b, y) in blocks {
+                y[0] = read_f16(b, 0);
+            }
+        }
+        GgmlType::BF16 => {
+            for (b, y) in blocks {
+                y[0] = f32::from_bits((u16::from_le_bytes([b[0], b[1]]) as u32) << 16);
+            }
+        }
+        GgmlType::Q4_0 => blocks.for_each(|(b, y)| dequantize_q4_0(b, y)),
+        GgmlType::Q8_0 => blocks.for_each(|(b, y)| dequantize_q8_0(b, y)),
+        GgmlType::Q4_K => blocks.for_each(|(b, y)| dequantize_q4_k(b, y)),
+        GgmlType::Q6_K => blocks.for_each(|(b, y)| dequantize_q6_k(b, y)),
+        other => return Err(anyhow!("Tensor type {} is not supported by the native backend", other.name())),
+    }
+    Ok(())
+}
+
+// block_q4_0: f16 scale, 16 bytes of packed 4-bit values (low nibbles first)
+fn dequantize_q4_0(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let qs = &b[2..18];
+    for j in 0..16 {
+        y[j] = ((qs[j] & 0x0f) as i32 - 8) as f32 * d;
+        y[j + 16] = ((qs[j] >> 4) as i32 - 8) as f32 * d;
+    }
+}
+
+// block_q8_0: f16 scale, 32 signed bytes
+fn dequantize_q8_0(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    for (j, q) in b[2..34].iter().enumerate() {
+        y[j] = (*q as i8) as f32 * d;
+    }
+}
+
+fn scale_min_k4(j: usize, q: &[u8]) -> (u8, u8) {
+    if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        (
+            (q[j + 4] & 0x0f) | ((q[j - 4] >> 6) << 4),
+            (q[j + 4] >> 4) | ((q[j] >> 6) << 4),
+        )
+    }
+}
+
+// block_q4_K: f16 d, f16 dmin, 12 bytes of 6-bit scales/mins, 128 bytes of nibbles
+fn dequantize_q4_k(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let dmin = read_f16(b, 2);
+    let scales = &b[4..16];
+    let qs = &b[16..144];
+
+    for (chunk, (q, y)) in qs.chunks_exact(32).zip(y.chunks_exact_mut(64)).enumerate() {
+        let (sc1, m1) = scale_min_k4(chunk * 2, scales);
+        let (sc2, m2) = scale_min_k4(chunk * 2 + 1, scales);
+        let (d1, min1) = (d * sc1 as f32, dmin * m1 as f32);
+        let (d2, min2) = (d * sc2 as f32, dmin * m2 as f32);
+        for l in 0..32 {
+            y[l] = d1 * (q[l] & 0x0f) as f32 - min1;
+            y[l + 32] = d2 * (q[l] >> 4) as f32 - min2;
+        }
+    }
+}
+
+// block_q6_K: 128 bytes low 4 bits, 64 bytes high 2 bits, 16 i8 scales, f16 d
+fn dequantize_q6_k(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 208);
+    for half in 0..2 {
+        let ql = &b[half * 64..half * 64 + 64];
+        let qh = &b[128 + half * 32..128 + half * 32 + 32];
+        let sc = &b[192 + half * 8..192 + half * 8 + 8];
+        let y = &mut y[half * 128..half * 128 + 128];
+        for l in 0..32 {
+            let is = l / 16;
+            let q1 = ((ql[l] & 0x0f) | ((qh[l] & 3) << 4)) as i32 - 32;
+            let q2 = ((ql[l + 32] & 0x0f) | (((qh[l] >> 2) & 3) << 4)) as i32 - 32;
+            let q3 = ((ql[l] >> 4) | (((qh[l] >> 4) & 3) << 4)) as i32 - 32;
+            let q4 = ((ql[l + 32] >> 4) | (((qh[l] >> 6) & 3) << 4)) as i32 - 32;
+            y[l] = d * (sc[is] as i8) as f32 * q1 as f32;
+            y[l + 32] = d * (sc[is + 2] as i8) as f32 * q2 as f32;
+            y[l + 64] = d * (sc[is + 4] as i8) as f32 * q3 as f32;
+            y[l + 96] = d * (sc[is + 6] as i8) as f32 * q4 as f32;
+        }
+    }
+}
+
+// Reference quantizers, used to build fixture models in tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::f32_to_f16;
+
+    pub fn quantize_q8_0(values: &[f32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for block in values.chunks(32) {
+            let amax = block.iter().fold(0f32, |m, v| m.max(v.abs()));
+            let d = amax / 127.0;
+            let id = if d > 0.0 { 1.0 / d } else { 0.0 };
+            out.extend_from_slice(&f32_to_f16(d).to_le_bytes());
+            out.extend(block.iter().map(|v| (v * id).round() as i8 as u8));
+        }
+        out
+    }
+
+    pub fn quantize_q4_0(values: &[f32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for block in values.chunks(32) {
+            let max = block.iter().fold(0f32, |m, v| if v.abs() > m.abs() { *v } else { m });
+            let d = max / -8.0;
+            let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+            out.extend_from_slice(&f32_to_f16(d).to_le_bytes());
+            for j in 0..16 {
+                let lo = ((block[j] * id + 8.5) as i32).clamp(0, 15) as u8;
+                let hi = ((block[j + 16] * id + 8.5) as i32).clamp(0, 15) as u8;
+                out.push(lo | (hi << 4));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+
+    fn ramp(n: usize) -> Vec<f32> {
+        (0..n).map(|i| (i as f32 - n as f32 / 2.0) / 16.0).collect()
+    }
+
+    #[test]
+    fn test_f16_round_trip() {
+        for v in [0.0f32, 1.0, -2.5, 0.000061, 65504.0, 0.333] {
+            let back = f16_to_f32(f32_to_f16(v));
+            assert!((back - v).abs() <= v.abs() * 1e-3, "{} -> {}", v, back);
+        }
+        assert!(f16_to_f32(0x7c00).is_infinite());
+    }
+
+    #[test]
+    fn test_q8_0_and_q4_0() {
+        let values = ramp(64);
+        let mut out = vec![0f32; 64];
+
+        dequantize(GgmlType::Q8_0, &quantize_q8_0(&values), &mut out).unwrap();
+        for (a, b) in values.iter().zip(&out) {
+            assert!((a - b).abs() < 0.02);
+        }
+
+        dequantize(GgmlType::Q4_0, &quantize_q4_0(&values), &mut out).unwrap();
+        for (a, b) in values.iter().zip(&out) {
+            assert!((a - b).abs() < 0.15);
+        }
+    }
+
+    #[test]
+    fn test_q4_k_block() {
+        // d = 1, dmin = 0.5, every sub-block scale 2 and min 1, nibbles 0..15 repeating
+        let mut block = Vec::new();
+        block.extend_from_slice(&f32_to_f16(1.0).to_le_bytes());
+        block.extend_from_slice(&f32_to_f16(0.5).to_le_bytes());
+        block.extend_from_slice(&[2, 2, 2, 2, 1, 1, 1, 1, 0x12, 0x12, 0x12, 0x12]);
+        block.extend((0..128).map(|i| (i % 16) as u8 | (((i + 1) % 16) as u8) << 4));
+
+        let mut out = vec![0f32; 256];
+        dequantize(GgmlType::Q4_K, &block, &mut out).unwrap();
+        assert_eq!(out[0], -0.5);
+        assert_eq!(out[3], 2.0 * 3.0 - 0.5);
+        assert_eq!(out[32], 2.0 * 1.0 - 0.5);
+        assert_eq!(out[255], 2.0 * ((127 + 1) % 16) as f32 - 0.5);
+    }
+
+    #[test]
+    fn test_q6_k_block() {
+        // All 6-bit values 33 (q = 1), scales 3, d = 0.5 -> every element 1.5
+        let mut block = vec![0x11u8; 128];
+        block.extend(std::iter::repeat_n(0xaa, 64));
+        block.extend(std::iter::repeat_n(3u8, 16));
+        block.extend_from_slice(&f32_to_f16(0.5).to_le_bytes());
+
+        let mut out = vec![0f32; 256];
+        dequantize(GgmlType::Q6_K, &block, &mut out).unwrap();
+        assert!(out.iter().all(|v| *v == 1.5));
+    }
+
+    #[test]
+    fn test_rejects_short_input() {
+        let mut out = vec![0f32; 32];
+        assert!(dequantize(GgmlType::Q8_0, &[0u8; 10], &mut out).is_err());
+        assert!(dequantize(GgmlType::Q4_K, &[0u8; 144], &mut out).is_err());
+    }
+}
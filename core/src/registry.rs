@@ -0,0 +1,490 @@
+use crate::{
+    BackendKind, ChatTemplate, EmbeddingRequest, EmbeddingResponse, Error, ErrorKind, InferenceRequest,
+    InferenceResponse, LoadMode, LoraInfo, LoraSpec, ModelMemory, PromptCacheStats, SuperTinyWasmLLM,
+};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+// One named model in a registry config. Unset fields fall back to the same
+// defaults as a single-model engine.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelSpec {
+    pub name: String,
+    pub path: String,
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub backend: Option<BackendKind>,
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub chat_template: Option<ChatTemplate>,
+    // Overrides the context length from the model metadata
+    pub context_length: Option<usize>,
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub load_mode: Option<LoadMode>,
+    // Adapters on top of the model, e.g. [{"name": "weather", "path": "weather.gguf", "scale": 0.8}]
+    #[serde(default)]
+    pub lora: Vec<LoraSpec>,
+}
+
+impl ModelSpec {
+    pub fn new(name: &str, path: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            path: path.to_string(),
+            backend: None,
+            chat_template: None,
+            context_length: None,
+            load_mode: None,
+            lora: Vec::new(),
+        }
+    }
+
+    // An engine configured from this spec; the model is not loaded yet
+    pub fn build(&self) -> SuperTinyWasmLLM {
+        let mut llm = SuperTinyWasmLLM::new(self.path.clone());
+        if let Some(kind) = self.backend {
+            llm = llm.with_backend_kind(kind);
+        }
+        if let Some(template) = self.chat_template {
+            llm = llm.with_chat_template(template);
+        }
+        if let Some(context_length) = self.context_length {
+            llm = llm.with_context_length(context_length);
+        }
+        if let Some(mode) = self.load_mode {
+            llm = llm.with_load_mode(mode);
+        }
+        for spec in &self.lora {
+            llm = llm.with_lora(spec.clone());
+        }
+        llm
+    }
+}
+
+// Registry config file, e.g.
+// {"models": [{"name": "tiny", "path": "tiny.gguf"}, {"name": "large", "path": "large.gguf"}],
+//  "default": "tiny", "aliases": {"planner": "tiny", "answer": "large"}, "memory_budget_mb": 1500}
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RegistryConfig {
+    pub models: Vec<ModelSpec>,
+    // Model for requests that name none; the first one when unset
+    pub default: Option<String>,
+    // Extra names for models, e.g. roles such as "planner"
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    // Loaded models are unloaded, least recently used first, to stay within this
+    pub memory_budget_mb: Option<u64>,
+}
+
+impl RegistryConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| anyhow!("Invalid model registry config: {}", e))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read model registry config: {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("In model registry config {}", path.display()))
+    }
+}
+
+struct Entry {
+    // None for engines handed over ready-made, which cannot be rebuilt and so are never unloaded
+    spec: Option<ModelSpec>,
+    llm: Option<SuperTinyWasmLLM>,
+    last_used: u64,
+}
+
+impl Entry {
+    fn is_loaded(&self) -> bool {
+        self.llm.as_ref().is_some_and(|llm| llm.is_loaded())
+    }
+
+    fn path(&self) -> &str {
+        match (&self.spec, &self.llm) {
+            (Some(spec), _) => &spec.path,
+            (None, Some(llm)) => llm.model_path(),
+            (None, None) => "",
+        }
+    }
+
+    // Bytes the model file takes once loaded: all of it can end up resident
+    fn memory_bytes(&self) -> u64 {
+        match self.llm.as_ref().and_then(|llm| llm.memory_usage()) {
+            Some(memory) => memory.file_bytes,
+            None => std::fs::metadata(self.path()).map(|m| m.len()).unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelStatus {
+    pub name: String,
+    pub path: String,
+    pub loaded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<ModelMemory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache: Option<PromptCacheStats>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lora: Vec<LoraInfo>,
+}
+
+// Named models loaded on first use. Requests pick one by name or alias; with a
+// memory budget, least recently used models are unloaded to make room.
+#[derive(Default)]
+pub struct ModelRegistry {
+    entries: HashMap<String, Entry>,
+    // Registration order, for status output and the implicit default
+    order: Vec<String>,
+    aliases: HashMap<String, String>,
+    default: Option<String>,
+    memory_budget: Option<u64>,
+    clock: u64,
+}
+
+impl ModelRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: RegistryConfig) -> Result<Self> {
+        let mut registry = Self::new();
+        for spec in config.models {
+            registry.add(spec)?;
+        }
+        for (alias, name) in config.aliases {
+            registry.alias(&alias, &name)?;
+        }
+        if let Some(name) = &config.default {
+            registry.set_default(name)?;
+        }
+        registry.memory_budget = config.memory_budget_mb.map(|mb| mb * 1024 * 1024);
+        Ok(registry)
+    }
+
+    pub fn load_config(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_config(RegistryConfig::load(path)?)
+    }
+
+    // A registry holding just `llm`, e.g. one built with a custom backend
+    pub fn single(name: &str, llm: SuperTinyWasmLLM) -> Self {
+        let mut registry = Self::new();
+        registry.insert(name, llm);
+        registry
+    }
+
+    pub fn with_memory_budget(mut self, bytes: u64) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    pub fn add(&mut self, spec: ModelSpec) -> Result<()> {
+        let name = spec.name.clone();
+        self.register(&name, Entry { spec: Some(spec), llm: None, last_used: 0 })
+    }
+
+    // Register a ready-made engine, replacing any model or alias of the same name. It
+    // is loaded on first use like the others but, as it cannot be rebuilt, never unloaded.
+    pub fn insert(&mut self, name: &str, llm: SuperTinyWasmLLM) {
+        self.entries.remove(name);
+        self.order.retain(|n| n != name);
+        self.aliases.remove(name);
+        self.entries.insert(name.to_string(), Entry { spec: None, llm: Some(llm), last_used: 0 });
+        self.order.push(name.to_string());
+    }
+
+    fn register(&mut self, name: &str, entry: Entry) -> Result<()> {
+        if self.entries.contains_key(name) || self.aliases.contains_key(name) {
+            return Err(anyhow!("Model '{}' is registered twice", name));
+        }
+        self.entries.insert(name.to_string(), entry);
+        self.order.push(name.to_string());
+        Ok(())
+    }
+
+    pub fn alias(&mut self, alias: &str, name: &str) -> Result<()> {
+        if self.entries.contains_key(alias) {
+            return Err(anyhow!("Alias '{}' shadows a model of the same name", alias));
+        }
+        let name = self.resolve(Some(name))?;
+        self.aliases.insert(alias.to_string(), name);
+        Ok(())
+    }
+
+    pub fn set_default(&mut self, name: &str) -> Result<()> {
+        self.default = Some(self.resolve(Some(name))?);
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name) || self.aliases.contains_key(name)
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.order
+    }
+
+    // The model a name or alias refers to; without one, the default model
+    pub fn resolve(&self, name: Option<&str>) -> Result<String> {
+        let resolved = match name {
+            Some(name) => self.aliases.get(name).map(String::as_str).unwrap_or(name),
+            None => match self.default.as_deref().or(self.order.first().map(String::as_str)) {
+                Some(name) => name,
+                None => return Err(Error::new(ErrorKind::ModelNotFound, "No models are registered").into()),
+            },
+        };
+        if !self.entries.contains_key(resolved) {
+            return Err(Error::new(ErrorKind::ModelNotFound, format!("Unknown model '{}'", resolved))
+                .with_detail("model", resolved)
+                .with_detail("available", &self.order)
+                .into());
+        }
+        Ok(resolved.to_string())
+    }
+
+    // The engine for a model, loading it first if needed
+    pub fn get(&mut self, name: Option<&str>) -> Result<&SuperTinyWasmLLM> {
+        let name = self.resolve(name)?;
+        if !self.entries[&name].is_loaded() {
+            self.load(&name)?;
+        }
+        self.clock += 1;
+        let entry = self.entries.get_mut(&name).expect("resolved model");
+        entry.last_used = self.clock;
+        Ok(entry.llm.as_ref().expect("loaded model"))
+    }
+
+    // The engine for a model if it is loaded, without loading it
+    pub fn peek(&self, name: Option<&str>) -> Option<&SuperTinyWasmLLM> {
+        let entry = self.entries.get(&self.resolve(name).ok()?)?;
+        entry.llm.as_ref().filter(|llm| llm.is_loaded())
+    }
+
+    fn load(&mut self, name: &str) -> Result<()> {
+        let needed = self.entries[name].memory_bytes();
+        if let Some(budget) = self.memory_budget {
+            if needed > budget {
+                return Err(Error::new(
+                    ErrorKind::ModelLoadFailed,
+                    format!("Model '{}' needs {} bytes, more than the memory budget of {}", name, needed, budget),
+                )
+                .with_detail("model", name)
+                .with_detail("memory_budget_bytes", budget)
+                .into());
+            }
+            while self.loaded_bytes() + needed > budget {
+                let victim = self.entries.iter()
+                    .filter(|(n, e)| n.as_str() != name && e.spec.is_some() && e.is_loaded())
+                    .min_by_key(|(_, e)| e.last_used)
+                    .map(|(n, _)| n.clone());
+                match victim {
+                    Some(victim) => {
+                        log::info!("Unloading model '{}' to stay within the memory budget", victim);
+                        self.unload(&victim);
+                    }
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::ModelLoadFailed,
+                            format!("Not enough of the memory budget left to load model '{}'", name),
+                        )
+                        .with_detail("model", name)
+                        .with_detail("memory_budget_bytes", budget)
+                        .into())
+                    }
+                }
+            }
+        }
+
+        log::info!("Loading model '{}'", name);
+        let entry = self.entries.get_mut(name).expect("resolved model");
+        let mut llm = match (entry.llm.take(), &entry.spec) {
+            (Some(llm), _) => llm,
+            (None, Some(spec)) => spec.build(),
+            (None, None) => unreachable!("entries without a spec keep their engine"),
+        };
+        let result = llm.load_model().with_context(|| format!("Failed to load model '{}'", name));
+        // Ready-made engines are kept even when loading fails, so a later call can retry
+        if result.is_ok() || entry.spec.is_none() {
+            entry.llm = Some(llm);
+        }
+        result
+    }
+
+    // Remove a model from the registry and hand over its engine, loaded or not, e.g.
+    // to serve it on its own
+    pub fn take(&mut self, name: Option<&str>) -> Result<SuperTinyWasmLLM> {
+        let name = self.resolve(name)?;
+        let entry = self.entries.remove(&name).expect("resolved model");
+        self.order.retain(|n| *n != name);
+        self.aliases.retain(|_, target| *target != name);
+        if self.default.as_deref() == Some(name.as_str()) {
+            self.default = None;
+        }
+        Ok(match (entry.llm, entry.spec) {
+            (Some(llm), _) => llm,
+            (None, Some(spec)) => spec.build(),
+            (None, None) => unreachable!("entries without a spec keep their engine"),
+        })
+    }
+
+    // Drop a loaded model, releasing its memory; it is loaded again on next use.
+    // Returns false for unknown, unloaded or ready-made models.
+    pub fn unload(&mut self, name: &str) -> bool {
+        let Ok(name) = self.resolve(Some(name)) else {
+            return false;
+        };
+        match self.entries.get_mut(&name) {
+            Some(entry) if entry.spec.is_some() && entry.is_loaded() => entry.llm.take().is_some(),
+            _ => false,
+        }
+    }
+
+    // File bytes of all loaded models, the most they can hold in RAM
+    pub fn loaded_bytes(&self) -> u64 {
+        self.entries.values().filter(|e| e.is_loaded()).map(Entry::memory_bytes).sum()
+    }
+
+    pub fn memory_budget(&self) -> Option<u64> {
+        self.memory_budget
+    }
+
+    pub fn status(&self) -> Vec<ModelStatus> {
+        self.order
+            .iter()
+            .map(|name| {
+                let entry = &self.entries[name];
+                let llm = entry.llm.as_ref().filter(|llm| llm.is_loaded());
+                ModelStatus {
+                    name: name.clone(),
+                    path: entry.path().to_string(),
+                    loaded: llm.is_some(),
+                    backend: llm.and_then(|llm| llm.backend_name()).map(str::to_string),
+                    memory: llm.and_then(|llm| llm.memory_usage()),
+                    prompt_cache: llm.and_then(|llm| llm.prompt_cache_stats()),
+                    lora: llm.map(|llm| llm.lora_adapters()).unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    // Generate with the model the request names, or the default one
+    pub fn generate_response_stream(
+        &mut self,
+        request: &InferenceRequest,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<InferenceResponse> {
+        self.get(request.model.as_deref())?.generate_response_stream(request, on_token)
+    }
+
+    pub fn generate_response(&mut self, request: &InferenceRequest) -> Result<InferenceResponse> {
+        self.generate_response_stream(request, &mut |_| {})
+    }
+
+    // Embed with the model the request names, e.g. a dedicated embedding model
+    pub fn embed(&mut self, request: &EmbeddingRequest) -> Result<EmbeddingResponse> {
+        self.get(request.model.as_deref())?.embed(request)
+    }
+}
+
+// Config values spelled the way the CLI and environment spell them
+fn from_str_opt<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => value.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::testing::tiny_llama;
+    use crate::{ReplayBackend, ReplayFixture};
+
+    fn replay(response: &str) -> SuperTinyWasmLLM {
+        let fixture = ReplayFixture { default_response: Some(response.to_string()), ..Default::default() };
+        SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(ReplayBackend::new(fixture).unwrap()))
+    }
+
+    #[test]
+    fn test_routes_by_name_alias_and_default() {
+        let mut registry = ModelRegistry::single("small", replay("from small"));
+        registry.insert("large", replay("from large"));
+        registry.alias("answer", "large").unwrap();
+
+        let request = |model: Option<&str>| InferenceRequest {
+            prompt: "hi".to_string(),
+            model: model.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(registry.generate_response(&request(None)).unwrap().response, "from small");
+        assert_eq!(registry.generate_response(&request(Some("answer"))).unwrap().response, "from large");
+
+        let error = registry.generate_response(&request(Some("huge"))).unwrap_err();
+        let error = Error::classify(&error, ErrorKind::Internal);
+        assert_eq!(error.kind(), ErrorKind::ModelNotFound);
+        assert_eq!(error.details()["available"], serde_json::json!(["small", "large"]));
+        assert!(registry.alias("small", "large").is_err());
+
+        // Inserting under an alias's name replaces the alias
+        registry.insert("answer", replay("from answer"));
+        assert_eq!(registry.generate_response(&request(Some("answer"))).unwrap().response, "from answer");
+        assert_eq!(registry.names(), ["small", "large", "answer"]);
+    }
+
+    #[test]
+    fn test_memory_budget_unloads_least_recently_used() {
+        let dir = std::env::temp_dir();
+        let paths: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| dir.join(format!("tinyedge-registry-{}-{}.gguf", name, std::process::id())))
+            .collect();
+        for path in &paths {
+            std::fs::write(path, tiny_llama(false)).unwrap();
+        }
+        let size = std::fs::metadata(&paths[0]).unwrap().len();
+
+        let config = RegistryConfig::from_json(&format!(
+            r#"{{"models": [{{"name": "a", "path": {:?}, "backend": "demo", "context_length": 32}},
+                            {{"name": "b", "path": {:?}, "backend": "demo", "load_mode": "read"}},
+                            {{"name": "c", "path": {:?}, "backend": "demo"}}],
+                "default": "b"}}"#,
+            paths[0].to_string_lossy(),
+            paths[1].to_string_lossy(),
+            paths[2].to_string_lossy()
+        ))
+        .unwrap();
+        // Room for two of the three models
+        let mut registry = ModelRegistry::from_config(config).unwrap().with_memory_budget(size * 2 + size / 2);
+
+        assert_eq!(registry.get(Some("a")).unwrap().context_length(), Some(32));
+        registry.get(None).unwrap();
+        registry.get(Some("a")).unwrap();
+        // Loading c evicts b, the least recently used
+        registry.get(Some("c")).unwrap();
+
+        let mut tight = ModelRegistry::new().with_memory_budget(size / 2);
+        tight.add(ModelSpec::new("big", &paths[0].to_string_lossy())).unwrap();
+        let error = Error::classify(&tight.get(None).err().unwrap(), ErrorKind::Internal);
+        assert_eq!(error.kind(), ErrorKind::ModelLoadFailed);
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let loaded: Vec<bool> = registry.status().iter().map(|s| s.loaded).collect();
+        assert_eq!(loaded, vec![true, false, true]);
+        assert_eq!(registry.loaded_bytes(), size * 2);
+        assert!(registry.unload("a") && !registry.unload("a"));
+        assert!(RegistryConfig::from_json(r#"{"models": [{"name": "x", "path": "", "backend": "gpu"}]}"#).is_err());
+    }
+}
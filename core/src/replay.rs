@@ -0,0 +1,334 @@
+use crate::backend::{hashed_embeddings, BackendOutput, InferenceBackend, ModelFile};
+use crate::embedding::Pooling;
+use crate::lora::{LoraAdapter, LoraInfo};
+use crate::prompt_cache::PromptCacheStats;
+use crate::{FinishReason, InferenceRequest};
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const REPLAY_FIXTURE_ENV_VAR: &str = "SUPERTINYWASMLLM_REPLAY_FIXTURE";
+pub const RECORD_FIXTURE_ENV_VAR: &str = "SUPERTINYWASMLLM_RECORD_FIXTURE";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptMatcher {
+    Exact(String),
+    Contains(String),
+    Regex(String),
+}
+
+// One prompt matcher -> completion rule. With several `responses` they are
+// served in order on successive hits and the last one repeats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayRule {
+    #[serde(rename = "match")]
+    pub matcher: PromptMatcher,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub responses: Vec<String>,
+    // Makes the backend fail instead of answering, to exercise error paths
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayFixture {
+    pub rules: Vec<ReplayRule>,
+    // Completion for prompts no rule matches; without it such prompts are an error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_response: Option<String>,
+}
+
+impl ReplayFixture {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| anyhow!("Invalid replay fixture: {}", e))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read replay fixture: {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("In replay fixture {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write replay fixture: {}", path.display()))
+    }
+}
+
+enum CompiledMatcher {
+    Exact(String),
+    Contains(String),
+    Regex(Regex),
+}
+
+impl CompiledMatcher {
+    fn matches(&self, prompt: &str) -> bool {
+        match self {
+            CompiledMatcher::Exact(s) => prompt == s,
+            CompiledMatcher::Contains(s) => prompt.contains(s.as_str()),
+            CompiledMatcher::Regex(re) => re.is_match(prompt),
+        }
+    }
+}
+
+// Answers prompts from a fixture of canned completions; rules are tried in order
+pub struct ReplayBackend {
+    rules: Vec<(CompiledMatcher, ReplayRule)>,
+    default_response: Option<String>,
+    hits: Mutex<Vec<usize>>,
+}
+
+impl ReplayBackend {
+    pub fn new(fixture: ReplayFixture) -> Result<Self> {
+        let mut rules = Vec::with_capacity(fixture.rules.len());
+        for (i, rule) in fixture.rules.into_iter().enumerate() {
+            if rule.response.is_none() && rule.responses.is_empty() && rule.error.is_none() {
+                return Err(anyhow!("Replay rule {} has no response, responses or error", i));
+            }
+            let compiled = match &rule.matcher {
+                PromptMatcher::Exact(s) => CompiledMatcher::Exact(s.clone()),
+                PromptMatcher::Contains(s) => CompiledMatcher::Contains(s.clone()),
+                PromptMatcher::Regex(pattern) => CompiledMatcher::Regex(
+                    Regex::new(pattern).map_err(|e| anyhow!("Replay rule {} has an invalid regex: {}", i, e))?,
+                ),
+            };
+            rules.push((compiled, rule));
+        }
+
+        Ok(Self {
+            hits: Mutex::new(vec![0; rules.len()]),
+            rules,
+            default_response: fixture.default_response,
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(ReplayFixture::load(path)?)
+    }
+}
+
+impl InferenceBackend for ReplayBackend {
+    fn name(&self) -> &str {
+        "replay"
+    }
+
+    // Recorded text is returned as it is, whatever the sampling options
+    fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput> {
+        let index = self.rules.iter().position(|(matcher, _)| matcher.matches(&request.prompt));
+
+        let text = match index {
+            Some(i) => {
+                let rule = &self.rules[i].1;
+                if let Some(error) = &rule.error {
+                    return Err(anyhow!("{}", error));
+                }
+                let mut hits = self.hits.lock().unwrap_or_else(|e| e.into_inner());
+                let hit = hits[i];
+                hits[i] += 1;
+                match rule.responses.len() {
+                    0 => rule.response.clone().unwrap_or_default(),
+                    n => rule.responses[hit.min(n - 1)].clone(),
+                }
+            }
+            None => self
+                .default_response
+                .clone()
+                .ok_or_else(|| anyhow!("No replay rule matches prompt: {}", preview(&request.prompt)))?,
+        };
+
+        Ok(BackendOutput {
+            text,
+            tokens_generated: None,
+            cached_prompt_tokens: 0,
+            finish_reason: FinishReason::Stop,
+            prompt_eval: None,
+            logprobs: None,
+        })
+    }
+
+    // Fixtures hold completions only; hashed vectors keep embedding callers deterministic
+    fn embed(&self, texts: &[String], _pooling: Pooling) -> Result<Vec<Vec<f32>>> {
+        Ok(hashed_embeddings(texts))
+    }
+}
+
+// Wraps a real backend and appends every prompt/completion pair to a fixture file
+pub struct RecordingBackend {
+    inner: Box<dyn InferenceBackend>,
+    path: PathBuf,
+    fixture: Mutex<ReplayFixture>,
+}
+
+impl RecordingBackend {
+    // Existing rules in `path` are kept and new recordings appended after them
+    pub fn new(inner: Box<dyn InferenceBackend>, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let fixture = if path.exists() {
+            ReplayFixture::load(&path)?
+        } else {
+            ReplayFixture::default()
+        };
+
+        Ok(Self {
+            inner,
+            path,
+            fixture: Mutex::new(fixture),
+        })
+    }
+}
+
+impl InferenceBackend for RecordingBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn load(&mut self, model: &ModelFile) -> Result<()> {
+        self.inner.load(model)
+    }
+
+    fn generate(&self, request: &InferenceRequest) -> Result<BackendOutput> {
+        self.generate_stream(request, &mut |_| {})
+    }
+
+    fn generate_stream(&self, request: &InferenceRequest, on_token: &mut dyn FnMut(&str)) -> Result<BackendOutput> {
+        let output = self.inner.generate_stream(request, on_token)?;
+
+        let mut fixture = self.fixture.lock().unwrap_or_else(|e| e.into_inner());
+        fixture.rules.push(ReplayRule {
+            matcher: PromptMatcher::Exact(request.prompt.clone()),
+            response: Some(output.text.clone()),
+            responses: Vec::new(),
+            error: None,
+        });
+        // Written after every call so a crash mid-session keeps what was recorded
+        fixture.save(&self.path)?;
+
+        Ok(output)
+    }
+
+    fn embed(&self, texts: &[String], pooling: Pooling) -> Result<Vec<Vec<f32>>> {
+        self.inner.embed(texts, pooling)
+    }
+
+    fn check_request(&self, request: &InferenceRequest) -> Result<()> {
+        self.inner.check_request(request)
+    }
+
+    fn prompt_cache_stats(&self) -> Option<PromptCacheStats> {
+        self.inner.prompt_cache_stats()
+    }
+
+    fn prepare_batch(&self, prompts: &[&str]) {
+        self.inner.prepare_batch(prompts)
+    }
+
+    fn load_lora(&mut self, adapters: Vec<LoraAdapter>) -> Result<()> {
+        self.inner.load_lora(adapters)
+    }
+
+    fn lora_adapters(&self) -> Vec<LoraInfo> {
+        self.inner.lora_adapters()
+    }
+}
+
+fn preview(prompt: &str) -> String {
+    let mut chars = prompt.chars();
+    let head: String = chars.by_ref().take(80).collect();
+    if chars.next().is_some() {
+        format!("{}...", head)
+    } else {
+        head
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::DemoBackend;
+
+    fn request(prompt: &str) -> InferenceRequest {
+        InferenceRequest {
+            prompt: prompt.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_matchers_in_rule_order() {
+        let fixture = ReplayFixture::from_json(
+            r#"{
+                "rules": [
+                    {"match": {"exact": "ping"}, "response": "pong"},
+                    {"match": {"regex": "(?i)^list\\s+files"}, "response": "ls"},
+                    {"match": {"contains": "2+2"}, "response": "4"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let backend = ReplayBackend::new(fixture).unwrap();
+
+        assert_eq!(backend.generate(&request("ping")).unwrap().text, "pong");
+        assert_eq!(backend.generate(&request("LIST  files now")).unwrap().text, "ls");
+        assert_eq!(backend.generate(&request("what is 2+2?")).unwrap().text, "4");
+        assert!(backend.generate(&request("ping!")).is_err());
+    }
+
+    #[test]
+    fn test_response_sequences_and_errors() {
+        let fixture = ReplayFixture::from_json(
+            r#"{
+                "rules": [
+                    {"match": {"contains": "step"}, "responses": ["one", "two"]},
+                    {"match": {"contains": "boom"}, "error": "backend exploded"}
+                ],
+                "default_response": "fallback"
+            }"#,
+        )
+        .unwrap();
+        let backend = ReplayBackend::new(fixture).unwrap();
+
+        let texts: Vec<String> = (0..3).map(|_| backend.generate(&request("step")).unwrap().text).collect();
+        assert_eq!(texts, vec!["one", "two", "two"]);
+        assert_eq!(backend.generate(&request("boom")).unwrap_err().to_string(), "backend exploded");
+        assert_eq!(backend.generate(&request("other")).unwrap().text, "fallback");
+    }
+
+    #[test]
+    fn test_invalid_rules_rejected() {
+        let bad_regex = ReplayFixture::from_json(r#"{"rules": [{"match": {"regex": "("}, "response": "x"}]}"#).unwrap();
+        assert!(ReplayBackend::new(bad_regex).is_err());
+
+        let empty = ReplayFixture::from_json(r#"{"rules": [{"match": {"exact": "x"}}]}"#).unwrap();
+        assert!(ReplayBackend::new(empty).is_err());
+    }
+
+    #[test]
+    fn test_record_then_replay() {
+        let path = std::env::temp_dir().join(format!("replay-record-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let recorder = RecordingBackend::new(Box::new(DemoBackend), &path).unwrap();
+        let recorded = recorder.generate(&request("hello")).unwrap();
+
+        let replay = ReplayBackend::from_file(&path).unwrap();
+        assert_eq!(replay.generate(&request("hello")).unwrap().text, recorded.text);
+        // Requests recorded with sampling options replay with them
+        let shaped = InferenceRequest { top_p: Some(0.9), repeat_penalty: Some(1.1), ..request("hello") };
+        assert!(replay.check_request(&shaped).is_ok() && recorder.check_request(&shaped).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_example_fixture_loads() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/replay_fixture.json");
+        let backend = ReplayBackend::from_file(path).unwrap();
+        assert!(backend.generate(&request("User task: What is 2+2?")).unwrap().text.contains("\"args\": [\"2+2\"]"));
+    }
+}
@@ -0,0 +1,359 @@
+use crate::InferenceRequest;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// Sampling defaults, matching llama.cpp's
+pub const DEFAULT_TEMPERATURE: f32 = 0.7;
+pub const DEFAULT_TOP_K: usize = 40;
+pub const DEFAULT_TOP_P: f32 = 0.95;
+pub const DEFAULT_MIN_P: f32 = 0.05;
+pub const DEFAULT_REPEAT_PENALTY: f32 = 1.0;
+
+// How many recent tokens the repetition/frequency/presence penalties look at
+pub const PENALTY_LAST_N: usize = 64;
+
+// Fully resolved sampling settings for one generation; echoed back in the response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    pub temperature: f32,
+    // 0 disables the top-k cut-off
+    pub top_k: usize,
+    pub top_p: f32,
+    pub min_p: f32,
+    pub repeat_penalty: f32,
+    pub frequency_penalty: f32,
+    pub presence_penalty: f32,
+    pub seed: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            temperature: DEFAULT_TEMPERATURE,
+            top_k: DEFAULT_TOP_K,
+            top_p: DEFAULT_TOP_P,
+            min_p: DEFAULT_MIN_P,
+            repeat_penalty: DEFAULT_REPEAT_PENALTY,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            seed: 0,
+            stop: Vec::new(),
+        }
+    }
+}
+
+impl SamplingParams {
+    // Request settings over the defaults; without an explicit seed one is drawn from the clock
+    pub fn from_request(request: &InferenceRequest) -> Self {
+        let defaults = Self::default();
+        Self {
+            temperature: request.temperature.unwrap_or(defaults.temperature),
+            top_k: request.top_k.unwrap_or(defaults.top_k),
+            top_p: request.top_p.unwrap_or(defaults.top_p),
+            min_p: request.min_p.unwrap_or(defaults.min_p),
+            repeat_penalty: request.repeat_penalty.unwrap_or(defaults.repeat_penalty),
+            frequency_penalty: request.frequency_penalty.unwrap_or(defaults.frequency_penalty),
+            presence_penalty: request.presence_penalty.unwrap_or(defaults.presence_penalty),
+            seed: request.seed.unwrap_or_else(seed_from_time),
+            stop: request.stop.clone(),
+        }
+    }
+
+    pub fn is_greedy(&self) -> bool {
+        self.temperature <= 0.0
+    }
+}
+
+pub(crate) fn seed_from_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0x2545_f491_4f6c_dd1d)
+}
+
+// xorshift64* generator; good enough for sampling and needs no dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Picks the next token from logits: penalties, temperature, then top-k, top-p and min-p
+pub struct Sampler {
+    params: SamplingParams,
+    rng: Rng,
+}
+
+impl Sampler {
+    pub fn new(params: &SamplingParams) -> Self {
+        Self {
+            rng: Rng(params.seed.max(1)),
+            params: params.clone(),
+        }
+    }
+
+    // `history` holds the tokens seen so far, prompt included, for the penalties
+    pub fn sample(&mut self, logits: &mut [f32], history: &[u32]) -> u32 {
+        self.apply_penalties(logits, history);
+        if self.params.is_greedy() {
+            return argmax(logits);
+        }
+
+        let mut candidates: Vec<(u32, f32)> = logits
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (i as u32, l / self.params.temperature))
+            .collect();
+        candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        if self.params.top_k > 0 {
+            candidates.truncate(self.params.top_k);
+        }
+
+        let max = candidates[0].1;
+        let mut total = 0.0;
+        for c in candidates.iter_mut() {
+            c.1 = (c.1 - max).exp();
+            total += c.1;
+        }
+
+        // Nucleus cut-off
+        let mut cumulative = 0.0;
+        let mut keep = candidates.len();
+        for (i, c) in candidates.iter().enumerate() {
+            cumulative += c.1 / total;
+            if cumulative >= self.params.top_p {
+                keep = i + 1;
+                break;
+            }
+        }
+        candidates.truncate(keep);
+
+        // Drop tokens far less likely than the best one; the best has weight 1 here
+        if self.params.min_p > 0.0 {
+            let floor = self.params.min_p;
+            let kept = candidates.iter().take_while(|c| c.1 >= floor).count();
+            candidates.truncate(kept.max(1));
+        }
+
+        let total: f32 = candidates.iter().map(|c| c.1).sum();
+        let mut r = self.rng.next_f32() * total;
+        for c in &candidates {
+            r -= c.1;
+            if r <= 0.0 {
+                return c.0;
+            }
+        }
+        candidates[candidates.len() - 1].0
+    }
+
+    fn apply_penalties(&self, logits: &mut [f32], history: &[u32]) {
+        let p = &self.params;
+        if p.repeat_penalty == 1.0 && p.frequency_penalty == 0.0 && p.presence_penalty == 0.0 {
+            return;
+        }
+
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for &token in &history[history.len().saturating_sub(PENALTY_LAST_N)..] {
+            *counts.entry(token).or_default() += 1;
+        }
+        for (token, count) in counts {
+            let Some(logit) = logits.get_mut(token as usize) else { continue };
+            if *logit > 0.0 {
+                *logit /= p.repeat_penalty;
+            } else {
+                *logit *= p.repeat_penalty;
+            }
+            *logit -= count as f32 * p.frequency_penalty + p.presence_penalty;
+        }
+    }
+}
+
+fn argmax(logits: &[f32]) -> u32 {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(Ordering::Equal))
+        .map(|(i, _)| i as u32)
+        .unwrap_or(0)
+}
+
+// A generated token with its log probability and those of the likeliest tokens at its
+// position, under the model's distribution before penalties, temperature and cut-offs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f32,
+}
+
+pub fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_total = logits.iter().map(|l| (l - max).exp()).sum::<f32>().ln() + max;
+    logits.iter().map(|l| l - log_total).collect()
+}
+
+// The `n` likeliest token ids and their log probabilities, most likely first
+pub fn top_logprobs(logprobs: &[f32], n: usize) -> Vec<(u32, f32)> {
+    let mut top: Vec<(u32, f32)> = logprobs.iter().enumerate().map(|(i, &l)| (i as u32, l)).collect();
+    if n < top.len() {
+        top.select_nth_unstable_by(n, |a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        top.truncate(n);
+    }
+    top.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    top
+}
+
+// Byte offset of the earliest stop sequence in `text`
+pub fn find_stop(text: &str, stop: &[String]) -> Option<usize> {
+    stop.iter().filter(|s| !s.is_empty()).filter_map(|s| text.find(s.as_str())).min()
+}
+
+// Length of the longest tail of `text` that could still grow into a stop sequence
+pub(crate) fn partial_stop_len(text: &str, stop: &[String]) -> usize {
+    stop.iter()
+        .filter_map(|s| {
+            (1..s.len())
+                .rev()
+                .filter(|&n| n <= text.len() && text.is_char_boundary(text.len() - n))
+                .find(|&n| s.as_bytes().starts_with(&text.as_bytes()[text.len() - n..]))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// Passes streamed text through, holding back anything that may be the start of a
+// stop sequence and suppressing everything from a completed stop sequence on
+pub(crate) struct StopFilter<'a> {
+    stop: &'a [String],
+    text: String,
+    emitted: usize,
+    stopped: bool,
+}
+
+impl<'a> StopFilter<'a> {
+    pub fn new(stop: &'a [String]) -> Self {
+        Self {
+            stop,
+            text: String::new(),
+            emitted: 0,
+            stopped: false,
+        }
+    }
+
+    pub fn push(&mut self, piece: &str, on_token: &mut dyn FnMut(&str)) {
+        if self.stopped {
+            return;
+        }
+        self.text.push_str(piece);
+
+        let end = match find_stop(&self.text, self.stop) {
+            Some(at) => {
+                self.stopped = true;
+                at
+            }
+            None => self.text.len() - partial_stop_len(&self.text, self.stop),
+        };
+        if end > self.emitted {
+            on_token(&self.text[self.emitted..end]);
+            self.emitted = end;
+        }
+    }
+
+    // Release held-back text once generation ended without hitting a stop sequence
+    pub fn finish(&mut self, on_token: &mut dyn FnMut(&str)) {
+        if !self.stopped && self.text.len() > self.emitted {
+            on_token(&self.text[self.emitted..]);
+            self.emitted = self.text.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(temperature: f32) -> SamplingParams {
+        SamplingParams {
+            temperature,
+            seed: 42,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sampler_respects_temperature() {
+        let mut logits = vec![0.0, 5.0, 1.0];
+        assert_eq!(Sampler::new(&params(0.0)).sample(&mut logits, &[]), 1);
+
+        // Low temperature concentrates almost all mass on the best token
+        let mut sampler = Sampler::new(&params(0.1));
+        let hits = (0..100).filter(|_| sampler.sample(&mut logits.clone(), &[]) == 1).count();
+        assert!(hits > 95);
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let logits = vec![1.0, 1.1, 0.9, 1.05];
+        let draw = |seed| {
+            let mut sampler = Sampler::new(&SamplingParams { temperature: 1.0, top_p: 1.0, min_p: 0.0, seed, ..Default::default() });
+            (0..20).map(|_| sampler.sample(&mut logits.clone(), &[])).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+    }
+
+    #[test]
+    fn test_penalties_and_cutoffs() {
+        // A strong repetition penalty moves greedy decoding off the repeated token
+        let mut penalized = Sampler::new(&SamplingParams { repeat_penalty: 10.0, ..params(0.0) });
+        assert_eq!(penalized.sample(&mut [2.0, 1.0], &[0, 0]), 1);
+
+        let mut presence = Sampler::new(&SamplingParams { presence_penalty: 1.5, ..params(0.0) });
+        assert_eq!(presence.sample(&mut [2.0, 1.0], &[0]), 1);
+
+        // top_k = 1 makes sampling greedy whatever the temperature
+        let mut top_k = Sampler::new(&SamplingParams { top_k: 1, ..params(5.0) });
+        assert!((0..20).all(|_| top_k.sample(&mut [0.0, 0.5, 0.2], &[]) == 1));
+
+        // min_p drops tokens below 60% of the best token's probability
+        let mut min_p = Sampler::new(&SamplingParams { min_p: 0.6, top_p: 1.0, ..params(1.0) });
+        assert!((0..20).all(|_| min_p.sample(&mut [0.0, 3.0, 0.0], &[]) == 1));
+    }
+
+    #[test]
+    fn test_stop_filter_holds_back_partial_matches() {
+        let stop = vec!["```".to_string(), "\nUser:".to_string()];
+        assert_eq!(find_stop("abc```def", &stop), Some(3));
+        assert_eq!(partial_stop_len("abc\nUs", &stop), 3);
+
+        let mut out = Vec::new();
+        let mut filter = StopFilter::new(&stop);
+        for piece in ["{\"a\": 1}", "`", "`", "` trailing"] {
+            filter.push(piece, &mut |s| out.push(s.to_string()));
+        }
+        filter.finish(&mut |s| out.push(s.to_string()));
+        assert_eq!(out.concat(), "{\"a\": 1}");
+
+        let mut out = String::new();
+        let mut filter = StopFilter::new(&stop);
+        filter.push("a\nU", &mut |s| out.push_str(s));
+        filter.push("nknown", &mut |s| out.push_str(s));
+        filter.finish(&mut |s| out.push_str(s));
+        assert_eq!(out, "a\nUnknown");
+    }
+}
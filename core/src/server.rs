@@ -0,0 +1,230 @@
+use crate::{BatchRequest, EmbeddingRequest, Error, ErrorKind, ErrorResponse, InferenceRequest, StreamEvent, SuperTinyWasmLLM};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlCommand {
+    // Re-read the model file, or load `model` instead
+    Reload,
+    // Stop after answering; requests already read have been answered
+    Shutdown,
+}
+
+// Control line for the persistent server, e.g. {"cmd": "reload", "model": "other.gguf"}
+#[derive(Debug, Deserialize)]
+pub struct ControlRequest {
+    pub cmd: ControlCommand,
+    pub id: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ControlResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub cmd: ControlCommand,
+    pub status: String,
+}
+
+// Serve requests from `input`, one JSON object per line, until EOF or a shutdown
+// command. Every line gets exactly one reply on `output` (or token events and a
+// done event when streaming), carrying the request id if there was one.
+pub fn serve(llm: &mut SuperTinyWasmLLM, input: impl BufRead, output: &mut impl Write) -> Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if handle_line(llm, &line, output)? == ControlFlow::Shutdown {
+            break;
+        }
+    }
+    log::info!("Server stopped");
+    Ok(())
+}
+
+#[derive(PartialEq)]
+enum ControlFlow {
+    Continue,
+    Shutdown,
+}
+
+fn handle_line(llm: &mut SuperTinyWasmLLM, line: &str, output: &mut impl Write) -> Result<ControlFlow> {
+    let value: serde_json::Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => {
+            write_error(output, None, Error::new(ErrorKind::InvalidRequest, format!("Failed to parse JSON: {}", e)))?;
+            return Ok(ControlFlow::Continue);
+        }
+    };
+    // Ids are reported even when the rest of the line is unusable
+    let id = value.get("id").and_then(|id| id.as_str()).map(str::to_string);
+
+    if value.get("cmd").is_some() {
+        let control: ControlRequest = match serde_json::from_value(value) {
+            Ok(control) => control,
+            Err(e) => {
+                write_error(output, id, Error::new(ErrorKind::InvalidCommand, format!("Invalid command: {}", e)))?;
+                return Ok(ControlFlow::Continue);
+            }
+        };
+        return run_command(llm, control, output);
+    }
+
+    // Lines with texts to embed instead of a prompt ask for vectors
+    if value.get("input").is_some() {
+        let result = serde_json::from_value::<EmbeddingRequest>(value)
+            .map_err(|e| Error::new(ErrorKind::InvalidRequest, format!("Failed to parse JSON: {}", e)).into())
+            .and_then(|request| llm.embed(&request));
+        match result {
+            Ok(response) => write_line(output, &response)?,
+            Err(e) => write_error(output, id, Error::classify(&e.context("Embedding failed"), ErrorKind::InferenceFailed))?,
+        }
+        return Ok(ControlFlow::Continue);
+    }
+
+    if BatchRequest::is_batch(&value) {
+        match BatchRequest::from_value(value) {
+            Ok(batch) => write_line(output, &llm.generate_batch(&batch))?,
+            Err(error) => write_error(output, id, error)?,
+        }
+        return Ok(ControlFlow::Continue);
+    }
+
+    let request: InferenceRequest = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(e) => {
+            write_error(output, id, Error::new(ErrorKind::InvalidRequest, format!("Failed to parse JSON: {}", e)))?;
+            return Ok(ControlFlow::Continue);
+        }
+    };
+
+    let result = if request.stream {
+        let mut index = 0;
+        let mut write_result = Ok(());
+        let result = llm.generate_response_stream(&request, &mut |text| {
+            let event = StreamEvent::Token { id: request.id.clone(), index, text: text.to_string() };
+            index += 1;
+            if write_result.is_ok() {
+                write_result = write_line(output, &event);
+            }
+        });
+        write_result?;
+        result.map(|response| write_line(output, &StreamEvent::Done(Box::new(response))))
+    } else {
+        llm.generate_response(&request).map(|response| write_line(output, &response))
+    };
+    match result {
+        Ok(written) => written?,
+        Err(e) => write_error(output, request.id, Error::classify(&e.context("Inference failed"), ErrorKind::InferenceFailed))?,
+    }
+    Ok(ControlFlow::Continue)
+}
+
+fn run_command(llm: &mut SuperTinyWasmLLM, control: ControlRequest, output: &mut impl Write) -> Result<ControlFlow> {
+    let flow = match control.cmd {
+        ControlCommand::Reload => {
+            if let Err(e) = llm.reload_model(control.model.as_deref()) {
+                write_error(output, control.id, Error::classify(&e.context("Reload failed"), ErrorKind::ModelLoadFailed))?;
+                return Ok(ControlFlow::Continue);
+            }
+            ControlFlow::Continue
+        }
+        ControlCommand::Shutdown => ControlFlow::Shutdown,
+    };
+    let response = ControlResponse {
+        id: control.id,
+        cmd: control.cmd,
+        status: "ok".to_string(),
+    };
+    write_line(output, &response)?;
+    Ok(flow)
+}
+
+fn write_error(output: &mut impl Write, id: Option<String>, error: Error) -> Result<()> {
+    write_line(output, &ErrorResponse { id, error })
+}
+
+// One JSON document per line, flushed so the client sees it immediately
+pub fn write_line(output: &mut impl Write, value: &impl Serialize) -> Result<()> {
+    serde_json::to_writer(&mut *output, value)?;
+    writeln!(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackendKind, ReplayBackend, ReplayFixture};
+
+    fn run(llm: &mut SuperTinyWasmLLM, input: &str) -> Vec<serde_json::Value> {
+        let mut output = Vec::new();
+        serve(llm, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_serves_requests_until_shutdown() {
+        let fixture = ReplayFixture::from_json(r#"{"rules": [{"match": {"exact": "ping"}, "response": "pong"}]}"#).unwrap();
+        let mut llm = SuperTinyWasmLLM::new(String::new()).with_backend(Box::new(ReplayBackend::new(fixture).unwrap()));
+        llm.load_model().unwrap();
+
+        let lines = run(
+            &mut llm,
+            "{\"id\": \"a\", \"prompt\": \"ping\"}\n\
+             \n\
+             not json\n\
+             {\"id\": \"b\", \"prompt\": \"ping\", \"stream\": true}\n\
+             {\"id\": \"c\", \"prompt\": 5}\n\
+             {\"cmd\": \"reload\", \"id\": \"r\"}\n\
+             {\"id\": \"e\", \"input\": [\"ping\", \"pong\"]}\n\
+             [{\"prompt\": \"ping\"}, {\"prompt\": \"pong\", \"max_tokens\": 4}]\n\
+             {\"cmd\": \"shutdown\"}\n\
+             {\"id\": \"never\", \"prompt\": \"ping\"}\n",
+        );
+
+        assert_eq!(lines.len(), 9);
+        assert_eq!((lines[0]["id"].as_str(), lines[0]["response"].as_str()), (Some("a"), Some("pong")));
+        assert_eq!((&lines[1]["code"], lines[1]["kind"].as_str()), (&serde_json::json!(3), Some("invalid_request")));
+        assert_eq!((lines[2]["event"].as_str(), lines[2]["id"].as_str()), (Some("token"), Some("b")));
+        assert_eq!((lines[3]["event"].as_str(), lines[3]["id"].as_str()), (Some("done"), Some("b")));
+        assert_eq!((lines[4]["id"].as_str(), lines[4]["kind"].as_str()), (Some("c"), Some("invalid_request")));
+        assert_eq!((lines[5]["id"].as_str(), lines[5]["status"].as_str()), (Some("r"), Some("ok")));
+        assert_eq!((lines[6]["id"].as_str(), lines[6]["embeddings"].as_array().map(Vec::len)), (Some("e"), Some(2)));
+        // Each batch item answers on its own: "pong" has no replay rule and falls back to demo
+        let results = lines[7]["results"].as_array().unwrap();
+        assert_eq!((results[0]["response"].as_str(), results[1]["finish_reason"].as_str()), (Some("pong"), Some("error")));
+        assert_eq!(lines[8]["cmd"], "shutdown");
+    }
+
+    #[test]
+    fn test_reload_swaps_model_and_keeps_it_on_failure() {
+        let path = std::env::temp_dir().join(format!("tinyedge-server-{}.gguf", std::process::id()));
+        std::fs::write(&path, crate::native::testing::tiny_llama(false)).unwrap();
+        let mut llm = SuperTinyWasmLLM::new(String::new()).with_backend_kind(BackendKind::Demo);
+        llm.load_model().unwrap();
+        assert!(llm.tokenizer().is_none());
+
+        let lines = run(
+            &mut llm,
+            &format!(
+                "{{\"cmd\": \"reload\", \"model\": {:?}}}\n{{\"cmd\": \"reload\", \"id\": \"x\", \"model\": \"/nonexistent.gguf\"}}\n",
+                path.to_string_lossy()
+            ),
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines[0]["status"], "ok");
+        assert_eq!((lines[1]["id"].as_str(), lines[1]["kind"].as_str()), (Some("x"), Some("model_not_found")));
+        assert_eq!(lines[1]["details"]["path"], "/nonexistent.gguf");
+        assert!(llm.tokenizer().is_some());
+        assert_eq!(llm.context_length(), Some(64));
+    }
+}
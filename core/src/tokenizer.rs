@@ -0,0 +1,534 @@
+use crate::gguf::{GgufFile, GgufValue};
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Values of `tokenizer.ggml.token_type`
+const TOKEN_TYPE_NORMAL: i64 = 1;
+const TOKEN_TYPE_CONTROL: i64 = 3;
+const TOKEN_TYPE_USER_DEFINED: i64 = 4;
+
+const SPM_SPACE: char = '\u{2581}';
+
+// GPT-2 pre-tokenizer, minus the `\s+(?!\S)` lookahead which is handled in code
+const GPT2_PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenizerKind {
+    // SentencePiece with score-ordered merges (`tokenizer.ggml.model = "llama"`)
+    SentencePiece,
+    // Byte-level BPE with ranked merges (`tokenizer.ggml.model = "gpt2"`)
+    Bpe,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SpecialTokens {
+    pub bos: Option<u32>,
+    pub eos: Option<u32>,
+    pub unk: Option<u32>,
+    pub pad: Option<u32>,
+}
+
+// Tokenizer built from the `tokenizer.ggml.*` metadata of a GGUF model
+pub struct Tokenizer {
+    kind: TokenizerKind,
+    tokens: Vec<String>,
+    scores: Vec<f32>,
+    token_types: Vec<i64>,
+    ids: HashMap<String, u32>,
+    merge_ranks: HashMap<(String, String), usize>,
+    byte_tokens: [Option<u32>; 256],
+    special: SpecialTokens,
+    // Control and user-defined tokens recognised verbatim in input text, longest first
+    special_pieces: Vec<(String, u32)>,
+    add_bos: bool,
+    add_eos: bool,
+    add_space_prefix: bool,
+    pre_tokenizer: Option<Regex>,
+    byte_decoder: HashMap<char, u8>,
+    byte_encoder: [char; 256],
+}
+
+#[derive(PartialEq)]
+struct Bigram {
+    score: f32,
+    left: usize,
+    right: usize,
+    len: usize,
+}
+
+impl Eq for Bigram {}
+
+impl Ord for Bigram {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Highest score first; on ties the leftmost pair wins
+        self.score
+            .partial_cmp(&other.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.left.cmp(&self.left))
+    }
+}
+
+impl PartialOrd for Bigram {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Tokenizer {
+    pub fn from_gguf(gguf: &GgufFile) -> Result<Self> {
+        let kind = match gguf.get_str("tokenizer.ggml.model") {
+            Some("llama") | None => TokenizerKind::SentencePiece,
+            Some("gpt2") => TokenizerKind::Bpe,
+            Some(other) => return Err(anyhow!("Tokenizer model '{}' is not supported", other)),
+        };
+
+        let tokens: Vec<String> = gguf
+            .get("tokenizer.ggml.tokens")
+            .and_then(GgufValue::as_array)
+            .ok_or_else(|| anyhow!("Model has no tokenizer.ggml.tokens vocabulary"))?
+            .iter()
+            .map(|t| t.as_str().unwrap_or_default().to_string())
+            .collect();
+        let scores: Vec<f32> = gguf
+            .get("tokenizer.ggml.scores")
+            .and_then(GgufValue::as_array)
+            .map(|s| s.iter().map(|v| v.as_f32().unwrap_or(0.0)).collect())
+            .unwrap_or_else(|| vec![0.0; tokens.len()]);
+        let token_types: Vec<i64> = gguf
+            .get("tokenizer.ggml.token_type")
+            .and_then(GgufValue::as_array)
+            .map(|t| t.iter().map(|v| v.as_i64().unwrap_or(TOKEN_TYPE_NORMAL)).collect())
+            .unwrap_or_else(|| vec![TOKEN_TYPE_NORMAL; tokens.len()]);
+
+        let mut merge_ranks = HashMap::new();
+        if let Some(merges) = gguf.get("tokenizer.ggml.merges").and_then(GgufValue::as_array) {
+            for (rank, merge) in merges.iter().enumerate() {
+                if let Some((a, b)) = merge.as_str().and_then(|m| m.split_once(' ')) {
+                    merge_ranks.insert((a.to_string(), b.to_string()), rank);
+                }
+            }
+        }
+        if kind == TokenizerKind::Bpe && merge_ranks.is_empty() {
+            return Err(anyhow!("BPE tokenizer has no tokenizer.ggml.merges"));
+        }
+
+        let id = |key: &str| gguf.get_u64(key).map(|v| v as u32).filter(|&id| (id as usize) < tokens.len());
+        let flag = |key: &str| gguf.get(key).and_then(GgufValue::as_bool);
+        let special = SpecialTokens {
+            bos: id("tokenizer.ggml.bos_token_id"),
+            eos: id("tokenizer.ggml.eos_token_id"),
+            unk: id("tokenizer.ggml.unknown_token_id"),
+            pad: id("tokenizer.ggml.padding_token_id"),
+        };
+
+        let mut tokenizer = Self {
+            kind,
+            scores,
+            token_types,
+            ids: tokens.iter().enumerate().map(|(i, t)| (t.clone(), i as u32)).collect(),
+            merge_ranks,
+            byte_tokens: [None; 256],
+            special,
+            special_pieces: Vec::new(),
+            add_bos: flag("tokenizer.ggml.add_bos_token").unwrap_or(kind == TokenizerKind::SentencePiece),
+            add_eos: flag("tokenizer.ggml.add_eos_token").unwrap_or(false),
+            add_space_prefix: flag("tokenizer.ggml.add_space_prefix").unwrap_or(kind == TokenizerKind::SentencePiece),
+            pre_tokenizer: match kind {
+                TokenizerKind::Bpe => Some(Regex::new(GPT2_PATTERN)?),
+                TokenizerKind::SentencePiece => None,
+            },
+            byte_decoder: HashMap::new(),
+            byte_encoder: ['\0'; 256],
+            tokens,
+        };
+        tokenizer.index_tokens();
+        Ok(tokenizer)
+    }
+
+    fn index_tokens(&mut self) {
+        for (i, token) in self.tokens.iter().enumerate() {
+            let token_type = self.token_types.get(i).copied().unwrap_or(TOKEN_TYPE_NORMAL);
+            if self.kind == TokenizerKind::SentencePiece {
+                if let Some(byte) = parse_byte_token(token) {
+                    self.byte_tokens[byte as usize] = Some(i as u32);
+                }
+            }
+            if (token_type == TOKEN_TYPE_CONTROL || token_type == TOKEN_TYPE_USER_DEFINED) && !token.is_empty() {
+                self.special_pieces.push((token.clone(), i as u32));
+            }
+        }
+        self.special_pieces.sort_by_key(|(piece, _)| std::cmp::Reverse(piece.len()));
+
+        let (encoder, decoder) = bytes_to_unicode();
+        self.byte_encoder = encoder;
+        self.byte_decoder = decoder;
+    }
+
+    pub fn kind(&self) -> TokenizerKind {
+        self.kind
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn special_tokens(&self) -> &SpecialTokens {
+        &self.special
+    }
+
+    pub fn bos_id(&self) -> Option<u32> {
+        self.special.bos
+    }
+
+    pub fn eos_id(&self) -> Option<u32> {
+        self.special.eos
+    }
+
+    pub fn token_id(&self, piece: &str) -> Option<u32> {
+        self.ids.get(piece).copied()
+    }
+
+    // Control tokens (BOS/EOS, chat markers) are not rendered in decoded text
+    pub fn is_control(&self, id: u32) -> bool {
+        self.token_types.get(id as usize) == Some(&TOKEN_TYPE_CONTROL)
+            || Some(id) == self.special.bos
+            || Some(id) == self.special.eos
+    }
+
+    // Encode text, recognising special token strings such as `<|im_start|>`.
+    // `add_special` adds BOS/EOS as configured by the model.
+    pub fn encode(&self, text: &str, add_special: bool) -> Vec<u32> {
+        let mut out = Vec::new();
+        if add_special && self.add_bos {
+            out.extend(self.special.bos);
+        }
+
+        let mut rest = text;
+        let mut at_start = true;
+        while !rest.is_empty() {
+            let next_special = self
+                .special_pieces
+                .iter()
+                .filter_map(|(piece, id)| rest.find(piece.as_str()).map(|pos| (pos, piece.len(), *id)))
+                .min_by_key(|(pos, _, _)| *pos);
+
+            let (segment, special) = match next_special {
+                Some((pos, len, id)) => {
+                    let segment = &rest[..pos];
+                    rest = &rest[pos + len..];
+                    (segment, Some(id))
+                }
+                None => (std::mem::take(&mut rest), None),
+            };
+
+            if !segment.is_empty() {
+                match self.kind {
+                    TokenizerKind::SentencePiece => self.encode_spm(segment, at_start, &mut out),
+                    TokenizerKind::Bpe => self.encode_bpe(segment, &mut out),
+                }
+            }
+            out.extend(special);
+            at_start = false;
+        }
+
+        if add_special && self.add_eos {
+            out.extend(self.special.eos);
+        }
+        out
+    }
+
+    pub fn count(&self, text: &str, add_special: bool) -> usize {
+        self.encode(text, add_special).len()
+    }
+
+    // Raw bytes of one token, e.g. for streaming output piece by piece
+    pub fn token_bytes(&self, id: u32) -> Vec<u8> {
+        let Some(token) = self.tokens.get(id as usize) else {
+            return Vec::new();
+        };
+        if self.is_control(id) {
+            return Vec::new();
+        }
+        match self.kind {
+            TokenizerKind::SentencePiece => match parse_byte_token(token) {
+                Some(byte) if self.byte_tokens[byte as usize] == Some(id) => vec![byte],
+                _ => token.replace(SPM_SPACE, " ").into_bytes(),
+            },
+            TokenizerKind::Bpe => {
+                if self.token_types.get(id as usize) == Some(&TOKEN_TYPE_USER_DEFINED) {
+                    return token.as_bytes().to_vec();
+                }
+                token
+                    .chars()
+                    .map(|c| self.byte_decoder.get(&c).copied().unwrap_or(b'?'))
+                    .collect()
+            }
+        }
+    }
+
+    pub fn decode(&self, ids: &[u32]) -> String {
+        let bytes: Vec<u8> = ids.iter().flat_map(|&id| self.token_bytes(id)).collect();
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        // SentencePiece marks word starts with a space; the first one is an artefact
+        if self.kind == TokenizerKind::SentencePiece && self.add_space_prefix {
+            if let Some(stripped) = text.strip_prefix(' ') {
+                return stripped.to_string();
+            }
+        }
+        text
+    }
+
+    fn encode_spm(&self, text: &str, at_start: bool, out: &mut Vec<u32>) {
+        let prefix = if at_start && self.add_space_prefix { " " } else { "" };
+        let normalized: String = format!("{}{}", prefix, text).replace(' ', &SPM_SPACE.to_string());
+
+        // Symbols are byte ranges into `normalized`, linked so merges are O(1)
+        let starts: Vec<usize> = normalized.char_indices().map(|(i, _)| i).collect();
+        let mut lens: Vec<usize> = starts
+            .iter()
+            .zip(starts.iter().skip(1).chain(std::iter::once(&normalized.len())))
+            .map(|(a, b)| b - a)
+            .collect();
+        let n = starts.len();
+        let mut prev: Vec<Option<usize>> = (0..n).map(|i| i.checked_sub(1)).collect();
+        let mut next: Vec<Option<usize>> = (0..n).map(|i| Some(i + 1).filter(|&j| j < n)).collect();
+
+        let mut queue = BinaryHeap::new();
+        let try_push = |queue: &mut BinaryHeap<Bigram>, lens: &[usize], left: usize, right: usize| {
+            let piece = &normalized[starts[left]..starts[right] + lens[right]];
+            if let Some(&id) = self.ids.get(piece) {
+                queue.push(Bigram {
+                    score: self.scores.get(id as usize).copied().unwrap_or(0.0),
+                    left,
+                    right,
+                    len: piece.len(),
+                });
+            }
+        };
+        for i in 1..n {
+            try_push(&mut queue, &lens, i - 1, i);
+        }
+
+        while let Some(bigram) = queue.pop() {
+            let (left, right) = (bigram.left, bigram.right);
+            // Skip stale entries whose symbols were merged since they were queued
+            if lens[left] == 0 || lens[right] == 0 || lens[left] + lens[right] != bigram.len || next[left] != Some(right) {
+                continue;
+            }
+            lens[left] += lens[right];
+            lens[right] = 0;
+            next[left] = next[right];
+            if let Some(after) = next[right] {
+                prev[after] = Some(left);
+            }
+            if let Some(before) = prev[left] {
+                try_push(&mut queue, &lens, before, left);
+            }
+            if let Some(after) = next[left] {
+                try_push(&mut queue, &lens, left, after);
+            }
+        }
+
+        let mut cursor = Some(0).filter(|_| n > 0);
+        while let Some(i) = cursor {
+            let piece = &normalized[starts[i]..starts[i] + lens[i]];
+            match self.ids.get(piece) {
+                Some(&id) => out.push(id),
+                None => {
+                    for byte in piece.bytes() {
+                        out.extend(self.byte_tokens[byte as usize].or(self.special.unk));
+                    }
+                }
+            }
+            cursor = next[i];
+        }
+    }
+
+    fn encode_bpe(&self, text: &str, out: &mut Vec<u32>) {
+        let Some(pattern) = &self.pre_tokenizer else { return };
+
+        let mut pos = 0;
+        while let Some(m) = pattern.find_at(text, pos) {
+            let mut end = m.end();
+            // `\s+(?!\S)`: a whitespace run leaves its last char to the following word
+            if m.as_str().chars().all(char::is_whitespace) && end < text.len() {
+                let last = m.as_str().chars().next_back().map(char::len_utf8).unwrap_or(0);
+                if m.as_str().len() > last {
+                    end -= last;
+                }
+            }
+            self.bpe_word(&text[m.start()..end], out);
+            pos = end;
+        }
+    }
+
+    fn bpe_word(&self, word: &str, out: &mut Vec<u32>) {
+        let mut parts: Vec<String> = word.bytes().map(|b| self.byte_encoder[b as usize].to_string()).collect();
+
+        loop {
+            let best = parts
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    self.merge_ranks
+                        .get(&(pair[0].clone(), pair[1].clone()))
+                        .map(|rank| (*rank, i))
+                })
+                .min();
+            let Some((_, i)) = best else { break };
+            let merged = format!("{}{}", parts[i], parts[i + 1]);
+            parts.splice(i..i + 2, std::iter::once(merged));
+        }
+
+        for part in parts {
+            match self.ids.get(&part) {
+                Some(&id) => out.push(id),
+                None => {
+                    // Unmerged pieces should not exist in a consistent vocab; emit per char
+                    for c in part.chars() {
+                        out.extend(self.ids.get(&c.to_string()).copied().or(self.special.unk));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Rough token count for text when no model vocabulary is available
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+fn parse_byte_token(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+// GPT-2's reversible byte <-> printable unicode mapping
+fn bytes_to_unicode() -> ([char; 256], HashMap<char, u8>) {
+    let mut encoder = ['\0'; 256];
+    let mut n = 0u32;
+    for b in 0..=255u8 {
+        let printable = matches!(b, b'!'..=b'~' | 0xa1..=0xac | 0xae..=0xff);
+        encoder[b as usize] = if printable {
+            b as char
+        } else {
+            n += 1;
+            char::from_u32(255 + n).unwrap_or('?')
+        };
+    }
+    let decoder = encoder.iter().enumerate().map(|(b, c)| (*c, b as u8)).collect();
+    (encoder, decoder)
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use crate::gguf::testing::{strings, GgufBuilder};
+    use crate::gguf::GgufValue;
+
+    pub const SPM_VOCAB: &[&str] = &[
+        "<unk>", "<s>", "</s>", "\u{2581}", "a", "b", "c", "\u{2581}a", "\u{2581}b", "ab", "\u{2581}ab", "<0x21>",
+    ];
+
+    // SentencePiece vocabulary metadata; scores increase with the token id
+    pub fn spm_metadata(builder: GgufBuilder) -> GgufBuilder {
+        builder
+            .kv("tokenizer.ggml.model", GgufValue::String("llama".to_string()))
+            .kv("tokenizer.ggml.tokens", strings(SPM_VOCAB))
+            .kv(
+                "tokenizer.ggml.scores",
+                GgufValue::Array((0..SPM_VOCAB.len()).map(|i| GgufValue::F32(i as f32)).collect()),
+            )
+            .kv(
+                "tokenizer.ggml.token_type",
+                GgufValue::Array(
+                    (0..SPM_VOCAB.len())
+                        .map(|i| GgufValue::I32(match i {
+                            0 => 2,
+                            1 | 2 => 3,
+                            11 => 6,
+                            _ => 1,
+                        }))
+                        .collect(),
+                ),
+            )
+            .kv("tokenizer.ggml.bos_token_id", GgufValue::U32(1))
+            .kv("tokenizer.ggml.eos_token_id", GgufValue::U32(2))
+            .kv("tokenizer.ggml.unknown_token_id", GgufValue::U32(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+    use crate::gguf::testing::{strings, GgufBuilder};
+
+    fn spm() -> Tokenizer {
+        let bytes = spm_metadata(GgufBuilder::new()).build();
+        Tokenizer::from_gguf(&GgufFile::parse(&bytes).unwrap()).unwrap()
+    }
+
+    fn bpe() -> Tokenizer {
+        // "Ġ" is the byte-level stand-in for a space
+        let bytes = GgufBuilder::new()
+            .kv("tokenizer.ggml.model", GgufValue::String("gpt2".to_string()))
+            .kv(
+                "tokenizer.ggml.tokens",
+                strings(&["h", "i", "Ġ", "!", "hi", "Ġhi", "<|endoftext|>", "ĠĠ"]),
+            )
+            .kv(
+                "tokenizer.ggml.token_type",
+                GgufValue::Array([1, 1, 1, 1, 1, 1, 3, 1].iter().map(|t| GgufValue::I32(*t)).collect()),
+            )
+            .kv("tokenizer.ggml.merges", strings(&["h i", "Ġ hi", "Ġ Ġ"]))
+            .kv("tokenizer.ggml.eos_token_id", GgufValue::U32(6))
+            .build();
+        Tokenizer::from_gguf(&GgufFile::parse(&bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_spm_merges_by_score() {
+        let tok = spm();
+
+        // "▁ab" has the highest score, so " ab" collapses to one token after BOS
+        assert_eq!(tok.encode("ab", true), vec![1, 10]);
+        assert_eq!(tok.encode("ab b", false), vec![10, 8]);
+        // Unknown characters fall back to byte tokens or <unk>
+        assert_eq!(tok.encode("!", false), vec![3, 11]);
+        assert_eq!(tok.encode("z", false), vec![3, 0]);
+        assert_eq!(tok.decode(&[1, 10, 8, 11, 2]), "ab b!");
+    }
+
+    #[test]
+    fn test_spm_special_tokens_in_text() {
+        let tok = spm();
+        assert_eq!(tok.encode("ab</s>b", false), vec![10, 2, 5]);
+        assert!(tok.is_control(2));
+        assert!(tok.token_bytes(1).is_empty());
+        assert_eq!(tok.token_bytes(11), vec![b'!']);
+    }
+
+    #[test]
+    fn test_bpe_encode_decode() {
+        let tok = bpe();
+        assert_eq!(tok.kind(), TokenizerKind::Bpe);
+
+        assert_eq!(tok.encode("hi hi!", true), vec![4, 5, 3]);
+        // Whitespace runs give their last space to the following word
+        assert_eq!(tok.encode("hi   hi", false), vec![4, 7, 5]);
+        assert_eq!(tok.encode("hi<|endoftext|>", false), vec![4, 6]);
+        assert_eq!(tok.decode(&[4, 7, 5, 3, 6]), "hi   hi!");
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+}